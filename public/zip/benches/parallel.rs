@@ -0,0 +1,86 @@
+//! Compares sequential and parallel compression of many entries.
+//!
+//! Run with `cargo bench --features parallel`. Set `RAYON_NUM_THREADS` to change the
+//! size of the pool.
+
+use std::time::Instant;
+
+use compressor::parallel::{stage_files, FileInput, Staged};
+use compressor::write::{ArchiveWriter, EntryOptions, Method};
+
+const FILES: usize = 64;
+const FILE_SIZE: usize = 1 << 20;
+
+/// Text-like data that deflate has to work for.
+fn inputs() -> Vec<FileInput> {
+    let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+    let words = ["alpha ", "beta ", "gamma ", "delta ", "epsilon ", "zeta\n"];
+    (0..FILES)
+        .map(|idx| {
+            let mut data = Vec::with_capacity(FILE_SIZE);
+            while data.len() < FILE_SIZE {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                data.extend_from_slice(words[(seed % words.len() as u64) as usize].as_bytes());
+            }
+            FileInput {
+                name: format!("file{idx}.txt"),
+                data: Some(data),
+                options: EntryOptions {
+                    method: Method::Auto,
+                    ..EntryOptions::default()
+                },
+            }
+        })
+        .collect()
+}
+
+fn sequential(inputs: &[FileInput]) -> Vec<u8> {
+    let mut writer = ArchiveWriter::new();
+    for input in inputs {
+        let data = input.data.as_deref().unwrap_or_default();
+        writer.add_file(&input.name, data, &input.options).unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+fn parallel(inputs: &[FileInput]) -> Vec<u8> {
+    let mut writer = ArchiveWriter::new();
+    for staged in stage_files(inputs, &[]) {
+        match staged.unwrap() {
+            Staged::File(file) => writer.add_staged(file).unwrap(),
+            Staged::Directory => unreachable!("the benchmark has no directories"),
+        }
+    }
+    writer.finish().unwrap()
+}
+
+fn time(label: &str, run: impl Fn() -> Vec<u8>) -> f64 {
+    let mut best = f64::MAX;
+    let mut size = 0;
+    for _ in 0..3 {
+        let started = Instant::now();
+        size = run().len();
+        best = best.min(started.elapsed().as_secs_f64());
+    }
+    let megabytes = (FILES * FILE_SIZE) as f64 / (1 << 20) as f64;
+    println!(
+        "{label:>10}: {:7.1} ms  {:7.1} MiB/s  {size} bytes",
+        best * 1e3,
+        megabytes / best
+    );
+    best
+}
+
+fn main() {
+    let inputs = inputs();
+    println!(
+        "{FILES} files of {} KiB, {} threads",
+        FILE_SIZE >> 10,
+        rayon::current_num_threads()
+    );
+    let sequential = time("sequential", || sequential(&inputs));
+    let parallel = time("parallel", || parallel(&inputs));
+    println!("{:>10}: {:.2}x", "speedup", sequential / parallel);
+}
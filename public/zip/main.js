@@ -1,4 +1,4 @@
-import init, { zip_files } from "../pkg/compressor.js";
+import init, { ZipBuilder } from "../pkg/compressor.js";
 
 const folderInput = document.getElementById("folderInput");
 const fileInput = document.getElementById("fileInput");
@@ -13,6 +13,11 @@ let deferredPrompt = null;
 
 let wasmReadyPromise = null;
 
+// Files larger than this are streamed into the archive in slices.
+const CHUNK_SIZE = 8 * 1024 * 1024;
+
+const ENTRY_OPTIONS = { method: "auto" };
+
 const sanitizePath = (value) => {
   const trimmed = value.replace(/^[/\\]+/, "");
   if (!trimmed) return "file";
@@ -26,6 +31,14 @@ const prettySize = (bytes) => {
   return `${(kb / 1024).toFixed(2)} MB`;
 };
 
+const escapeHtml = (value) => value.replace(/[&<>"']/g, (ch) => `&#${ch.charCodeAt(0)};`);
+
+const formatEta = (seconds) => {
+  if (seconds < 60) return `${Math.ceil(seconds)}s`;
+  const minutes = Math.floor(seconds / 60);
+  return `${minutes}m ${Math.ceil(seconds % 60)}s`;
+};
+
 const setStatus = (message) => {
   statusEl.innerHTML = message ? `<strong>Status:</strong> ${message}` : "";
 };
@@ -85,6 +98,18 @@ const readFileAsUint8 = (file) => new Promise((resolve, reject) => {
   reader.readAsArrayBuffer(file);
 });
 
+const addFileToBuilder = async (builder, file, name) => {
+  const options = { ...ENTRY_OPTIONS, last_modified: file.lastModified };
+  if (file.size <= CHUNK_SIZE) {
+    builder.add_file(name, await readFileAsUint8(file), options);
+    return;
+  }
+  builder.start_file(name, { ...options, size_hint: file.size });
+  for (let offset = 0; offset < file.size; offset += CHUNK_SIZE) {
+    builder.write_chunk(await readFileAsUint8(file.slice(offset, offset + CHUNK_SIZE)));
+  }
+};
+
 const ensureWasm = () => {
   if (!wasmReadyPromise) {
     wasmReadyPromise = init();
@@ -120,21 +145,30 @@ const handleCompress = async () => {
   try {
     await ensureWasm();
 
-    const buffers = [];
-    for (let i = 0; i < files.length; i += 1) {
-      const file = files[i];
-      setStatus(`Reading files (${i + 1}/${files.length})`);
-      setProgress(Math.round(((i + 0.5) / files.length) * 60), `Reading ${i + 1}/${files.length}`);
-      buffers.push(await readFileAsUint8(file));
+    // Folder uploads drag along .DS_Store, Thumbs.db and friends; leave them out.
+    const builder = new ZipBuilder({ skip_junk: true });
+    const totalSize = files.reduce((sum, f) => sum + f.size, 0);
+    const startedAt = performance.now();
+    builder.on_progress(({ bytes_processed, total_bytes, entry_index, entry_name }) => {
+      const fraction = total_bytes ? bytes_processed / total_bytes : 1;
+      const elapsed = (performance.now() - startedAt) / 1000;
+      // Wait a moment before estimating so the first slow chunk does not skew the ETA.
+      const eta = fraction > 0 && elapsed > 1 ? ` • ${formatEta((elapsed * (1 - fraction)) / fraction)} left` : "";
+      setStatus(`Compressing ${entry_index + 1}/${files.length}: ${escapeHtml(entry_name)}${eta}`);
+      setProgress(Math.round(fraction * 95), `${prettySize(bytes_processed)} / ${prettySize(total_bytes)}`);
+    }, totalSize);
+    for (const file of files) {
+      await addFileToBuilder(builder, file, sanitizePath(file.webkitRelativePath || file.name));
     }
-    const names = files.map((f) => sanitizePath(f.webkitRelativePath || f.name));
 
-    setStatus("Compressing with WebAssembly...");
-    setProgress(80, "Compressing...");
-    const zipped = zip_files(names, buffers);
+    setStatus("Finishing archive...");
+    setProgress(95, "Finishing...");
+    const skipped = builder.skipped().length;
+    const zipped = builder.finish();
 
     const filename = downloadZip(zipped);
-    setStatus(`Done. Downloaded ${filename}.`);
+    const note = skipped ? ` Skipped ${skipped} junk file${skipped === 1 ? "" : "s"}.` : "";
+    setStatus(`Done. Downloaded ${filename}.${note}`);
     setProgress(100, "Done");
   } catch (err) {
     console.error(err);
@@ -0,0 +1,140 @@
+//! Format-independent reading of ZIP, 7z and RAR archives.
+//!
+//! The format is sniffed from the first bytes and every format lists into the same
+//! [`EntryInfo`] and extracts into the same [`ExtractedEntry`] as the ZIP functions.
+
+use crate::error::{Error, Result};
+use crate::limits::Limits;
+use crate::read::{self, EntryInfo, ExtractedEntry};
+use crate::{rar, sevenz};
+
+/// An archive format that can be listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    SevenZip,
+    Rar,
+}
+
+impl ArchiveFormat {
+    /// Identify an archive from its first bytes. Self-extracting archives, which start
+    /// with an executable, are not detected.
+    pub fn detect(data: &[u8]) -> Option<ArchiveFormat> {
+        if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if data.starts_with(sevenz::SIGNATURE) {
+            Some(ArchiveFormat::SevenZip)
+        } else if data.starts_with(rar::RAR5_SIGNATURE) || data.starts_with(rar::RAR4_SIGNATURE) {
+            Some(ArchiveFormat::Rar)
+        } else {
+            None
+        }
+    }
+
+    /// The lowercase name reported to JS, e.g. `"7z"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::SevenZip => "7z",
+            ArchiveFormat::Rar => "rar",
+        }
+    }
+}
+
+fn detect(bytes: &[u8]) -> Result<ArchiveFormat> {
+    ArchiveFormat::detect(bytes).ok_or(Error::InvalidArchive("not a ZIP, 7z or RAR archive"))
+}
+
+/// List the entries of an archive of any supported format.
+pub fn list_entries(bytes: &[u8]) -> Result<(ArchiveFormat, Vec<EntryInfo>)> {
+    let format = detect(bytes)?;
+    let entries = match format {
+        ArchiveFormat::Zip => read::list_entries(bytes)?,
+        ArchiveFormat::SevenZip => sevenz::list_entries(bytes)?,
+        ArchiveFormat::Rar => rar::list_entries(bytes)?,
+    };
+    Ok((format, entries))
+}
+
+/// Read a single entry by name. `password` only applies to ZIP archives.
+pub fn extract_entry(
+    bytes: &[u8],
+    name: &str,
+    password: Option<&str>,
+    limits: &Limits,
+) -> Result<Vec<u8>> {
+    match detect(bytes)? {
+        ArchiveFormat::Zip => read::extract_entry(bytes, name, password, limits),
+        ArchiveFormat::SevenZip => sevenz::extract_entry(bytes, name, limits),
+        ArchiveFormat::Rar => rar::extract_entry(bytes, name, limits),
+    }
+}
+
+/// Read every entry in archive order. `password` only applies to ZIP archives.
+pub fn extract_all(
+    bytes: &[u8],
+    password: Option<&str>,
+    limits: &Limits,
+) -> Result<Vec<ExtractedEntry>> {
+    match detect(bytes)? {
+        ArchiveFormat::Zip => read::extract_all(bytes, password, limits),
+        ArchiveFormat::SevenZip => sevenz::extract_all(bytes, limits),
+        ArchiveFormat::Rar => rar::extract_all(bytes, limits),
+    }
+}
+
+/// Hand every entry to `each` in archive order. ZIP entries are decompressed one at a
+/// time; 7z and RAR entries are all read before the first is handed over.
+pub fn for_each_entry(
+    bytes: &[u8],
+    password: Option<&str>,
+    limits: &Limits,
+    each: impl FnMut(ExtractedEntry) -> Result<()>,
+) -> Result<()> {
+    match detect(bytes)? {
+        ArchiveFormat::Zip => read::for_each_entry(bytes, password, limits, each),
+        ArchiveFormat::SevenZip => sevenz::extract_all(bytes, limits)?
+            .into_iter()
+            .try_for_each(each),
+        ArchiveFormat::Rar => rar::extract_all(bytes, limits)?
+            .into_iter()
+            .try_for_each(each),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write::{ArchiveWriter, EntryOptions};
+
+    #[test]
+    fn detects_and_dispatches_by_format() {
+        let mut writer = ArchiveWriter::new();
+        writer
+            .add_file("a.txt", b"zip data", &EntryOptions::default())
+            .unwrap();
+        let zip = writer.finish().unwrap();
+        assert_eq!(ArchiveFormat::detect(&zip), Some(ArchiveFormat::Zip));
+        assert_eq!(
+            ArchiveFormat::detect(b"7z\xBC\xAF\x27\x1C\x00\x04"),
+            Some(ArchiveFormat::SevenZip)
+        );
+        assert_eq!(
+            ArchiveFormat::detect(b"Rar!\x1A\x07\x01\x00"),
+            Some(ArchiveFormat::Rar)
+        );
+        assert_eq!(ArchiveFormat::detect(b"Rar!\x1A\x07\x02"), None);
+
+        let (format, entries) = list_entries(&zip).unwrap();
+        assert_eq!(format.name(), "zip");
+        assert_eq!(entries[0].name, "a.txt");
+        assert_eq!(
+            extract_entry(&zip, "a.txt", None, &Limits::default()).unwrap(),
+            b"zip data"
+        );
+        assert!(matches!(
+            extract_all(b"plain text", None, &Limits::default()),
+            Err(Error::InvalidArchive(_))
+        ));
+    }
+}
@@ -0,0 +1,414 @@
+//! Command-line front end to the archive code behind the wasm build.
+//!
+//! Options are the JSON objects the JS functions take, so
+//! `--archive-options '{"deterministic": true}'` writes the same bytes as
+//! `zip_files(names, contents, undefined, { deterministic: true })` in the browser.
+//! Build with `cargo build --release --features cli`.
+
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, UNIX_EPOCH};
+
+use clap::{Parser, Subcommand};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use compressor::archive;
+use compressor::limits::Limits;
+use compressor::manifest::{self, VerifyManifestOptions};
+use compressor::path::sanitize_name;
+use compressor::read::ExtractedEntry;
+use compressor::verify;
+use compressor::write::{ArchiveOptions, ArchiveWriter, EntryOptions};
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+#[derive(Parser)]
+#[command(version, about = "Create, list, extract and check archives")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a ZIP archive, as `zip_files` does.
+    Create {
+        /// Archive to write, or `-` for standard output.
+        archive: PathBuf,
+        /// Files and directories to add. A directory is added with everything under
+        /// it, named after the directory; `.` adds the contents of the current one.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Entry options for every entry, e.g. '{"method": "zstd", "level": 19}'.
+        /// Unset `last_modified` and `unix_permissions` come from the file system.
+        #[arg(long, value_name = "JSON")]
+        options: Option<String>,
+        /// Archive options, e.g. '{"deterministic": true}'.
+        #[arg(long, value_name = "JSON")]
+        archive_options: Option<String>,
+    },
+    /// Print the entries of a ZIP, 7z or RAR archive, as `list_archive` returns them.
+    List {
+        /// Archive to read, or `-` for standard input.
+        archive: PathBuf,
+    },
+    /// Extract a ZIP, 7z or RAR archive, as `extract_archive` does.
+    Extract {
+        /// Archive to read, or `-` for standard input.
+        archive: PathBuf,
+        /// Entries to extract. All of them when none are given.
+        names: Vec<String>,
+        /// Directory to extract into.
+        #[arg(short = 'C', long, default_value = ".")]
+        directory: PathBuf,
+        /// Password for encrypted ZIP entries.
+        #[arg(long)]
+        password: Option<String>,
+        /// Extraction limits, e.g. '{"max_total_size": null}'.
+        #[arg(long, value_name = "JSON")]
+        limits: Option<String>,
+        /// Replace files that already exist instead of failing.
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Check a ZIP archive against its checksum manifest, as `verify_manifest` does.
+    /// Exits with status 1 when anything does not match.
+    Verify {
+        /// Archive to read, or `-` for standard input.
+        archive: PathBuf,
+        /// Verify options, e.g. '{"name": "SHA256SUMS"}'.
+        #[arg(long, value_name = "JSON")]
+        options: Option<String>,
+    },
+    /// Check the headers and CRC-32 of every ZIP entry, as `verify_zip` does. Exits
+    /// with status 1 when the archive has problems.
+    Test {
+        /// Archive to read, or `-` for standard input.
+        archive: PathBuf,
+        /// Password for encrypted entries.
+        #[arg(long)]
+        password: Option<String>,
+    },
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse().command) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(err) => {
+            eprintln!("compressor: {err}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Run `command`, returning whether a check passed. Commands without a check pass.
+fn run(command: Command) -> CliResult<bool> {
+    match command {
+        Command::Create {
+            archive,
+            paths,
+            options,
+            archive_options,
+        } => {
+            let options: EntryOptions = parse_json(options.as_deref(), "options")?;
+            let archive_options: ArchiveOptions =
+                parse_json(archive_options.as_deref(), "archive options")?;
+            let bytes = create(&paths, &options, &archive_options)?;
+            write_output(&archive, &bytes)?;
+            Ok(true)
+        }
+        Command::List { archive } => {
+            let (format, entries) = archive::list_entries(&read_input(&archive)?)?;
+            #[derive(Serialize)]
+            struct Listing<T> {
+                format: &'static str,
+                entries: T,
+            }
+            print_json(&Listing {
+                format: format.name(),
+                entries,
+            })?;
+            Ok(true)
+        }
+        Command::Extract {
+            archive,
+            names,
+            directory,
+            password,
+            limits,
+            overwrite,
+        } => {
+            let limits: Limits = parse_json(limits.as_deref(), "limits")?;
+            let bytes = read_input(&archive)?;
+            let mut out = Extraction::new(directory, overwrite);
+            if names.is_empty() {
+                archive::for_each_entry(&bytes, password.as_deref(), &limits, |entry| {
+                    out.write(entry)
+                })?;
+            } else {
+                let (_, entries) = archive::list_entries(&bytes)?;
+                for name in names {
+                    let name = sanitize_name(&name)?;
+                    let info = entries
+                        .iter()
+                        .find(|info| info.name == name)
+                        .ok_or_else(|| compressor::error::Error::EntryNotFound(name.clone()))?;
+                    let data = archive::extract_entry(&bytes, &name, password.as_deref(), &limits)?;
+                    out.write(ExtractedEntry {
+                        name,
+                        is_dir: info.is_dir,
+                        last_modified: info.last_modified,
+                        unix_mode: info.unix_mode,
+                        data,
+                    })?;
+                }
+            }
+            out.finish()?;
+            Ok(true)
+        }
+        Command::Verify { archive, options } => {
+            let options: VerifyManifestOptions = parse_json(options.as_deref(), "options")?;
+            let report = manifest::verify_manifest(&read_input(&archive)?, &options)?;
+            print_json(&report)?;
+            Ok(report.ok)
+        }
+        Command::Test { archive, password } => {
+            let report = verify::verify_archive(&read_input(&archive)?, password.as_deref())?;
+            print_json(&report)?;
+            Ok(report.ok)
+        }
+    }
+}
+
+/// Parse a JSON options argument the way `parse_options` parses a JS object: a missing
+/// argument or `null` means the defaults.
+fn parse_json<T: DeserializeOwned + Default>(json: Option<&str>, what: &str) -> CliResult<T> {
+    let Some(json) = json else {
+        return Ok(T::default());
+    };
+    let value: Option<T> =
+        serde_json::from_str(json).map_err(|err| format!("invalid {what}: {err}"))?;
+    Ok(value.unwrap_or_default())
+}
+
+fn read_input(path: &Path) -> CliResult<Vec<u8>> {
+    if path == Path::new("-") {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes)?;
+        return Ok(bytes);
+    }
+    fs::read(path).map_err(|err| format!("cannot read {}: {err}", path.display()).into())
+}
+
+fn write_output(path: &Path, bytes: &[u8]) -> CliResult<()> {
+    if path == Path::new("-") {
+        io::stdout().write_all(bytes)?;
+        return Ok(());
+    }
+    fs::write(path, bytes).map_err(|err| format!("cannot write {}: {err}", path.display()).into())
+}
+
+fn print_json(value: &impl Serialize) -> CliResult<()> {
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, value)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// A file system entry to add, with the entry name it gets.
+struct Input {
+    name: String,
+    path: PathBuf,
+    metadata: fs::Metadata,
+}
+
+/// Every entry under `paths` in the order they are added: each path, then the sorted
+/// contents of directories below it.
+fn collect_inputs(paths: &[PathBuf]) -> CliResult<Vec<Input>> {
+    let mut inputs = Vec::new();
+    for path in paths {
+        let metadata = fs::symlink_metadata(path)
+            .map_err(|err| format!("cannot read {}: {err}", path.display()))?;
+        match path.file_name() {
+            Some(name) => walk(
+                name.to_string_lossy().into_owned(),
+                path,
+                metadata,
+                &mut inputs,
+            )?,
+            // `.` and `/` have no name of their own, so only their contents are added.
+            None if metadata.is_dir() => walk_children("", path, &mut inputs)?,
+            None => return Err(format!("cannot add {}", path.display()).into()),
+        }
+    }
+    Ok(inputs)
+}
+
+fn walk(
+    name: String,
+    path: &Path,
+    metadata: fs::Metadata,
+    inputs: &mut Vec<Input>,
+) -> CliResult<()> {
+    let is_dir = metadata.is_dir();
+    let prefix = format!("{name}/");
+    inputs.push(Input {
+        name: if is_dir { prefix.clone() } else { name },
+        path: path.to_path_buf(),
+        metadata,
+    });
+    if is_dir {
+        walk_children(&prefix, path, inputs)?;
+    }
+    Ok(())
+}
+
+fn walk_children(prefix: &str, dir: &Path, inputs: &mut Vec<Input>) -> CliResult<()> {
+    let mut children = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let name = format!("{prefix}{}", child.file_name().to_string_lossy());
+        walk(
+            name,
+            &child.path(),
+            fs::symlink_metadata(child.path())?,
+            inputs,
+        )?;
+    }
+    Ok(())
+}
+
+/// Build the archive with the same `ArchiveWriter` calls `zip_files` makes.
+fn create(
+    paths: &[PathBuf],
+    options: &EntryOptions,
+    archive_options: &ArchiveOptions,
+) -> CliResult<Vec<u8>> {
+    let mut writer = ArchiveWriter::with_options(archive_options)?;
+    for input in collect_inputs(paths)? {
+        let options = entry_options(options, &input.metadata);
+        let file_type = input.metadata.file_type();
+        let added = if file_type.is_dir() {
+            writer.add_directory(&input.name, &options)
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&input.path)?;
+            writer.add_symlink(&input.name, &target.to_string_lossy(), &options)
+        } else {
+            writer.add_file(&input.name, &fs::read(&input.path)?, &options)
+        };
+        added.map_err(|err| format!("unable to add {}: {err}", input.path.display()))?;
+    }
+    Ok(writer.finish()?)
+}
+
+/// `options` with the modification time and permissions of the file filled in, as the
+/// browser fills in `File.lastModified`.
+fn entry_options(options: &EntryOptions, metadata: &fs::Metadata) -> EntryOptions {
+    let mut options = options.clone();
+    if options.last_modified.is_none() {
+        options.last_modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_millis() as f64);
+    }
+    #[cfg(unix)]
+    if options.unix_permissions.is_none() {
+        use std::os::unix::fs::PermissionsExt;
+        options.unix_permissions = Some(metadata.permissions().mode() & 0o7777);
+    }
+    options
+}
+
+/// Writes extracted entries below a directory. Symbolic links and directory
+/// permissions are applied last, so no file is ever written through a link from the
+/// archive or into a directory it made read-only.
+struct Extraction {
+    root: PathBuf,
+    overwrite: bool,
+    links: Vec<(PathBuf, String)>,
+    directories: Vec<(PathBuf, u32)>,
+}
+
+impl Extraction {
+    fn new(root: PathBuf, overwrite: bool) -> Self {
+        Self {
+            root,
+            overwrite,
+            links: Vec::new(),
+            directories: Vec::new(),
+        }
+    }
+
+    /// Write `entry`, whose name `compressor` has already cleaned of `..` and absolute
+    /// paths.
+    fn write(&mut self, entry: ExtractedEntry) -> compressor::error::Result<()> {
+        let path = self.root.join(entry.name.trim_end_matches('/'));
+        let mode = entry.unix_mode.unwrap_or(0);
+        if entry.is_dir {
+            fs::create_dir_all(&path)?;
+            if entry.unix_mode.is_some() {
+                self.directories.push((path, mode & 0o7777));
+            }
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if mode & 0o170_000 == 0o120_000 {
+            let target = String::from_utf8_lossy(&entry.data).into_owned();
+            self.links.push((path, target));
+            return Ok(());
+        }
+
+        // Replace rather than truncate, so an existing symbolic link is not followed to
+        // a file outside the destination.
+        if self.overwrite {
+            match fs::symlink_metadata(&path) {
+                Ok(meta) if !meta.is_dir() => fs::remove_file(&path)?,
+                _ => {}
+            }
+        }
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+        file.write_all(&entry.data)?;
+        if entry.last_modified > 0.0 {
+            file.set_modified(UNIX_EPOCH + Duration::from_millis(entry.last_modified as u64))?;
+        }
+        #[cfg(unix)]
+        if entry.unix_mode.is_some() {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> CliResult<()> {
+        for (path, target) in self.links {
+            if self.overwrite && fs::symlink_metadata(&path).is_ok() {
+                fs::remove_file(&path)?;
+            }
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&target, &path)?;
+            #[cfg(not(unix))]
+            eprintln!(
+                "compressor: skipped symbolic link {} -> {target}",
+                path.display()
+            );
+        }
+        #[cfg(unix)]
+        for (path, mode) in self.directories.into_iter().rev() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+}
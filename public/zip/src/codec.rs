@@ -0,0 +1,688 @@
+//! Single-stream compression formats (gzip, zlib, raw deflate, brotli, zstd) for data
+//! that does not need an archive container.
+//!
+//! Both directions are push-based: callers feed chunks in and get back whatever output
+//! is ready, so large inputs never have to be held twice.
+
+use std::fmt;
+use std::io::{self, Write};
+
+use flate2::write::{DeflateEncoder, GzEncoder, MultiGzDecoder, ZlibEncoder};
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use serde::Deserialize;
+use zstd::stream::raw::{Decoder as ZstdRawDecoder, InBuffer, Operation, OutBuffer};
+
+use crate::error::{Error, Result};
+use crate::limits::{LimitExceeded, DEFAULT_MAX_TOTAL_SIZE};
+
+const BROTLI_BUFFER_SIZE: usize = 64 * 1024;
+/// Brotli window of 4 MiB (2^22), the default of the reference encoder.
+const BROTLI_WINDOW_BITS: u32 = 22;
+/// Output buffer growth per decoding step for zlib, deflate and zstd.
+const OUTPUT_CHUNK: usize = 64 * 1024;
+/// Bytes needed to tell the formats with a signature apart.
+const SNIFF_LEN: usize = 4;
+
+/// A single-stream compression format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Gzip,
+    Zlib,
+    /// Raw deflate (RFC 1951) without any header, as used inside ZIP entries.
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl Format {
+    /// Identify gzip, zlib and zstd streams from their first bytes. Brotli and raw
+    /// deflate have no signature and are never detected.
+    pub fn detect(data: &[u8]) -> Option<Format> {
+        match data {
+            [0x1F, 0x8B, ..] => Some(Format::Gzip),
+            [0x28, 0xB5, 0x2F, 0xFD, ..] => Some(Format::Zstd),
+            [cmf, flg, ..]
+                if cmf & 0x0F == 8
+                    && cmf >> 4 <= 7
+                    && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0 =>
+            {
+                Some(Format::Zlib)
+            }
+            _ => None,
+        }
+    }
+
+    /// The lowercase name used in options, e.g. `"gzip"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Zlib => "zlib",
+            Format::Deflate => "deflate",
+            Format::Brotli => "brotli",
+            Format::Zstd => "zstd",
+        }
+    }
+
+    /// `(maximum, default)` compression level.
+    fn levels(self) -> (u32, u32) {
+        match self {
+            Format::Gzip | Format::Zlib | Format::Deflate => (9, 6),
+            Format::Brotli => (11, 9),
+            Format::Zstd => (22, zstd::DEFAULT_COMPRESSION_LEVEL as u32),
+        }
+    }
+}
+
+/// Options for `compress` and `StreamCompressor`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CompressOptions {
+    pub format: Format,
+    /// 0-9 for gzip, zlib and deflate (default 6), 0-11 for brotli (default 9) and
+    /// 1-22 for zstd (default 3).
+    pub level: Option<u32>,
+}
+
+impl CompressOptions {
+    fn level(&self) -> Result<u32> {
+        let (max, default) = self.format.levels();
+        let min = u32::from(self.format == Format::Zstd);
+        match self.level {
+            None => Ok(default),
+            Some(level) if (min..=max).contains(&level) => Ok(level),
+            Some(_) => Err(Error::InvalidOption(match self.format {
+                Format::Gzip => "gzip level must be 0-9",
+                Format::Zlib => "zlib level must be 0-9",
+                Format::Deflate => "deflate level must be 0-9",
+                Format::Brotli => "brotli level must be 0-11",
+                Format::Zstd => "zstd level must be 1-22",
+            })),
+        }
+    }
+}
+
+/// Options for `decompress` and `StreamDecompressor`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DecompressOptions {
+    /// Detected from the data when missing. Anything that is not gzip, zlib or zstd
+    /// is then decoded as brotli, so raw deflate must always be named.
+    pub format: Option<Format>,
+    /// Decompressed bytes the whole stream may produce, 2 GiB by default, as for
+    /// archive extraction. `None` turns the limit off.
+    pub max_output_size: Option<u64>,
+}
+
+impl Default for DecompressOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            max_output_size: Some(DEFAULT_MAX_TOTAL_SIZE),
+        }
+    }
+}
+
+/// Push-based compressor. Also usable as an `io::Write` sink.
+pub struct StreamEncoder(Encoder);
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Zlib(ZlibEncoder<Vec<u8>>),
+    Deflate(DeflateEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::Encoder<'static, Vec<u8>>),
+}
+
+impl StreamEncoder {
+    pub fn new(options: &CompressOptions) -> Result<Self> {
+        let level = options.level()?;
+        Ok(StreamEncoder(match options.format {
+            Format::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::new(level))),
+            Format::Zlib => Encoder::Zlib(ZlibEncoder::new(Vec::new(), Compression::new(level))),
+            Format::Deflate => {
+                Encoder::Deflate(DeflateEncoder::new(Vec::new(), Compression::new(level)))
+            }
+            Format::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER_SIZE,
+                level,
+                BROTLI_WINDOW_BITS,
+            ))),
+            Format::Zstd => Encoder::Zstd(zstd::Encoder::new(Vec::new(), level as i32)?),
+        }))
+    }
+
+    /// Compress `chunk` and return the output produced so far. Encoders buffer
+    /// internally, so this is often empty.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        self.write_all(chunk)?;
+        Ok(std::mem::take(self.output()))
+    }
+
+    /// Flush the encoder and return the remaining output, including any trailer.
+    pub fn finish(self) -> Result<Vec<u8>> {
+        Ok(match self.0 {
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Zlib(encoder) => encoder.finish()?,
+            Encoder::Deflate(encoder) => encoder.finish()?,
+            Encoder::Brotli(encoder) => encoder.into_inner(),
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        })
+    }
+
+    fn output(&mut self) -> &mut Vec<u8> {
+        match &mut self.0 {
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            Encoder::Zlib(encoder) => encoder.get_mut(),
+            Encoder::Deflate(encoder) => encoder.get_mut(),
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+        }
+    }
+}
+
+impl Write for StreamEncoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zlib(encoder) => encoder.write(buf),
+            Encoder::Deflate(encoder) => encoder.write(buf),
+            Encoder::Brotli(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zlib(encoder) => encoder.flush(),
+            Encoder::Deflate(encoder) => encoder.flush(),
+            Encoder::Brotli(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Push-based decompressor.
+pub struct StreamDecoder {
+    decoder: Decoder,
+    max_output_size: Option<u64>,
+}
+
+enum Decoder {
+    /// Format detection is waiting for the first few bytes.
+    Sniffing(Vec<u8>),
+    Gzip(MultiGzDecoder<Output>),
+    Zlib(Inflater),
+    Deflate(Inflater),
+    Brotli(Box<brotli::DecompressorWriter<Output>>),
+    Zstd {
+        decoder: Box<ZstdRawDecoder<'static>>,
+        output: Output,
+        /// Whether the input so far ends exactly at the end of a frame.
+        frame_done: bool,
+    },
+}
+
+impl Decoder {
+    fn new(format: Format, max: Option<u64>) -> Result<Self> {
+        Ok(match format {
+            Format::Gzip => Decoder::Gzip(MultiGzDecoder::new(Output::new(max))),
+            Format::Zlib => Decoder::Zlib(Inflater::new(true, max)),
+            Format::Deflate => Decoder::Deflate(Inflater::new(false, max)),
+            Format::Brotli => Decoder::Brotli(Box::new(brotli::DecompressorWriter::new(
+                Output::new(max),
+                BROTLI_BUFFER_SIZE,
+            ))),
+            Format::Zstd => Decoder::Zstd {
+                decoder: Box::new(ZstdRawDecoder::new()?),
+                output: Output::new(max),
+                frame_done: false,
+            },
+        })
+    }
+}
+
+/// Raised through a decoder's `io::Write` sink when its output passes the limit.
+#[derive(Debug)]
+struct OutputLimit(u64);
+
+impl fmt::Display for OutputLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", LimitExceeded::TotalSize(self.0))
+    }
+}
+
+impl std::error::Error for OutputLimit {}
+
+/// Decoded bytes waiting to be returned, failing as soon as the stream as a whole
+/// produces more than `max`, so a small bomb cannot fill memory within one push.
+struct Output {
+    data: Vec<u8>,
+    /// Bytes produced since the stream began.
+    total: u64,
+    max: Option<u64>,
+}
+
+impl Output {
+    fn new(max: Option<u64>) -> Self {
+        Output {
+            data: Vec::new(),
+            total: 0,
+            max,
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.total += buf.len() as u64;
+        if let Some(max) = self.max.filter(|&max| self.total > max) {
+            return Err(io::Error::other(OutputLimit(max)));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Turn a decoder's I/O error into the limit error when the output limit caused it.
+fn decode_error(err: io::Error) -> Error {
+    match err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<OutputLimit>())
+    {
+        Some(OutputLimit(max)) => LimitExceeded::TotalSize(*max).into(),
+        None => err.into(),
+    }
+}
+
+impl StreamDecoder {
+    pub fn new(options: &DecompressOptions) -> Result<Self> {
+        let decoder = match options.format {
+            Some(format) => Decoder::new(format, options.max_output_size)?,
+            None => Decoder::Sniffing(Vec::new()),
+        };
+        Ok(StreamDecoder {
+            decoder,
+            max_output_size: options.max_output_size,
+        })
+    }
+
+    /// The stream's format, once known.
+    pub fn format(&self) -> Option<Format> {
+        match &self.decoder {
+            Decoder::Sniffing(_) => None,
+            Decoder::Gzip(_) => Some(Format::Gzip),
+            Decoder::Zlib(_) => Some(Format::Zlib),
+            Decoder::Deflate(_) => Some(Format::Deflate),
+            Decoder::Brotli(_) => Some(Format::Brotli),
+            Decoder::Zstd { .. } => Some(Format::Zstd),
+        }
+    }
+
+    /// Decompress `chunk` and return the output produced so far.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        if let Decoder::Sniffing(pending) = &mut self.decoder {
+            pending.extend_from_slice(chunk);
+            if pending.len() < SNIFF_LEN {
+                return Ok(Vec::new());
+            }
+            let pending = std::mem::take(pending);
+            self.resolve(&pending)?;
+            return self.push(&pending);
+        }
+
+        match &mut self.decoder {
+            Decoder::Sniffing(_) => unreachable!("format detection is resolved above"),
+            Decoder::Gzip(decoder) => {
+                decoder.write_all(chunk).map_err(decode_error)?;
+                Ok(std::mem::take(&mut decoder.get_mut().data))
+            }
+            Decoder::Zlib(inflater) | Decoder::Deflate(inflater) => inflater.push(chunk),
+            Decoder::Brotli(decoder) => {
+                decoder.write_all(chunk).map_err(decode_error)?;
+                Ok(std::mem::take(&mut decoder.get_mut().data))
+            }
+            Decoder::Zstd {
+                decoder,
+                output,
+                frame_done,
+            } => {
+                let mut input = InBuffer::around(chunk);
+                let mut buffer = vec![0u8; OUTPUT_CHUNK];
+                loop {
+                    let consumed_before = input.pos();
+                    let mut out = OutBuffer::around(buffer.as_mut_slice());
+                    let hint = decoder.run(&mut input, &mut out)?;
+                    let written = out.pos();
+                    output.write_all(&buffer[..written]).map_err(decode_error)?;
+                    // A hint of 0 marks the end of a frame. Steps that neither read
+                    // nor write say nothing about where the stream stands.
+                    if written > 0 || input.pos() > consumed_before {
+                        *frame_done = hint == 0;
+                    }
+                    if input.pos() == chunk.len() && written < buffer.len() {
+                        break;
+                    }
+                }
+                Ok(std::mem::take(&mut output.data))
+            }
+        }
+    }
+
+    /// Check that the stream is complete and return the remaining output.
+    pub fn finish(mut self) -> Result<Vec<u8>> {
+        let mut tail = Vec::new();
+        if let Decoder::Sniffing(pending) = &mut self.decoder {
+            let pending = std::mem::take(pending);
+            self.resolve(&pending)?;
+            tail = self.push(&pending)?;
+        }
+
+        match self.decoder {
+            Decoder::Sniffing(_) => unreachable!("format detection is resolved above"),
+            Decoder::Gzip(decoder) => tail.extend(decoder.finish().map_err(decode_error)?.data),
+            Decoder::Zlib(inflater) | Decoder::Deflate(inflater) => {
+                if !inflater.done {
+                    return Err(truncated());
+                }
+                tail.extend(inflater.output);
+            }
+            Decoder::Brotli(decoder) => match decoder.into_inner() {
+                Ok(output) => tail.extend(output.data),
+                Err(Output {
+                    total,
+                    max: Some(max),
+                    ..
+                }) if total > max => return Err(LimitExceeded::TotalSize(max).into()),
+                Err(_) => return Err(truncated()),
+            },
+            Decoder::Zstd {
+                output, frame_done, ..
+            } => {
+                if !frame_done {
+                    return Err(truncated());
+                }
+                tail.extend(output.data);
+            }
+        }
+        Ok(tail)
+    }
+
+    fn resolve(&mut self, head: &[u8]) -> Result<()> {
+        let format = Format::detect(head).unwrap_or(Format::Brotli);
+        self.decoder = Decoder::new(format, self.max_output_size)?;
+        Ok(())
+    }
+}
+
+/// Zlib or raw deflate decoder that knows whether the final block has been seen,
+/// which `flate2`'s writer-based decoders do not report.
+struct Inflater {
+    state: Decompress,
+    output: Vec<u8>,
+    done: bool,
+    max: Option<u64>,
+}
+
+impl Inflater {
+    fn new(zlib_header: bool, max: Option<u64>) -> Self {
+        Inflater {
+            state: Decompress::new(zlib_header),
+            output: Vec::new(),
+            done: false,
+            max,
+        }
+    }
+
+    fn push(&mut self, mut chunk: &[u8]) -> Result<Vec<u8>> {
+        // Keep going while input remains or the last step filled the buffer, which
+        // means more output may be pending. Bytes after the end of the stream are ignored.
+        let mut filled = false;
+        while (!chunk.is_empty() || filled) && !self.done {
+            self.output.reserve(OUTPUT_CHUNK);
+            let (before_in, before_out) = (self.state.total_in(), self.state.total_out());
+            let status = self
+                .state
+                .decompress_vec(chunk, &mut self.output, FlushDecompress::None)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            if let Some(max) = self.max.filter(|&max| self.state.total_out() > max) {
+                return Err(LimitExceeded::TotalSize(max).into());
+            }
+            let consumed = (self.state.total_in() - before_in) as usize;
+            chunk = &chunk[consumed..];
+            self.done = status == Status::StreamEnd;
+            filled = self.output.len() == self.output.capacity();
+            if consumed == 0 && self.state.total_out() == before_out {
+                break;
+            }
+        }
+        Ok(std::mem::take(&mut self.output))
+    }
+}
+
+fn truncated() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "compressed stream ends early",
+    ))
+}
+
+/// Compress `data` in one call.
+pub fn compress(data: &[u8], options: &CompressOptions) -> Result<Vec<u8>> {
+    let mut encoder = StreamEncoder::new(options)?;
+    let mut out = encoder.push(data)?;
+    out.extend(encoder.finish()?);
+    Ok(out)
+}
+
+/// Decompress `data` in one call.
+pub fn decompress(data: &[u8], options: &DecompressOptions) -> Result<Vec<u8>> {
+    let mut decoder = StreamDecoder::new(options)?;
+    let mut out = decoder.push(data)?;
+    out.extend(decoder.finish()?);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Format; 5] = [
+        Format::Gzip,
+        Format::Zlib,
+        Format::Deflate,
+        Format::Brotli,
+        Format::Zstd,
+    ];
+
+    fn sample() -> Vec<u8> {
+        (0..20_000u32)
+            .flat_map(|n| format!("line {n}: status=ok\n").into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_every_format_in_chunks() {
+        let data = sample();
+        for format in ALL {
+            let options = CompressOptions {
+                format,
+                level: None,
+            };
+            let mut encoder = StreamEncoder::new(&options).unwrap();
+            let mut packed = Vec::new();
+            for chunk in data.chunks(7_000) {
+                packed.extend(encoder.push(chunk).unwrap());
+            }
+            packed.extend(encoder.finish().unwrap());
+            assert!(packed.len() < data.len() / 4, "{format:?}");
+
+            let mut decoder = StreamDecoder::new(&DecompressOptions {
+                format: Some(format),
+                ..DecompressOptions::default()
+            })
+            .unwrap();
+            let mut unpacked = Vec::new();
+            for chunk in packed.chunks(1_000) {
+                unpacked.extend(decoder.push(chunk).unwrap());
+            }
+            unpacked.extend(decoder.finish().unwrap());
+            assert_eq!(unpacked, data, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn inflates_highly_compressible_input_in_one_push() {
+        let data = vec![0u8; 5 << 20];
+        for format in ALL {
+            let options = CompressOptions {
+                format,
+                level: None,
+            };
+            let packed = compress(&data, &options).unwrap();
+            let unpacked = decompress(
+                &packed,
+                &DecompressOptions {
+                    format: Some(format),
+                    ..DecompressOptions::default()
+                },
+            );
+            assert_eq!(unpacked.unwrap(), data, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn stops_output_at_the_limit() {
+        let data = vec![0u8; 5 << 20];
+        for format in ALL {
+            let packed = compress(
+                &data,
+                &CompressOptions {
+                    format,
+                    level: None,
+                },
+            )
+            .unwrap();
+            let capped = DecompressOptions {
+                format: Some(format),
+                max_output_size: Some(1 << 20),
+            };
+            assert!(
+                matches!(
+                    decompress(&packed, &capped),
+                    Err(Error::LimitExceeded(LimitExceeded::TotalSize(max))) if max == 1 << 20
+                ),
+                "{format:?}"
+            );
+
+            // The limit covers the whole stream, not each push.
+            let mut decoder = StreamDecoder::new(&capped).unwrap();
+            let result = packed
+                .chunks(1_000)
+                .try_for_each(|chunk| decoder.push(chunk).map(drop));
+            assert!(matches!(result, Err(Error::LimitExceeded(_))), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn detects_format_on_decompression() {
+        let data = sample();
+        for format in [Format::Gzip, Format::Zlib, Format::Zstd, Format::Brotli] {
+            let packed = compress(
+                &data,
+                &CompressOptions {
+                    format,
+                    level: Some(1),
+                },
+            )
+            .unwrap();
+            let expected = (format != Format::Brotli).then_some(format);
+            assert_eq!(Format::detect(&packed), expected);
+
+            // Feed the stream one byte at a time so detection has to wait for enough input.
+            let mut decoder = StreamDecoder::new(&DecompressOptions::default()).unwrap();
+            let mut unpacked = Vec::new();
+            for byte in &packed {
+                unpacked.extend(decoder.push(std::slice::from_ref(byte)).unwrap());
+            }
+            assert_eq!(decoder.format(), Some(format));
+            unpacked.extend(decoder.finish().unwrap());
+            assert_eq!(unpacked, data, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn honours_levels_and_rejects_out_of_range() {
+        let mut state = 0x2545_f491_u32;
+        let data: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                b"aaaabbbcde"[(state % 10) as usize]
+            })
+            .collect();
+        let fast = compress(
+            &data,
+            &CompressOptions {
+                format: Format::Zstd,
+                level: Some(1),
+            },
+        );
+        let small = compress(
+            &data,
+            &CompressOptions {
+                format: Format::Zstd,
+                level: Some(19),
+            },
+        );
+        assert!(small.unwrap().len() < fast.unwrap().len());
+
+        for (format, level) in [(Format::Gzip, 10), (Format::Brotli, 12), (Format::Zstd, 0)] {
+            assert!(matches!(
+                compress(
+                    &data,
+                    &CompressOptions {
+                        format,
+                        level: Some(level)
+                    }
+                ),
+                Err(Error::InvalidOption(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_streams() {
+        let data = sample();
+        for format in ALL {
+            let packed = compress(
+                &data,
+                &CompressOptions {
+                    format,
+                    level: None,
+                },
+            )
+            .unwrap();
+            let cut = &packed[..packed.len() / 2];
+            assert!(
+                decompress(
+                    cut,
+                    &DecompressOptions {
+                        format: Some(format),
+                        ..DecompressOptions::default()
+                    }
+                )
+                .is_err(),
+                "{format:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Detection of files with identical contents.
+//!
+//! Writers with `dedupe_content` set hash every whole file before writing it. A file
+//! whose contents match an earlier one is stored only once: ZIP archives leave the
+//! copy out and map its name to the stored file in `duplicates.json`, tar archives
+//! store it as a symbolic link to the first file.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+
+/// Entry name of the map ZIP archives get when duplicates are left out.
+pub const DUPLICATES_JSON: &str = "duplicates.json";
+
+/// Files that share one content. The first name is the copy that is stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DuplicateGroup {
+    pub size: u64,
+    /// BLAKE3 digest of the contents, as lowercase hex.
+    pub blake3: String,
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DuplicateReport {
+    /// Every content shared by two or more files, in the order of their first files.
+    pub groups: Vec<DuplicateGroup>,
+    /// Files after the first of each group.
+    pub duplicate_files: usize,
+    /// Uncompressed size of those files, which deduplication does not store.
+    pub bytes_saved: u64,
+}
+
+/// The contents seen so far, grouped by BLAKE3 digest.
+#[derive(Debug, Default)]
+pub struct Duplicates {
+    groups: Vec<DuplicateGroup>,
+    by_digest: HashMap<[u8; 32], usize>,
+    /// Every file name offered, to keep `duplicates.json` from replacing one.
+    names: HashSet<String>,
+}
+
+impl Duplicates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the file `name` and return the name of an earlier file with the same
+    /// contents. Empty files are never duplicates, as leaving them out saves nothing.
+    pub fn check(&mut self, name: &str, data: &[u8]) -> Option<&str> {
+        self.names.insert(name.to_string());
+        if data.is_empty() {
+            return None;
+        }
+        let digest = blake3::hash(data);
+        match self.by_digest.entry(*digest.as_bytes()) {
+            Entry::Occupied(entry) => {
+                let group = &mut self.groups[*entry.get()];
+                group.names.push(name.to_string());
+                Some(&group.names[0])
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(self.groups.len());
+                self.groups.push(DuplicateGroup {
+                    size: data.len() as u64,
+                    blake3: digest.to_hex().to_string(),
+                    names: vec![name.to_string()],
+                });
+                None
+            }
+        }
+    }
+
+    /// Record a file whose contents are not compared, such as a streamed one.
+    pub fn record(&mut self, name: &str) {
+        self.names.insert(name.to_string());
+    }
+
+    pub fn report(&self) -> DuplicateReport {
+        let groups: Vec<DuplicateGroup> = self
+            .groups
+            .iter()
+            .filter(|group| group.names.len() > 1)
+            .cloned()
+            .collect();
+        DuplicateReport {
+            duplicate_files: groups.iter().map(|group| group.names.len() - 1).sum(),
+            bytes_saved: groups
+                .iter()
+                .map(|group| group.size * (group.names.len() as u64 - 1))
+                .sum(),
+            groups,
+        }
+    }
+
+    /// `duplicates.json`, mapping each left-out file to the stored file with its
+    /// contents, or `None` when no file was left out.
+    pub fn render(&self) -> Result<Option<Vec<u8>>> {
+        let map: BTreeMap<&str, &str> = self
+            .groups
+            .iter()
+            .flat_map(|group| {
+                group.names[1..]
+                    .iter()
+                    .map(|name| (name.as_str(), group.names[0].as_str()))
+            })
+            .collect();
+        if map.is_empty() {
+            return Ok(None);
+        }
+        if self.names.contains(DUPLICATES_JSON) {
+            return Err(Error::NameCollision(DUPLICATES_JSON.to_string()));
+        }
+        let mut json = serde_json::to_vec_pretty(&map)
+            .map_err(|_| Error::Internal("duplicate map did not serialize"))?;
+        json.push(b'\n');
+        Ok(Some(json))
+    }
+}
+
+/// Group `files` by contents without writing anything. Names ending in `/` are
+/// directories and are skipped.
+pub fn find_duplicates<'a>(
+    files: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+) -> DuplicateReport {
+    let mut duplicates = Duplicates::new();
+    for (name, data) in files {
+        if !name.ends_with('/') {
+            duplicates.check(name, data);
+        }
+    }
+    duplicates.report()
+}
+
+/// Target for a symbolic link at `name` that points to `original`, relative to the
+/// link's directory so the archive can be extracted anywhere.
+pub fn relative_link(name: &str, original: &str) -> String {
+    let link_dir: Vec<&str> = name.split('/').collect();
+    let link_dir = &link_dir[..link_dir.len() - 1];
+    let target: Vec<&str> = original.split('/').collect();
+    let common = link_dir
+        .iter()
+        .zip(&target[..target.len() - 1])
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut parts = vec![".."; link_dir.len() - common];
+    parts.extend(&target[common..]);
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limits;
+    use crate::manifest::{verify_manifest, ManifestOptions, VerifyManifestOptions, SHA256SUMS};
+    use crate::read;
+    use crate::tarball::{self, TarEntryOptions, TarOptions, TarWriter};
+    use crate::write::{ArchiveOptions, ArchiveWriter, EntryOptions};
+
+    const PHOTO: &[u8] = b"\xFF\xD8\xFF\xE0 not really a jpeg";
+
+    #[test]
+    fn reports_groups_and_bytes_saved() {
+        let report = find_duplicates([
+            ("a.jpg", PHOTO),
+            ("empty-1", &b""[..]),
+            ("b.jpg", b"other"),
+            ("dir/", &b""[..]),
+            ("copy of a.jpg", PHOTO),
+            ("empty-2", &b""[..]),
+            ("a (2).jpg", PHOTO),
+        ]);
+        assert_eq!(report.groups.len(), 1);
+        assert_eq!(
+            report.groups[0].names,
+            ["a.jpg", "copy of a.jpg", "a (2).jpg"]
+        );
+        assert_eq!(
+            report.groups[0].blake3,
+            blake3::hash(PHOTO).to_hex().as_str()
+        );
+        assert_eq!(report.duplicate_files, 2);
+        assert_eq!(report.bytes_saved, 2 * PHOTO.len() as u64);
+
+        assert_eq!(relative_link("a/b/x.jpg", "a/c/y.jpg"), "../c/y.jpg");
+        assert_eq!(relative_link("a/x.jpg", "a/y.jpg"), "y.jpg");
+        assert_eq!(relative_link("x.jpg", "a/b/y.jpg"), "a/b/y.jpg");
+        assert_eq!(relative_link("a/b/x.jpg", "y.jpg"), "../../y.jpg");
+    }
+
+    #[test]
+    fn zip_stores_one_copy_and_a_duplicate_map() {
+        let options = EntryOptions::default();
+        let mut writer = ArchiveWriter::with_options(&ArchiveOptions {
+            dedupe_content: true,
+            manifest: Some(ManifestOptions::default()),
+            ..ArchiveOptions::default()
+        })
+        .unwrap();
+        writer.add_file("2024/a.jpg", PHOTO, &options).unwrap();
+        writer.add_file("2024/b.jpg", b"other", &options).unwrap();
+        writer.add_file("backup/a.jpg", PHOTO, &options).unwrap();
+        assert_eq!(writer.duplicates().bytes_saved, PHOTO.len() as u64);
+        let bytes = writer.finish().unwrap();
+
+        let entries = read::extract_all(&bytes, None, &Limits::default()).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["2024/a.jpg", "2024/b.jpg", DUPLICATES_JSON, SHA256SUMS]
+        );
+        assert_eq!(
+            String::from_utf8(entries[2].data.clone()).unwrap(),
+            "{\n  \"backup/a.jpg\": \"2024/a.jpg\"\n}\n"
+        );
+        // The map is checked by the manifest like the files it describes.
+        let report = verify_manifest(&bytes, &VerifyManifestOptions::default()).unwrap();
+        assert!(report.ok && report.unlisted.is_empty());
+        assert_eq!(report.entries.len(), 3);
+
+        let mut writer = ArchiveWriter::with_options(&ArchiveOptions {
+            dedupe_content: true,
+            ..ArchiveOptions::default()
+        })
+        .unwrap();
+        writer.add_file(DUPLICATES_JSON, b"{}", &options).unwrap();
+        writer.add_file("a.jpg", PHOTO, &options).unwrap();
+        writer.add_file("b.jpg", PHOTO, &options).unwrap();
+        assert!(matches!(writer.finish(), Err(Error::NameCollision(_))));
+
+        // The kept copy depends on the order files are added in.
+        for options in [
+            ArchiveOptions {
+                deterministic: true,
+                ..ArchiveOptions::default()
+            },
+            ArchiveOptions {
+                source_date_epoch: Some(0.0),
+                ..ArchiveOptions::default()
+            },
+        ] {
+            let options = ArchiveOptions {
+                dedupe_content: true,
+                ..options
+            };
+            assert!(matches!(
+                ArchiveWriter::with_options(&options),
+                Err(Error::InvalidOption(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn tar_links_duplicates_to_the_first_copy() {
+        let options = TarEntryOptions::default();
+        let mut writer = TarWriter::new(&TarOptions {
+            dedupe_content: true,
+            ..TarOptions::default()
+        })
+        .unwrap();
+        writer.add_file("2024/a.jpg", PHOTO, &options).unwrap();
+        writer
+            .add_file("backup/old/a.jpg", PHOTO, &options)
+            .unwrap();
+        assert_eq!(writer.duplicates().duplicate_files, 1);
+        let bytes = writer.finish().unwrap();
+
+        let mut entries = Vec::new();
+        tarball::for_each_entry(&bytes, &Limits::default(), |entry| {
+            entries.push(entry);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(entries[0].data, PHOTO);
+        assert_eq!(entries[1].name, "backup/old/a.jpg");
+        assert_eq!(entries[1].data, b"../../2024/a.jpg");
+        assert_eq!(
+            entries[1].unix_mode.map(|mode| mode & 0o170_000),
+            Some(0o120_000)
+        );
+    }
+}
@@ -0,0 +1,445 @@
+//! Editing existing archives.
+//!
+//! Entries that are kept are copied as raw compressed bytes and renamed entries only
+//! get new headers, so just the files that are added or replaced are compressed. The
+//! edited archive is assembled with a rewritten central directory.
+
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::ops::Range;
+
+use zip::ZipArchive;
+
+use crate::error::{Error, Result};
+use crate::path::{sanitize_name, NamePolicy};
+use crate::staged::{
+    entry_records, put_end_of_central_directory, read_u16, relocate_central_header, write_u16,
+    END_OF_CENTRAL_DIRECTORY_LEN, FLAG_UTF8, STAGING_LAYOUT,
+};
+use crate::write::{ArchiveOptions, ArchiveWriter, EntryOptions};
+
+const LOCAL_HEADER_LEN: usize = 30;
+
+/// One entry of the edited archive, stored as the raw records of the archive it came from.
+struct Entry {
+    name: String,
+    /// Whether `name` has to be written into the copied headers.
+    renamed: bool,
+    /// The one-entry archive an added file was compressed into, or `None` for entries
+    /// of the archive being edited.
+    staged: Option<Vec<u8>>,
+    /// Local header, data and any data descriptor.
+    local: Range<usize>,
+    /// Central directory record.
+    central: Range<usize>,
+}
+
+/// Reject the archive options other than `comment`, which only apply while an
+/// archive is written from scratch. Every field is named so a new option cannot be
+/// ignored silently.
+fn check_options(options: &ArchiveOptions) -> Result<()> {
+    let ArchiveOptions {
+        comment: _,
+        deterministic,
+        source_date_epoch,
+        include,
+        exclude,
+        skip_junk,
+        name_policy,
+        manifest,
+        dedupe_content,
+    } = options;
+    if *deterministic
+        || source_date_epoch.is_some()
+        || !include.is_empty()
+        || !exclude.is_empty()
+        || *skip_junk
+        || *name_policy != NamePolicy::Keep
+        || manifest.is_some()
+        || *dedupe_content
+    {
+        return Err(Error::InvalidOption(
+            "editing an archive only supports the comment option",
+        ));
+    }
+    Ok(())
+}
+
+/// An archive being edited. Changes are applied by [`ArchiveEditor::finish`].
+pub struct ArchiveEditor {
+    source: Vec<u8>,
+    entries: Vec<Entry>,
+    comment: Vec<u8>,
+}
+
+impl ArchiveEditor {
+    /// Open `bytes` for editing. The archive comment is kept unless `options` sets one.
+    /// Copied entries keep their bytes, so every other archive option is rejected.
+    pub fn open(bytes: Vec<u8>, options: &ArchiveOptions) -> Result<Self> {
+        options.validate()?;
+        check_options(options)?;
+        let (entries, comment) = {
+            let mut archive = ZipArchive::new(Cursor::new(bytes.as_slice()))?;
+            let comment = match &options.comment {
+                Some(comment) => comment.as_bytes().to_vec(),
+                None => archive.comment().to_vec(),
+            };
+            (entries(&mut archive, &bytes)?, comment)
+        };
+        Ok(Self {
+            source: bytes,
+            entries,
+            comment,
+        })
+    }
+
+    /// Names of the entries the edited archive will contain, in order.
+    pub fn names(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add a file, replacing any entry with the same name in place. New entries go at
+    /// the end.
+    pub fn add_file(&mut self, name: &str, data: &[u8], options: &EntryOptions) -> Result<()> {
+        let mut writer = ArchiveWriter::new();
+        writer.add_file(name, data, options)?;
+        self.put(writer.finish()?)
+    }
+
+    /// Add a directory entry, replacing any entry with the same name in place.
+    pub fn add_directory(&mut self, name: &str, options: &EntryOptions) -> Result<()> {
+        let mut writer = ArchiveWriter::new();
+        writer.add_directory(name, options)?;
+        self.put(writer.finish()?)
+    }
+
+    /// Remove an entry. Removing a directory such as `docs/` also removes everything
+    /// under it, whether or not the archive has an entry for the directory itself.
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        let name = sanitize_name(name)?;
+        let before = self.entries.len();
+        self.entries
+            .retain(|entry| !(entry.name == name || is_under(&entry.name, &name)));
+        if self.entries.len() == before {
+            return Err(Error::EntryNotFound(name));
+        }
+        Ok(())
+    }
+
+    /// Rename an entry without recompressing it. Renaming a directory such as `docs/`
+    /// moves everything under it.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let from = sanitize_name(from)?;
+        let mut to = sanitize_name(to)?;
+        if from.ends_with('/') && !to.ends_with('/') {
+            to.push('/');
+        }
+        let new_name = |name: &str| -> Option<String> {
+            if name == from {
+                Some(to.clone())
+            } else if is_under(name, &from) {
+                Some(format!("{to}{}", &name[from.len()..]))
+            } else {
+                None
+            }
+        };
+
+        let renames: Vec<Option<String>> = self
+            .entries
+            .iter()
+            .map(|entry| new_name(&entry.name))
+            .collect();
+        if renames.iter().all(Option::is_none) {
+            return Err(Error::EntryNotFound(from));
+        }
+        let targets: HashSet<&str> = renames.iter().flatten().map(String::as_str).collect();
+        let collides = self
+            .entries
+            .iter()
+            .zip(&renames)
+            .any(|(entry, rename)| rename.is_none() && targets.contains(entry.name.as_str()));
+        if collides {
+            return Err(Error::InvalidOption(
+                "an entry with the new name already exists",
+            ));
+        }
+        if targets
+            .iter()
+            .any(|name| name.len() > usize::from(u16::MAX))
+        {
+            return Err(Error::InvalidName(
+                "entry names are limited to 65,535 bytes",
+            ));
+        }
+
+        for (entry, rename) in self.entries.iter_mut().zip(renames) {
+            if let Some(name) = rename {
+                entry.name = name;
+                entry.renamed = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the edited archive.
+    pub fn finish(self) -> Result<Vec<u8>> {
+        let size = self
+            .entries
+            .iter()
+            .map(|entry| entry.local.len() + entry.central.len())
+            .sum::<usize>();
+        let mut out = Vec::with_capacity(size + self.comment.len() + END_OF_CENTRAL_DIRECTORY_LEN);
+        let mut centrals = Vec::with_capacity(self.entries.len());
+
+        for entry in &self.entries {
+            let bytes = entry.staged.as_deref().unwrap_or(&self.source);
+            let name = entry.renamed.then_some(entry.name.as_bytes());
+            let offset = out.len();
+            copy_local_header(&mut out, &bytes[entry.local.clone()], name)?;
+            centrals.push(relocate_central_header(
+                &bytes[entry.central.clone()],
+                name,
+                0,
+                offset as u64,
+            )?);
+        }
+
+        let central = out.len();
+        for record in centrals {
+            out.extend_from_slice(&record);
+        }
+        put_end_of_central_directory(&mut out, central, self.entries.len(), &self.comment);
+        Ok(out)
+    }
+
+    /// Insert the entry of a one-entry staging archive, replacing any entry with its name.
+    fn put(&mut self, staged: Vec<u8>) -> Result<()> {
+        let mut entry = {
+            let mut archive = ZipArchive::new(Cursor::new(staged.as_slice()))?;
+            entries(&mut archive, &staged)?
+                .pop()
+                .ok_or(STAGING_LAYOUT)?
+        };
+        entry.staged = Some(staged);
+        match self.entries.iter().position(|kept| kept.name == entry.name) {
+            Some(idx) => self.entries[idx] = entry,
+            None => self.entries.push(entry),
+        }
+        Ok(())
+    }
+}
+
+fn entries(archive: &mut ZipArchive<Cursor<&[u8]>>, bytes: &[u8]) -> Result<Vec<Entry>> {
+    entry_records(archive, bytes)?
+        .into_iter()
+        .map(|records| {
+            Ok(Entry {
+                name: sanitize_name(&records.name)?,
+                renamed: false,
+                staged: None,
+                local: records.local,
+                central: records.central,
+            })
+        })
+        .collect()
+}
+
+/// Whether `name` lies inside the directory `dir` (a name ending in `/`).
+fn is_under(name: &str, dir: &str) -> bool {
+    dir.ends_with('/') && name.len() > dir.len() && name.starts_with(dir)
+}
+
+/// Copy an entry's local header and data, writing `name` into the header when set.
+fn copy_local_header(out: &mut Vec<u8>, local: &[u8], name: Option<&[u8]>) -> Result<()> {
+    let Some(name) = name else {
+        out.extend_from_slice(local);
+        return Ok(());
+    };
+    let old_name_len = usize::from(read_u16(local, 26)?);
+    let rest = local
+        .get(LOCAL_HEADER_LEN + old_name_len..)
+        .ok_or(STAGING_LAYOUT)?;
+
+    let start = out.len();
+    out.extend_from_slice(&local[..LOCAL_HEADER_LEN]);
+    write_u16(out, start + 26, name.len() as u16);
+    if !name.is_ascii() {
+        let flags = read_u16(out, start + 6)?;
+        write_u16(out, start + 6, flags | FLAG_UTF8);
+    }
+    out.extend_from_slice(name);
+    out.extend_from_slice(rest);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limits;
+    use crate::read;
+    use crate::verify::verify_archive;
+    use crate::write::{Encryption, Method};
+
+    const MTIME: f64 = 1_700_000_000_000.0;
+
+    fn sample_archive() -> Vec<u8> {
+        let mut writer = ArchiveWriter::with_options(&ArchiveOptions {
+            comment: Some("release 1".into()),
+            ..ArchiveOptions::default()
+        })
+        .unwrap();
+        let options = EntryOptions {
+            last_modified: Some(MTIME),
+            unix_permissions: Some(0o755),
+            comment: Some("entry notes ✓".into()),
+            ..EntryOptions::default()
+        };
+        writer
+            .add_file("run.sh", &b"echo hi\n".repeat(40), &options)
+            .unwrap();
+        writer
+            .add_directory("docs/", &EntryOptions::default())
+            .unwrap();
+        let secret = EntryOptions {
+            password: Some("pw".into()),
+            encryption: Encryption::Aes256,
+            ..EntryOptions::default()
+        };
+        writer
+            .add_file("docs/secret.txt", b"classified", &secret)
+            .unwrap();
+        let zstd = EntryOptions {
+            method: Method::Zstd,
+            ..EntryOptions::default()
+        };
+        writer.start_file("docs/big.txt", &zstd).unwrap();
+        writer.write_chunk(&b"zstd text ".repeat(100)).unwrap();
+        writer.finish().unwrap()
+    }
+
+    /// The raw compressed bytes of `name`.
+    fn raw_data(bytes: &[u8], name: &str) -> Vec<u8> {
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let idx = archive.index_for_name(name).unwrap();
+        let file = archive.by_index_raw(idx).unwrap();
+        let start = file.data_start() as usize;
+        bytes[start..start + file.compressed_size() as usize].to_vec()
+    }
+
+    #[test]
+    fn edits_without_recompressing_kept_entries() {
+        let bytes = sample_archive();
+        let mut editor = ArchiveEditor::open(bytes.clone(), &ArchiveOptions::default()).unwrap();
+        editor.rename("docs/", "notes").unwrap();
+        editor.remove("notes/big.txt").unwrap();
+        editor
+            .add_file("run.sh", b"echo bye\n", &EntryOptions::default())
+            .unwrap();
+        editor
+            .add_file("readme.md", b"# hello", &EntryOptions::default())
+            .unwrap();
+        assert_eq!(
+            editor.names(),
+            ["run.sh", "notes/", "notes/secret.txt", "readme.md"]
+        );
+        let edited = editor.finish().unwrap();
+
+        assert!(verify_archive(&edited, Some("pw")).unwrap().ok);
+        assert_eq!(read::archive_comment(&edited).unwrap(), "release 1");
+        assert_eq!(
+            raw_data(&edited, "notes/secret.txt"),
+            raw_data(&bytes, "docs/secret.txt")
+        );
+        let extracted = read::extract_all(&edited, Some("pw"), &Limits::default()).unwrap();
+        let data: Vec<_> = extracted
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.data.as_slice()))
+            .collect();
+        assert_eq!(
+            data,
+            [
+                ("run.sh", &b"echo bye\n"[..]),
+                ("notes/", b""),
+                ("notes/secret.txt", b"classified"),
+                ("readme.md", b"# hello"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_metadata_of_copied_entries() {
+        let bytes = sample_archive();
+        let mut editor = ArchiveEditor::open(
+            bytes,
+            &ArchiveOptions {
+                comment: Some("release 2".into()),
+                ..ArchiveOptions::default()
+            },
+        )
+        .unwrap();
+        editor.rename("run.sh", "bin/run-é.sh").unwrap();
+        let edited = editor.finish().unwrap();
+
+        assert!(verify_archive(&edited, Some("pw")).unwrap().ok);
+        assert_eq!(read::archive_comment(&edited).unwrap(), "release 2");
+        let entry = &read::list_entries(&edited).unwrap()[0];
+        assert_eq!(entry.name, "bin/run-é.sh");
+        assert_eq!(entry.comment, "entry notes ✓");
+        assert_eq!(entry.last_modified, MTIME);
+        assert_eq!(entry.unix_mode.map(|mode| mode & 0o777), Some(0o755));
+    }
+
+    #[test]
+    fn rejects_options_that_only_apply_to_new_archives() {
+        for options in [
+            ArchiveOptions {
+                deterministic: true,
+                ..ArchiveOptions::default()
+            },
+            ArchiveOptions {
+                manifest: Some(Default::default()),
+                ..ArchiveOptions::default()
+            },
+            ArchiveOptions {
+                exclude: vec!["*.log".into()],
+                ..ArchiveOptions::default()
+            },
+        ] {
+            assert!(matches!(
+                ArchiveEditor::open(sample_archive(), &options),
+                Err(Error::InvalidOption(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_missing_and_colliding_names() {
+        let mut editor = ArchiveEditor::open(sample_archive(), &ArchiveOptions::default()).unwrap();
+        assert!(matches!(
+            editor.remove("missing.txt"),
+            Err(Error::EntryNotFound(_))
+        ));
+        assert!(matches!(
+            editor.rename("missing/", "other/"),
+            Err(Error::EntryNotFound(_))
+        ));
+        assert!(matches!(
+            editor.rename("run.sh", "docs/secret.txt"),
+            Err(Error::InvalidOption(_))
+        ));
+        // Swapping names inside one rename is fine; only untouched entries collide.
+        editor.rename("docs/", "docs/").unwrap();
+        assert_eq!(editor.len(), 4);
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+use std::io;
+
+use zip::result::ZipError;
+
+use crate::limits::LimitExceeded;
+
+/// Errors produced by the archive core before they are handed to JS as strings.
+#[derive(Debug)]
+pub enum Error {
+    /// An entry name was rejected by the path-safety rules.
+    InvalidName(&'static str),
+    /// An entry name matches an earlier one, ignoring case and Unicode normalization.
+    NameCollision(String),
+    /// No entry with the requested name exists in the archive.
+    EntryNotFound(String),
+    /// Data was written before any file entry was started.
+    NoOpenEntry,
+    /// An option value is outside its accepted range.
+    InvalidOption(&'static str),
+    /// The entry is encrypted and no password was supplied.
+    PasswordRequired(String),
+    /// The supplied password does not decrypt the entry.
+    InvalidPassword(String),
+    /// An invariant of the archive pipeline itself was violated.
+    Internal(&'static str),
+    /// Extraction stopped because the archive exceeds one of its limits.
+    LimitExceeded(LimitExceeded),
+    /// The archive is not one of the formats that can be read, or its structure is broken.
+    InvalidArchive(&'static str),
+    /// The archive uses a feature that can be listed but not read.
+    Unsupported(&'static str),
+    /// The entry's data is compressed or encrypted with a method that cannot be decoded.
+    UnsupportedMethod {
+        name: String,
+        method: String,
+    },
+    /// A JS callback threw, which stops the operation that called it.
+    Aborted(String),
+    Zip(ZipError),
+    SevenZip(sevenz_rust2::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidName(reason) => f.write_str(reason),
+            Error::NameCollision(name) => {
+                write!(f, "{name:?} collides with an earlier entry")
+            }
+            Error::EntryNotFound(name) => write!(f, "no entry named {name:?} in archive"),
+            Error::NoOpenEntry => f.write_str("no file entry has been started"),
+            Error::InvalidOption(reason) => f.write_str(reason),
+            Error::PasswordRequired(name) => {
+                write!(f, "{name:?} is encrypted; a password is required")
+            }
+            Error::InvalidPassword(name) => write!(f, "wrong password for {name:?}"),
+            Error::Internal(reason) => write!(f, "internal error: {reason}"),
+            Error::LimitExceeded(limit) => write!(f, "{limit}"),
+            Error::InvalidArchive(reason) => f.write_str(reason),
+            Error::Unsupported(reason) => f.write_str(reason),
+            Error::UnsupportedMethod { name, method } => {
+                write!(f, "{name:?} uses an unsupported method: {method}")
+            }
+            Error::Aborted(reason) => f.write_str(reason),
+            Error::Zip(err) => write!(f, "{err}"),
+            Error::SevenZip(err) => write!(f, "{err}"),
+            Error::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ZipError> for Error {
+    fn from(err: ZipError) -> Self {
+        match err {
+            ZipError::Io(err) => Error::Io(err),
+            err => Error::Zip(err),
+        }
+    }
+}
+
+impl From<sevenz_rust2::Error> for Error {
+    fn from(err: sevenz_rust2::Error) -> Self {
+        Error::SevenZip(err)
+    }
+}
+
+impl From<LimitExceeded> for Error {
+    fn from(limit: LimitExceeded) -> Self {
+        Error::LimitExceeded(limit)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
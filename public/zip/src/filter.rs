@@ -0,0 +1,331 @@
+//! Include/exclude rules for the entries of an archive.
+//!
+//! Rules follow `.gitignore` syntax: `*` and `?` stay within one path segment, `**`
+//! spans segments, a pattern without a `/` matches a name at any depth, a leading
+//! `/` anchors it to the archive root, a trailing `/` matches directories only and a
+//! leading `!` re-includes what an earlier rule excluded. As in git, an excluded
+//! directory excludes everything below it.
+
+use crate::error::{Error, Result};
+
+/// Operating-system clutter and dependency folders skipped by `skip_junk`.
+pub const JUNK_RULES: &[&str] = &[
+    ".DS_Store",
+    "._*",
+    "__MACOSX/",
+    ".Spotlight-V100/",
+    ".Trashes/",
+    ".fseventsd/",
+    "Thumbs.db",
+    "ehthumbs.db",
+    "desktop.ini",
+    "$RECYCLE.BIN/",
+    "node_modules/",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    /// `?`: one character other than `/`.
+    AnyChar,
+    /// `*`: any run of characters other than `/`.
+    AnySegment,
+    /// `**/`: nothing, or any run of whole directories.
+    AnyDirs,
+    /// `**` elsewhere: anything at all.
+    AnyPath,
+    /// `[...]`, with `!` or `^` after the bracket negating it.
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    tokens: Vec<Token>,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl Rule {
+    /// Parse one rule. Blank lines and `#` comments yield `None`.
+    fn parse(line: &str) -> Result<Option<Rule>> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return Err(Error::InvalidOption("filter rules need a pattern"));
+        }
+
+        let mut tokens = if anchored {
+            Vec::new()
+        } else {
+            vec![Token::AnyDirs]
+        };
+        let mut chars = line.chars().peekable();
+        while let Some(ch) = chars.next() {
+            tokens.push(match ch {
+                '\\' => Token::Literal(chars.next().unwrap_or('\\')),
+                '?' => Token::AnyChar,
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    while chars.peek() == Some(&'*') {
+                        chars.next();
+                    }
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        Token::AnyDirs
+                    } else {
+                        Token::AnyPath
+                    }
+                }
+                '*' => Token::AnySegment,
+                '[' => parse_class(&mut chars)?,
+                ch => Token::Literal(ch),
+            });
+        }
+        Ok(Some(Rule {
+            tokens,
+            negated,
+            dir_only,
+        }))
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        (is_dir || !self.dir_only) && matches(&self.tokens, &path.chars().collect::<Vec<_>>())
+    }
+}
+
+fn parse_class(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<Token> {
+    let negated = matches!(chars.peek(), Some('!' | '^'));
+    if negated {
+        chars.next();
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let ch = chars
+            .next()
+            .ok_or(Error::InvalidOption("unclosed [ in filter rule"))?;
+        if ch == ']' && !first {
+            break;
+        }
+        first = false;
+        let start = if ch == '\\' {
+            chars
+                .next()
+                .ok_or(Error::InvalidOption("unclosed [ in filter rule"))?
+        } else {
+            ch
+        };
+        let mut ahead = chars.clone();
+        let end = match (ahead.next(), ahead.next()) {
+            (Some('-'), Some(end)) if end != ']' => {
+                chars.next();
+                chars.next();
+                end
+            }
+            _ => start,
+        };
+        ranges.push((start, end));
+    }
+    Ok(Token::Class { negated, ranges })
+}
+
+fn matches(tokens: &[Token], path: &[char]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return path.is_empty();
+    };
+    match token {
+        Token::Literal(ch) => path.first() == Some(ch) && matches(rest, &path[1..]),
+        Token::AnyChar => path.first().is_some_and(|&ch| ch != '/') && matches(rest, &path[1..]),
+        Token::Class { negated, ranges } => {
+            path.first().is_some_and(|&ch| {
+                let inside = ranges
+                    .iter()
+                    .any(|&(start, end)| (start..=end).contains(&ch));
+                ch != '/' && inside != *negated
+            }) && matches(rest, &path[1..])
+        }
+        Token::AnySegment => {
+            let segment = path.iter().position(|&ch| ch == '/').unwrap_or(path.len());
+            (0..=segment).any(|skip| matches(rest, &path[skip..]))
+        }
+        Token::AnyDirs => {
+            matches(rest, path)
+                || path
+                    .iter()
+                    .enumerate()
+                    .any(|(at, &ch)| ch == '/' && matches(rest, &path[at + 1..]))
+        }
+        Token::AnyPath => (0..=path.len()).any(|skip| matches(rest, &path[skip..])),
+    }
+}
+
+/// Compiled include and exclude rules.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    include: Vec<Rule>,
+    exclude: Vec<Rule>,
+}
+
+impl Filter {
+    /// Compile the rules. The junk preset comes before `exclude`, so `!` rules there
+    /// can bring back anything it skips.
+    pub fn new(include: &[String], exclude: &[String], skip_junk: bool) -> Result<Self> {
+        let junk = JUNK_RULES.iter().copied().filter(|_| skip_junk);
+        let parse = |lines: &mut dyn Iterator<Item = &str>| -> Result<Vec<Rule>> {
+            lines
+                .filter_map(|line| Rule::parse(line).transpose())
+                .collect()
+        };
+        Ok(Self {
+            include: parse(&mut include.iter().map(String::as_str))?,
+            exclude: parse(&mut junk.chain(exclude.iter().map(String::as_str)))?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether the entry `name` stays out of the archive. Directory names may end in `/`.
+    pub fn skips(&self, name: &str, is_dir: bool) -> bool {
+        let path = name.trim_end_matches('/');
+        let is_dir = is_dir || name.ends_with('/');
+        let candidates = ancestors(path)
+            .map(|dir| (dir, true))
+            .chain(std::iter::once((path, is_dir)));
+
+        let mut included = self.include.is_empty();
+        for (candidate, candidate_is_dir) in candidates {
+            let excluded = self.exclude.iter().fold(false, |excluded, rule| {
+                if rule.matches(candidate, candidate_is_dir) {
+                    !rule.negated
+                } else {
+                    excluded
+                }
+            });
+            if excluded {
+                return true;
+            }
+            included |= self
+                .include
+                .iter()
+                .any(|rule| !rule.negated && rule.matches(candidate, candidate_is_dir));
+        }
+        !included
+    }
+}
+
+/// The parent directories of `path`, outermost first.
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(move |(at, _)| &path[..at])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str], skip_junk: bool) -> Filter {
+        let owned = |rules: &[&str]| {
+            rules
+                .iter()
+                .map(|rule| rule.to_string())
+                .collect::<Vec<_>>()
+        };
+        Filter::new(&owned(include), &owned(exclude), skip_junk).unwrap()
+    }
+
+    #[test]
+    fn skips_junk_files_and_folders() {
+        let junk = filter(&[], &[], true);
+        for name in [
+            ".DS_Store",
+            "photos/.DS_Store",
+            "photos/._IMG_0001.jpg",
+            "__MACOSX/photos/._a.jpg",
+            "Thumbs.db",
+            "app/node_modules/left-pad/index.js",
+            "app/node_modules/",
+        ] {
+            assert!(junk.skips(name, false), "{name}");
+        }
+        for name in ["photos/IMG_0001.jpg", "node_modules.txt", "app/src/"] {
+            assert!(!junk.skips(name, false), "{name}");
+        }
+        assert!(!filter(&[], &[], false).skips(".DS_Store", false));
+    }
+
+    #[test]
+    fn follows_gitignore_rules() {
+        let rules = filter(
+            &[],
+            &[
+                "# build output",
+                "*.log",
+                "!keep.log",
+                "/build/",
+                "docs/**/draft-*.md",
+                "cache/",
+                "[Tt]emp?",
+            ],
+            false,
+        );
+        let cases = [
+            ("debug.log", true),
+            ("logs/app.log", true),
+            ("keep.log", false),
+            ("logs/keep.log", false),
+            ("build/out.bin", true),
+            ("src/build/out.bin", false),
+            ("docs/draft-1.md", true),
+            ("docs/a/b/draft-2.md", true),
+            ("docs/final.md", false),
+            ("src/cache/x", true),
+            ("cache", false),
+            ("Temp1", true),
+            ("temp12", false),
+        ];
+        for (name, skipped) in cases {
+            assert_eq!(rules.skips(name, false), skipped, "{name}");
+        }
+        assert!(rules.skips("cache/", true));
+    }
+
+    #[test]
+    fn include_rules_keep_only_matches() {
+        let rules = filter(&["*.txt", "src/"], &["secret.txt"], false);
+        assert!(!rules.skips("a.txt", false));
+        assert!(!rules.skips("deep/b.txt", false));
+        assert!(!rules.skips("src/main.rs", false));
+        assert!(!rules.skips("src/", true));
+        assert!(rules.skips("image.png", false));
+        assert!(rules.skips("docs/", true));
+        assert!(rules.skips("secret.txt", false));
+    }
+
+    #[test]
+    fn rejects_bad_patterns() {
+        for rule in ["[abc", "/"] {
+            assert!(
+                Filter::new(&[], &[rule.to_string()], false).is_err(),
+                "{rule}"
+            );
+        }
+        let negated = filter(&[], &["node_modules/", "!node_modules/"], true);
+        assert!(!negated.skips("node_modules/pkg/index.js", false));
+    }
+}
@@ -1,9 +1,50 @@
-use std::io::{Cursor, Write};
+use js_sys::{Array, Function, Object, Reflect, Uint8Array};
+use std::io::{Seek, Write};
 
-use js_sys::{Array, Uint8Array};
+use serde::de::DeserializeOwned;
 use wasm_bindgen::prelude::*;
-use zip::write::FileOptions;
-use zip::{CompressionMethod, ZipWriter};
+use zip::write::StreamWriter;
+
+pub mod archive;
+pub mod codec;
+pub mod dedupe;
+pub mod edit;
+pub mod error;
+pub mod filter;
+pub mod limits;
+pub mod manifest;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+pub mod path;
+pub mod progress;
+pub mod rar;
+pub mod read;
+pub mod repack;
+pub mod sevenz;
+pub mod sink;
+pub mod sniff;
+pub mod split;
+pub mod staged;
+pub mod stats;
+pub mod tarball;
+pub mod time;
+pub mod verify;
+pub mod write;
+pub mod zipcrypto;
+
+use archive::ArchiveFormat;
+use codec::{CompressOptions, DecompressOptions, Format, StreamDecoder, StreamEncoder};
+use dedupe::Duplicates;
+use edit::ArchiveEditor;
+use limits::Limits;
+use manifest::VerifyManifestOptions;
+use progress::{Progress, ProgressEvent};
+use repack::{RepackFormat, RepackOptions};
+use sink::{ChunkSink, DEFAULT_CHUNK_SIZE};
+use split::SplitOptions;
+use stats::{StatsOptions, StatsReport};
+use tarball::{TarEntryOptions, TarOptions, TarWriter};
+use write::{ArchiveOptions, ArchiveWriter, EntryOptions};
 
 #[cfg(feature = "console_error_panic_hook")]
 #[wasm_bindgen(start)]
@@ -15,9 +56,72 @@ fn to_js_error(err: impl std::fmt::Display) -> JsValue {
     JsValue::from_str(&err.to_string())
 }
 
-/// Build a ZIP archive in-memory using the provided file names and byte buffers.
-#[wasm_bindgen]
-pub fn zip_files(names: Array, contents: Array) -> Result<Uint8Array, JsValue> {
+/// Deserialize an optional JS options object, treating `undefined` and `null` as defaults.
+fn parse_options<T: DeserializeOwned + Default>(options: JsValue) -> Result<T, JsValue> {
+    if options.is_undefined() || options.is_null() {
+        return Ok(T::default());
+    }
+    serde_wasm_bindgen::from_value(options).map_err(to_js_error)
+}
+
+/// Wrap a JS callback as a `Progress` receiving
+/// `{ bytes_processed, total_bytes, entry_index, entry_name }`. Throwing from the
+/// callback aborts the archive with the thrown message.
+fn js_progress(callback: Function, total_bytes: u64) -> Progress {
+    Progress::new(total_bytes, move |event: &ProgressEvent| {
+        let event = serde_wasm_bindgen::to_value(event)
+            .map_err(|err| error::Error::Aborted(err.to_string()))?;
+        callback
+            .call1(&JsValue::NULL, &event)
+            .map_err(|thrown| error::Error::Aborted(thrown_message(thrown)))?;
+        Ok(())
+    })
+}
+
+/// The message of a value thrown by a JS callback.
+fn thrown_message(thrown: JsValue) -> String {
+    match thrown.dyn_ref::<js_sys::Error>() {
+        Some(err) => String::from(err.message()),
+        None => thrown.as_string().unwrap_or_else(|| "aborted".into()),
+    }
+}
+
+type JsSink = ChunkSink<Box<dyn FnMut(&[u8]) -> error::Result<()>>>;
+
+/// A sink that passes each chunk of a streamed archive to `callback` as a new
+/// `Uint8Array`. Anything `callback` throws stops the archive.
+fn js_sink(callback: Function) -> JsSink {
+    ChunkSink::new(
+        DEFAULT_CHUNK_SIZE,
+        Box::new(move |chunk: &[u8]| {
+            callback
+                .call1(&JsValue::NULL, &Uint8Array::from(chunk))
+                .map_err(|thrown| error::Error::Aborted(thrown_message(thrown)))?;
+            Ok(())
+        }),
+    )
+}
+
+/// Walk the `names`, `contents` and `options` arguments of `zip_files`, handing each
+/// entry to `add` with its bytes, or `None` for a directory.
+fn for_each_input(
+    names: &Array,
+    contents: &Array,
+    options: JsValue,
+    mut add: impl FnMut(&str, Option<Vec<u8>>, &EntryOptions) -> error::Result<()>,
+) -> Result<(), JsValue> {
+    for_each_indexed_input(names, contents, options, |idx, name, data, options| {
+        add(name, data, options).map_err(|err| entry_error(idx, err))
+    })
+}
+
+/// [`for_each_input`] for callers that report their own errors, given each input's index.
+fn for_each_indexed_input(
+    names: &Array,
+    contents: &Array,
+    options: JsValue,
+    mut add: impl FnMut(u32, &str, Option<Vec<u8>>, &EntryOptions) -> Result<(), JsValue>,
+) -> Result<(), JsValue> {
     let name_count = names.length();
     if name_count == 0 {
         return Err(JsValue::from_str("no files provided"));
@@ -27,42 +131,825 @@ pub fn zip_files(names: Array, contents: Array) -> Result<Uint8Array, JsValue> {
         return Err(JsValue::from_str("names and contents length mismatch"));
     }
 
-    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
-    let options = FileOptions::default()
-        .compression_method(CompressionMethod::Deflated)
-        .unix_permissions(0o644);
+    let per_entry = options.dyn_ref::<Array>().cloned();
+    let shared: EntryOptions = if per_entry.is_some() {
+        EntryOptions::default()
+    } else {
+        parse_options(options)?
+    };
 
     for idx in 0..name_count {
-        let mut name = names
+        let name = names
             .get(idx)
             .as_string()
             .ok_or_else(|| JsValue::from_str("file names must be strings"))?;
 
-        if name.contains("..") {
-            return Err(JsValue::from_str("parent traversal is not allowed"));
+        let entry_options = match &per_entry {
+            Some(list) => parse_options(list.get(idx))?,
+            None => shared.clone(),
+        };
+
+        let data = (!name.ends_with('/')).then(|| Uint8Array::new(&contents.get(idx)).to_vec());
+        add(idx, &name, data, &entry_options)?;
+    }
+    Ok(())
+}
+
+fn entry_error(idx: u32, err: error::Error) -> JsValue {
+    JsValue::from_str(&format!("unable to add file #{idx}: {err}"))
+}
+
+/// Compress the inputs of `zip_files` on the thread pool and append them in order,
+/// one batch at a time.
+#[cfg(feature = "parallel")]
+fn add_inputs_parallel<W: Write + Seek>(
+    writer: &mut ArchiveWriter<W>,
+    archive_options: &ArchiveOptions,
+    names: &Array,
+    contents: &Array,
+    options: JsValue,
+) -> Result<(), JsValue> {
+    use parallel::Batch;
+
+    let flush = |batch: &mut Batch, writer: &mut ArchiveWriter<W>| {
+        batch
+            .flush(writer)
+            .map_err(|(idx, err)| entry_error(idx as u32, err))
+    };
+    let mut batch = Batch::default();
+    for_each_indexed_input(names, contents, options, |idx, name, data, options| {
+        let admitted = admit_input(writer, archive_options, name, data, options);
+        let full = admitted
+            .map_err(|err| entry_error(idx, err))?
+            .is_some_and(|input| batch.push(idx as usize, input));
+        if full {
+            flush(&mut batch, writer)?;
         }
+        Ok(())
+    })?;
+    flush(&mut batch, writer)
+}
+
+/// The input to stage for `name`, or `None` when the writer leaves it out.
+#[cfg(feature = "parallel")]
+fn admit_input<W: Write + Seek>(
+    writer: &mut ArchiveWriter<W>,
+    archive_options: &ArchiveOptions,
+    name: &str,
+    data: Option<Vec<u8>>,
+    options: &EntryOptions,
+) -> error::Result<Option<parallel::FileInput>> {
+    let admitted = match &data {
+        Some(data) => writer.admit_file(name, data)?,
+        None => writer.admit(name, true)?,
+    };
+    let Some(name) = admitted else {
+        return Ok(None);
+    };
+    let options = archive_options.entry_options(options, data.is_none())?;
+    Ok(Some(parallel::FileInput {
+        name,
+        data,
+        options: options.into_owned(),
+    }))
+}
 
-        while name.starts_with('/') || name.starts_with('\\') {
-            name.remove(0);
+/// Start `threads` Web Workers for parallel compression in the threaded build. Entries
+/// are compressed one after another until the returned promise resolves.
+#[cfg(all(target_arch = "wasm32", feature = "wasm-threads"))]
+#[wasm_bindgen]
+pub fn init_threads(threads: usize) -> js_sys::Promise {
+    parallel::init_threads(threads)
+}
+
+/// Build a ZIP archive in-memory using the provided file names and byte buffers.
+///
+/// `options` is optional: either one options object applied to every entry, or an
+/// array of per-entry options objects matching `names`. Names ending in `/` become
+/// directory entries and their contents are ignored. `archive_options` sets
+/// archive-wide fields such as the comment. `on_progress`, when given, is called at
+/// most every 100 ms while the archive is written.
+#[wasm_bindgen]
+pub fn zip_files(
+    names: Array,
+    contents: Array,
+    options: JsValue,
+    archive_options: JsValue,
+    on_progress: Option<Function>,
+) -> Result<Uint8Array, JsValue> {
+    let archive_options: ArchiveOptions = parse_options(archive_options)?;
+    let mut writer = ArchiveWriter::with_options(&archive_options).map_err(to_js_error)?;
+    add_inputs(
+        &mut writer,
+        &archive_options,
+        &names,
+        &contents,
+        options,
+        on_progress,
+    )?;
+    let bytes = writer.finish().map_err(to_js_error)?;
+
+    Ok(Uint8Array::from(bytes.as_slice()))
+}
+
+/// `zip_files` without holding the archive in memory: it is passed to `sink` as it is
+/// written, in `Uint8Array` chunks of 1 MiB and a shorter last one. Returns the
+/// archive's size. `sink` must consume or copy each chunk before returning, and
+/// anything it throws stops the archive.
+#[wasm_bindgen]
+pub fn zip_files_to_sink(
+    names: Array,
+    contents: Array,
+    options: JsValue,
+    archive_options: JsValue,
+    sink: Function,
+    on_progress: Option<Function>,
+) -> Result<f64, JsValue> {
+    let archive_options: ArchiveOptions = parse_options(archive_options)?;
+    let mut writer =
+        ArchiveWriter::streaming(js_sink(sink), &archive_options).map_err(to_js_error)?;
+    add_inputs(
+        &mut writer,
+        &archive_options,
+        &names,
+        &contents,
+        options,
+        on_progress,
+    )?;
+    let sink = writer.finish_stream().map_err(to_js_error)?;
+    Ok(sink.finish().map_err(to_js_error)? as f64)
+}
+
+/// Add the inputs of `zip_files` to `writer`, on the thread pool when there is one.
+fn add_inputs<W: Write + Seek>(
+    writer: &mut ArchiveWriter<W>,
+    archive_options: &ArchiveOptions,
+    names: &Array,
+    contents: &Array,
+    options: JsValue,
+    on_progress: Option<Function>,
+) -> Result<(), JsValue> {
+    if let Some(callback) = on_progress {
+        let total_bytes = (0..names.length())
+            .filter(|&idx| {
+                !names
+                    .get(idx)
+                    .as_string()
+                    .is_some_and(|name| name.ends_with('/'))
+            })
+            .map(|idx| u64::from(Uint8Array::new(&contents.get(idx)).length()))
+            .sum();
+        writer.set_progress(js_progress(callback, total_bytes));
+    }
+
+    #[cfg(feature = "parallel")]
+    if parallel::enabled() {
+        return add_inputs_parallel(writer, archive_options, names, contents, options);
+    }
+    #[cfg(not(feature = "parallel"))]
+    let _ = archive_options;
+
+    for_each_input(names, contents, options, |name, data, options| match data {
+        Some(data) => writer.add_file(name, &data, options),
+        None => writer.add_directory(name, options),
+    })
+}
+
+/// Dry run of `zip_files`: compress every entry with the same inputs and report
+/// `{ entries, original_size, compressed_size, ratio, elapsed_ms }` without keeping
+/// the archive. Each entry is `{ name, original_size, compressed_size, ratio, method,
+/// elapsed_ms, sampled }`. Entries `archive_options` would leave out are not reported.
+/// `stats_options.sample_size` limits how much of each file is compressed.
+#[wasm_bindgen]
+pub fn zip_stats(
+    names: Array,
+    contents: Array,
+    options: JsValue,
+    archive_options: JsValue,
+    stats_options: JsValue,
+) -> Result<JsValue, JsValue> {
+    let archive_options: ArchiveOptions = parse_options(archive_options)?;
+    let stats_options: StatsOptions = parse_options(stats_options)?;
+    let mut writer = ArchiveWriter::with_options(&archive_options).map_err(to_js_error)?;
+    let mut report = StatsReport::default();
+    for_each_input(&names, &contents, options, |name, data, options| {
+        let admitted = match &data {
+            Some(data) => writer.admit_file(name, data)?,
+            None => writer.admit(name, true)?,
+        };
+        let Some(name) = admitted else {
+            return Ok(());
+        };
+        let options = archive_options.entry_options(options, data.is_none())?;
+        report.push(stats::measure_entry(
+            &name,
+            data.as_deref(),
+            &options,
+            &stats_options,
+        )?);
+        Ok(())
+    })?;
+    serde_wasm_bindgen::to_value(&report).map_err(to_js_error)
+}
+
+/// The names among `names` that `zip_files` would leave out under the `include`,
+/// `exclude` and `skip_junk` fields of `archive_options`, so callers can show them
+/// before zipping. Names ending in `/` are treated as directories.
+#[wasm_bindgen]
+pub fn skipped_paths(names: Array, archive_options: JsValue) -> Result<Array, JsValue> {
+    let writer = admit_names(&names, archive_options, |_| ())?;
+    Ok(writer.skipped().iter().map(JsValue::from).collect())
+}
+
+/// The names `zip_files` would store for `names` under `archive_options`, without
+/// compressing anything: `{ names, changes }`. `names` matches the input, with `null`
+/// for entries left out by the filters or the name policy. `changes` lists every
+/// `{ original, name, reasons }` the name policy produced.
+#[wasm_bindgen]
+pub fn normalize_names(names: Array, archive_options: JsValue) -> Result<JsValue, JsValue> {
+    let stored = Array::new();
+    let writer = admit_names(&names, archive_options, |name| {
+        stored.push(&name.map_or(JsValue::NULL, JsValue::from));
+    })?;
+    let out = Object::new();
+    Reflect::set(&out, &"names".into(), &stored)?;
+    let changes = serde_wasm_bindgen::to_value(writer.name_changes()).map_err(to_js_error)?;
+    Reflect::set(&out, &"changes".into(), &changes)?;
+    Ok(out.into())
+}
+
+/// Group the files among the same inputs as `zip_files` by contents, without
+/// compressing anything: `{ groups, duplicate_files, bytes_saved }`. Each group is
+/// `{ size, blake3, names }`, its first name being the copy `dedupe_content` keeps.
+/// Empty files and directories are never duplicates.
+#[wasm_bindgen]
+pub fn find_duplicates(names: Array, contents: Array) -> Result<JsValue, JsValue> {
+    let mut duplicates = Duplicates::new();
+    for_each_input(&names, &contents, JsValue::UNDEFINED, |name, data, _| {
+        if let Some(data) = data {
+            duplicates.check(name, &data);
         }
+        Ok(())
+    })?;
+    serde_wasm_bindgen::to_value(&duplicates.report()).map_err(to_js_error)
+}
+
+/// Run `names` through a writer's filters and name policy, passing each result to
+/// `admitted`. Names ending in `/` are directories.
+fn admit_names(
+    names: &Array,
+    archive_options: JsValue,
+    mut admitted: impl FnMut(Option<String>),
+) -> Result<ArchiveWriter, JsValue> {
+    let options: ArchiveOptions = parse_options(archive_options)?;
+    let mut writer = ArchiveWriter::with_options(&options).map_err(to_js_error)?;
+    for (idx, name) in names.iter().enumerate() {
+        let name = name
+            .as_string()
+            .ok_or_else(|| JsValue::from_str("file names must be strings"))?;
+        let name = writer
+            .admit(&name, name.ends_with('/'))
+            .map_err(|err| entry_error(idx as u32, err))?;
+        admitted(name);
+    }
+    Ok(writer)
+}
+
+/// Build a tar archive in-memory from the same inputs as `zip_files`.
+///
+/// `options` is one `TarEntryOptions` object for every entry or an array matching
+/// `names`; names ending in `/` become directories. `tar_options` picks the header
+/// format and whether the output is gzip- or zstd-compressed.
+#[wasm_bindgen]
+pub fn tar_files(
+    names: Array,
+    contents: Array,
+    options: JsValue,
+    tar_options: JsValue,
+) -> Result<Uint8Array, JsValue> {
+    let name_count = names.length();
+    if name_count == 0 {
+        return Err(JsValue::from_str("no files provided"));
+    }
+
+    if name_count != contents.length() {
+        return Err(JsValue::from_str("names and contents length mismatch"));
+    }
+
+    let per_entry = options.dyn_ref::<Array>().cloned();
+    let shared: TarEntryOptions = if per_entry.is_some() {
+        TarEntryOptions::default()
+    } else {
+        parse_options(options)?
+    };
+
+    let tar_options: TarOptions = parse_options(tar_options)?;
+    let mut writer = TarWriter::new(&tar_options).map_err(to_js_error)?;
+
+    for idx in 0..name_count {
+        let name = names
+            .get(idx)
+            .as_string()
+            .ok_or_else(|| JsValue::from_str("file names must be strings"))?;
+
+        let entry_options = match &per_entry {
+            Some(list) => parse_options(list.get(idx))?,
+            None => shared.clone(),
+        };
 
-        if name.is_empty() {
-            return Err(JsValue::from_str("file name cannot be empty"));
+        let added = if name.ends_with('/') {
+            writer.add_directory(&name, &entry_options)
+        } else {
+            let data = Uint8Array::new(&contents.get(idx)).to_vec();
+            writer.add_file(&name, &data, &entry_options)
+        };
+        added.map_err(|err| JsValue::from_str(&format!("unable to add file #{idx}: {err}")))?;
+    }
+
+    let bytes = writer.finish().map_err(to_js_error)?;
+
+    Ok(Uint8Array::from(bytes.as_slice()))
+}
+
+/// The `#[wasm_bindgen]` methods `ZipBuilder` and `ZipStreamBuilder` share, which
+/// forward to their `ArchiveWriter`.
+macro_rules! archive_builder_methods {
+    ($builder:ident) => {
+        #[wasm_bindgen]
+        impl $builder {
+            /// Number of entries added so far.
+            #[wasm_bindgen(getter)]
+            pub fn length(&self) -> usize {
+                self.inner.len()
+            }
+
+            /// Names of the entries left out by the `include`, `exclude` and `skip_junk`
+            /// options so far.
+            pub fn skipped(&self) -> Array {
+                self.inner.skipped().iter().map(JsValue::from).collect()
+            }
+
+            /// `{ original, name, reasons }` for every entry name the name policy changed so far.
+            pub fn name_changes(&self) -> Result<JsValue, JsValue> {
+                serde_wasm_bindgen::to_value(self.inner.name_changes()).map_err(to_js_error)
+            }
+
+            /// `{ groups, duplicate_files, bytes_saved }` for the files the `dedupe_content`
+            /// option left out so far, as `find_duplicates` reports them.
+            pub fn duplicates(&self) -> Result<JsValue, JsValue> {
+                serde_wasm_bindgen::to_value(&self.inner.duplicates()).map_err(to_js_error)
+            }
+
+            /// Call `callback` with progress at most every 100 ms from now on. `total_bytes`
+            /// is the size of all files still to be added.
+            pub fn on_progress(&mut self, callback: Function, total_bytes: f64) {
+                self.inner
+                    .set_progress(js_progress(callback, total_bytes as u64));
+            }
+
+            /// Add a whole file. `options` may be omitted.
+            pub fn add_file(
+                &mut self,
+                name: String,
+                bytes: &[u8],
+                options: JsValue,
+            ) -> Result<(), JsValue> {
+                let options: EntryOptions = parse_options(options)?;
+                self.inner
+                    .add_file(&name, bytes, &options)
+                    .map_err(to_js_error)
+            }
+
+            /// Start a file whose contents will follow through `write_chunk`.
+            pub fn start_file(&mut self, name: String, options: JsValue) -> Result<(), JsValue> {
+                let options: EntryOptions = parse_options(options)?;
+                self.inner.start_file(&name, &options).map_err(to_js_error)
+            }
+
+            /// Append a chunk to the file most recently opened with `start_file`.
+            pub fn write_chunk(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+                self.inner.write_chunk(bytes).map_err(to_js_error)
+            }
+
+            /// Add an empty directory entry.
+            pub fn add_directory(&mut self, name: String, options: JsValue) -> Result<(), JsValue> {
+                let options: EntryOptions = parse_options(options)?;
+                self.inner
+                    .add_directory(&name, &options)
+                    .map_err(to_js_error)
+            }
         }
+    };
+}
+
+/// Incremental ZIP builder for JS callers that want to feed files one at a time.
+///
+/// Each `add_file` or `write_chunk` call copies its bytes straight into the archive,
+/// so JS can drop its buffer as soon as the call returns.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct ZipBuilder {
+    inner: ArchiveWriter,
+}
+
+#[wasm_bindgen]
+impl ZipBuilder {
+    /// Create a builder. `options` may be omitted or carry archive-wide fields such as `comment`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(options: JsValue) -> Result<ZipBuilder, JsValue> {
+        let options: ArchiveOptions = parse_options(options)?;
+        Ok(ZipBuilder {
+            inner: ArchiveWriter::with_options(&options).map_err(to_js_error)?,
+        })
+    }
+
+    /// Finish the archive and return its bytes. The builder cannot be used afterwards.
+    pub fn finish(self) -> Result<Uint8Array, JsValue> {
+        let bytes = self.inner.finish().map_err(to_js_error)?;
+        Ok(Uint8Array::from(bytes.as_slice()))
+    }
+
+    /// Finish the archive and split it into volumes, as `split_zip` does.
+    pub fn finish_split(self, options: JsValue) -> Result<Array, JsValue> {
+        let options: SplitOptions = parse_options(options)?;
+        let bytes = self.inner.finish().map_err(to_js_error)?;
+        volume_array(split::split_archive(&bytes, &options).map_err(to_js_error)?)
+    }
+}
 
-        let raw = contents.get(idx);
-        let data = Uint8Array::new(&raw).to_vec();
+archive_builder_methods!(ZipBuilder);
 
-        writer
-            .start_file(name, options)
-            .map_err(|err| JsValue::from_str(&format!("unable to add file #{idx}: {err}")))?;
-        writer
-            .write_all(&data)
-            .map_err(|err| JsValue::from_str(&format!("unable to write file #{idx}: {err}")))?;
+/// `ZipBuilder` that passes the archive to a JS sink as it is written instead of
+/// returning it from `finish`, for output piped to a file or a download stream.
+#[wasm_bindgen]
+pub struct ZipStreamBuilder {
+    inner: ArchiveWriter<StreamWriter<JsSink>>,
+}
+
+#[wasm_bindgen]
+impl ZipStreamBuilder {
+    /// Create a builder that calls `sink` with each `Uint8Array` chunk of the archive.
+    /// `options` takes the same archive-wide fields as `ZipBuilder`, except `deterministic`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(sink: Function, options: JsValue) -> Result<ZipStreamBuilder, JsValue> {
+        let options: ArchiveOptions = parse_options(options)?;
+        Ok(ZipStreamBuilder {
+            inner: ArchiveWriter::streaming(js_sink(sink), &options).map_err(to_js_error)?,
+        })
     }
 
-    let cursor = writer.finish().map_err(to_js_error)?;
-    let bytes = cursor.into_inner();
+    /// Write the central directory, pass the last chunk to the sink and return the
+    /// archive's size. The builder cannot be used afterwards.
+    pub fn finish(self) -> Result<f64, JsValue> {
+        let sink = self.inner.finish_stream().map_err(to_js_error)?;
+        Ok(sink.finish().map_err(to_js_error)? as f64)
+    }
+}
+
+archive_builder_methods!(ZipStreamBuilder);
 
+/// Edits an existing ZIP archive. Entries that are kept or renamed are copied without
+/// being decompressed; only files added through `add_file` are compressed.
+#[wasm_bindgen]
+pub struct ZipEditor {
+    inner: ArchiveEditor,
+}
+
+#[wasm_bindgen]
+impl ZipEditor {
+    /// Open an archive for editing. Its comment is kept unless `options.comment` is set.
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: Uint8Array, options: JsValue) -> Result<ZipEditor, JsValue> {
+        let options: ArchiveOptions = parse_options(options)?;
+        Ok(ZipEditor {
+            inner: ArchiveEditor::open(bytes.to_vec(), &options).map_err(to_js_error)?,
+        })
+    }
+
+    /// Number of entries the edited archive will contain.
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Names of the entries the edited archive will contain, in order.
+    pub fn names(&self) -> Array {
+        self.inner.names().into_iter().map(JsValue::from).collect()
+    }
+
+    /// Add a file, replacing an entry with the same name. `options` may be omitted.
+    pub fn add_file(
+        &mut self,
+        name: String,
+        bytes: &[u8],
+        options: JsValue,
+    ) -> Result<(), JsValue> {
+        let options: EntryOptions = parse_options(options)?;
+        self.inner
+            .add_file(&name, bytes, &options)
+            .map_err(to_js_error)
+    }
+
+    /// Add a directory entry, replacing an entry with the same name.
+    pub fn add_directory(&mut self, name: String, options: JsValue) -> Result<(), JsValue> {
+        let options: EntryOptions = parse_options(options)?;
+        self.inner
+            .add_directory(&name, &options)
+            .map_err(to_js_error)
+    }
+
+    /// Remove an entry, or a directory and everything under it.
+    pub fn remove(&mut self, name: String) -> Result<(), JsValue> {
+        self.inner.remove(&name).map_err(to_js_error)
+    }
+
+    /// Rename an entry, or a directory and everything under it.
+    pub fn rename(&mut self, from: String, to: String) -> Result<(), JsValue> {
+        self.inner.rename(&from, &to).map_err(to_js_error)
+    }
+
+    /// Write the edited archive and return its bytes. The editor cannot be used afterwards.
+    pub fn finish(self) -> Result<Uint8Array, JsValue> {
+        let bytes = self.inner.finish().map_err(to_js_error)?;
+        Ok(Uint8Array::from(bytes.as_slice()))
+    }
+}
+
+/// List the entries of a ZIP archive: name, sizes, method, CRC32, mtime, directory flag,
+/// Unix mode and comment.
+#[wasm_bindgen]
+pub fn list_zip(bytes: Uint8Array) -> Result<JsValue, JsValue> {
+    let entries = read::list_entries(&bytes.to_vec()).map_err(to_js_error)?;
+    serde_wasm_bindgen::to_value(&entries).map_err(to_js_error)
+}
+
+/// Decompress a single entry of a ZIP archive by name. `password` is needed for encrypted
+/// entries. `limits` overrides the default extraction limits.
+#[wasm_bindgen]
+pub fn extract_zip_entry(
+    bytes: Uint8Array,
+    name: String,
+    password: Option<String>,
+    limits: JsValue,
+) -> Result<Uint8Array, JsValue> {
+    let limits: Limits = parse_options(limits)?;
+    let data = read::extract_entry(&bytes.to_vec(), &name, password.as_deref(), &limits)
+        .map_err(to_js_error)?;
+    Ok(Uint8Array::from(data.as_slice()))
+}
+
+/// Read the archive-level comment of a ZIP archive. Returns an empty string when there is none.
+#[wasm_bindgen]
+pub fn zip_comment(bytes: Uint8Array) -> Result<String, JsValue> {
+    read::archive_comment(&bytes.to_vec()).map_err(to_js_error)
+}
+
+/// Check a ZIP archive without extracting it: local headers must agree with the central
+/// directory and every entry must decompress to its recorded CRC-32. Returns
+/// `{ ok, recovered, issues, entries }`, with a `status` per entry. When the central
+/// directory is missing, entries are recovered from their local headers.
+/// `password` is needed to check the data of encrypted entries.
+#[wasm_bindgen]
+pub fn verify_zip(bytes: Uint8Array, password: Option<String>) -> Result<JsValue, JsValue> {
+    let report =
+        verify::verify_archive(&bytes.to_vec(), password.as_deref()).map_err(to_js_error)?;
+    serde_wasm_bindgen::to_value(&report).map_err(to_js_error)
+}
+
+/// Check a ZIP archive against the checksum manifest `zip_files` embedded with the
+/// `manifest` option: every listed file is hashed again. Returns
+/// `{ ok, manifests, entries, unlisted }`, with a `status` of `ok`, `mismatch` or
+/// `missing` per listed file. `options` takes `{ password, name }`.
+#[wasm_bindgen]
+pub fn verify_manifest(bytes: Uint8Array, options: JsValue) -> Result<JsValue, JsValue> {
+    let options: VerifyManifestOptions = parse_options(options)?;
+    let report = manifest::verify_manifest(&bytes.to_vec(), &options).map_err(to_js_error)?;
+    serde_wasm_bindgen::to_value(&report).map_err(to_js_error)
+}
+
+/// Split a ZIP archive into volumes of at most `options.volume_size` bytes, returned as
+/// `{ name, data }` objects named `.z01`, `.z02`, ... and finally `.zip`, after
+/// `options.name`. An archive that already fits is returned as the only volume.
+#[wasm_bindgen]
+pub fn split_zip(bytes: Uint8Array, options: JsValue) -> Result<Array, JsValue> {
+    let options: SplitOptions = parse_options(options)?;
+    let volumes = split::split_archive(&bytes.to_vec(), &options).map_err(to_js_error)?;
+    volume_array(volumes)
+}
+
+/// Convert split volumes into `{ name, data }` objects.
+fn volume_array(volumes: Vec<split::Volume>) -> Result<Array, JsValue> {
+    let out = Array::new_with_length(volumes.len() as u32);
+    for (idx, volume) in volumes.into_iter().enumerate() {
+        let obj = Object::new();
+        Reflect::set(&obj, &"name".into(), &volume.name.into())?;
+        Reflect::set(
+            &obj,
+            &"data".into(),
+            &Uint8Array::from(volume.data.as_slice()),
+        )?;
+        out.set(idx as u32, obj.into());
+    }
+
+    Ok(out)
+}
+
+/// Join the volumes of a split archive, given as `Uint8Array`s in order from `.z01` to
+/// `.zip`, into one archive that the other functions can read.
+#[wasm_bindgen]
+pub fn join_zip(volumes: Array) -> Result<Uint8Array, JsValue> {
+    let volumes: Vec<Vec<u8>> = volumes
+        .iter()
+        .map(|volume| Uint8Array::new(&volume).to_vec())
+        .collect();
+    let bytes = split::join_volumes(&volumes).map_err(to_js_error)?;
     Ok(Uint8Array::from(bytes.as_slice()))
 }
+
+/// Decompress every entry of a ZIP archive into
+/// `{ name, is_dir, last_modified, unix_mode, data }` objects.
+/// `password` is needed when any entry is encrypted. `limits` overrides the default
+/// extraction limits.
+#[wasm_bindgen]
+pub fn extract_zip(
+    bytes: Uint8Array,
+    password: Option<String>,
+    limits: JsValue,
+) -> Result<Array, JsValue> {
+    let limits: Limits = parse_options(limits)?;
+    let entries =
+        read::extract_all(&bytes.to_vec(), password.as_deref(), &limits).map_err(to_js_error)?;
+    extracted_array(entries)
+}
+
+/// Convert extracted entries into `{ name, is_dir, last_modified, unix_mode, data }` objects.
+fn extracted_array(entries: Vec<read::ExtractedEntry>) -> Result<Array, JsValue> {
+    let out = Array::new_with_length(entries.len() as u32);
+
+    for (idx, entry) in entries.into_iter().enumerate() {
+        let obj = Object::new();
+        Reflect::set(&obj, &"name".into(), &entry.name.into())?;
+        Reflect::set(&obj, &"is_dir".into(), &entry.is_dir.into())?;
+        Reflect::set(&obj, &"last_modified".into(), &entry.last_modified.into())?;
+        Reflect::set(&obj, &"unix_mode".into(), &entry.unix_mode.into())?;
+        Reflect::set(
+            &obj,
+            &"data".into(),
+            &Uint8Array::from(entry.data.as_slice()),
+        )?;
+        out.set(idx as u32, obj.into());
+    }
+
+    Ok(out)
+}
+
+/// Name the format of an archive (`"zip"`, `"7z"` or `"rar"`) from its first bytes.
+#[wasm_bindgen]
+pub fn detect_archive(bytes: &[u8]) -> Option<String> {
+    ArchiveFormat::detect(bytes).map(|format| format.name().to_string())
+}
+
+/// List the entries of a ZIP, 7z or RAR archive as `{ format, entries }`, with entries
+/// shaped like those of `list_zip`.
+#[wasm_bindgen]
+pub fn list_archive(bytes: Uint8Array) -> Result<JsValue, JsValue> {
+    let (format, entries) = archive::list_entries(&bytes.to_vec()).map_err(to_js_error)?;
+    let obj = Object::new();
+    Reflect::set(&obj, &"format".into(), &format.name().into())?;
+    Reflect::set(
+        &obj,
+        &"entries".into(),
+        &serde_wasm_bindgen::to_value(&entries).map_err(to_js_error)?,
+    )?;
+    Ok(obj.into())
+}
+
+/// Decompress a single entry of a ZIP, 7z or RAR archive by name. 7z entries must use
+/// LZMA, LZMA2 or no compression; RAR entries must be stored. `password` only applies
+/// to ZIP archives.
+#[wasm_bindgen]
+pub fn extract_archive_entry(
+    bytes: Uint8Array,
+    name: String,
+    password: Option<String>,
+    limits: JsValue,
+) -> Result<Uint8Array, JsValue> {
+    let limits: Limits = parse_options(limits)?;
+    let data = archive::extract_entry(&bytes.to_vec(), &name, password.as_deref(), &limits)
+        .map_err(to_js_error)?;
+    Ok(Uint8Array::from(data.as_slice()))
+}
+
+/// Decompress every entry of a ZIP, 7z or RAR archive into the objects `extract_zip`
+/// returns, with the same restrictions as `extract_archive_entry`.
+#[wasm_bindgen]
+pub fn extract_archive(
+    bytes: Uint8Array,
+    password: Option<String>,
+    limits: JsValue,
+) -> Result<Array, JsValue> {
+    let limits: Limits = parse_options(limits)?;
+    let entries =
+        archive::extract_all(&bytes.to_vec(), password.as_deref(), &limits).map_err(to_js_error)?;
+    extracted_array(entries)
+}
+
+/// Convert an archive to `target_format`: `"zip"`, `"tar"`, `"tar.gz"` or `"tar.zst"`.
+/// ZIP, 7z and RAR sources are read as `extract_archive` reads them, anything else as
+/// a tar archive, plain or compressed with gzip or zstd. Names, modification times,
+/// Unix permissions and symbolic links are kept. `options` takes
+/// `{ password, method, level, tar_format, limits }`.
+#[wasm_bindgen]
+pub fn repack_archive(
+    bytes: Uint8Array,
+    target_format: String,
+    options: JsValue,
+) -> Result<Uint8Array, JsValue> {
+    let format = RepackFormat::from_name(&target_format).map_err(to_js_error)?;
+    let options: RepackOptions = parse_options(options)?;
+    let data = repack::repack(&bytes.to_vec(), format, &options).map_err(to_js_error)?;
+    Ok(Uint8Array::from(data.as_slice()))
+}
+
+/// Compress a single buffer with gzip, zlib, raw deflate, brotli or zstd.
+/// `options` is `{ format, level }`; the format defaults to gzip.
+#[wasm_bindgen]
+pub fn compress(bytes: &[u8], options: JsValue) -> Result<Uint8Array, JsValue> {
+    let options: CompressOptions = parse_options(options)?;
+    let data = codec::compress(bytes, &options).map_err(to_js_error)?;
+    Ok(Uint8Array::from(data.as_slice()))
+}
+
+/// Decompress a single buffer. Without `options.format`, gzip, zlib and zstd are
+/// detected from the data and anything else is decoded as brotli.
+#[wasm_bindgen]
+pub fn decompress(bytes: &[u8], options: JsValue) -> Result<Uint8Array, JsValue> {
+    let options: DecompressOptions = parse_options(options)?;
+    let data = codec::decompress(bytes, &options).map_err(to_js_error)?;
+    Ok(Uint8Array::from(data.as_slice()))
+}
+
+/// Name the format of a compressed buffer (`"gzip"`, `"zlib"` or `"zstd"`), if it has a signature.
+#[wasm_bindgen]
+pub fn detect_format(bytes: &[u8]) -> Option<String> {
+    Format::detect(bytes).map(|format| format.name().to_string())
+}
+
+/// Chunked counterpart of `compress` for inputs too large to pass in one buffer.
+#[wasm_bindgen]
+pub struct StreamCompressor {
+    inner: StreamEncoder,
+}
+
+#[wasm_bindgen]
+impl StreamCompressor {
+    #[wasm_bindgen(constructor)]
+    pub fn new(options: JsValue) -> Result<StreamCompressor, JsValue> {
+        let options: CompressOptions = parse_options(options)?;
+        Ok(StreamCompressor {
+            inner: StreamEncoder::new(&options).map_err(to_js_error)?,
+        })
+    }
+
+    /// Compress a chunk and return the output ready so far, which may be empty.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Uint8Array, JsValue> {
+        let data = self.inner.push(bytes).map_err(to_js_error)?;
+        Ok(Uint8Array::from(data.as_slice()))
+    }
+
+    /// Return the remaining output. The compressor cannot be used afterwards.
+    pub fn finish(self) -> Result<Uint8Array, JsValue> {
+        let data = self.inner.finish().map_err(to_js_error)?;
+        Ok(Uint8Array::from(data.as_slice()))
+    }
+}
+
+/// Chunked counterpart of `decompress`.
+#[wasm_bindgen]
+pub struct StreamDecompressor {
+    inner: StreamDecoder,
+}
+
+#[wasm_bindgen]
+impl StreamDecompressor {
+    #[wasm_bindgen(constructor)]
+    pub fn new(options: JsValue) -> Result<StreamDecompressor, JsValue> {
+        let options: DecompressOptions = parse_options(options)?;
+        Ok(StreamDecompressor {
+            inner: StreamDecoder::new(&options).map_err(to_js_error)?,
+        })
+    }
+
+    /// Decompress a chunk and return the output ready so far, which may be empty.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Uint8Array, JsValue> {
+        let data = self.inner.push(bytes).map_err(to_js_error)?;
+        Ok(Uint8Array::from(data.as_slice()))
+    }
+
+    /// Check that the stream was complete and return the remaining output.
+    /// The decompressor cannot be used afterwards.
+    pub fn finish(self) -> Result<Uint8Array, JsValue> {
+        let data = self.inner.finish().map_err(to_js_error)?;
+        Ok(Uint8Array::from(data.as_slice()))
+    }
+}
@@ -0,0 +1,397 @@
+//! Resource limits for extraction.
+//!
+//! A small upload can expand into more data than a browser tab can hold. Sizes
+//! declared in the central directory are checked before anything is decompressed,
+//! but they can lie, so the bytes actually produced are counted as well and
+//! decompression stops as soon as a limit is crossed.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use serde::Deserialize;
+
+use crate::error;
+
+/// Uncompressed bytes extraction may produce unless told otherwise: half of what
+/// 32-bit wasm can address, leaving room for the archive itself.
+pub const DEFAULT_MAX_TOTAL_SIZE: u64 = 2 << 30;
+pub const DEFAULT_MAX_ENTRIES: u64 = 100_000;
+pub const DEFAULT_MAX_PATH_DEPTH: u32 = 64;
+/// Output every entry may produce before `max_ratio` applies, so tiny entries with
+/// a high ratio, which cannot exhaust anything, are not rejected.
+pub const RATIO_GRACE: u64 = 1 << 20;
+/// Most bytes reserved for an entry before any of it is read. Declared sizes may lie,
+/// so larger entries grow their buffer as the data arrives.
+const MAX_PREALLOCATION: u64 = 1 << 20;
+
+/// Limits applied while extracting. `None` turns a limit off.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Uncompressed bytes across all extracted entries.
+    pub max_total_size: Option<u64>,
+    /// Uncompressed size over compressed size, for any one entry. Off by default:
+    /// deflate alone stays under about 1,032:1, but other methods legitimately go far
+    /// higher on empty disk images and the like.
+    pub max_ratio: Option<f64>,
+    /// Entries in the archive, directories included.
+    pub max_entries: Option<u64>,
+    /// Path components in an entry name: `a/b/c.txt` has a depth of 3. Archives stored
+    /// inside the archive are never opened, so their nesting is not limited.
+    pub max_path_depth: Option<u32>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_total_size: Some(DEFAULT_MAX_TOTAL_SIZE),
+            max_ratio: None,
+            max_entries: Some(DEFAULT_MAX_ENTRIES),
+            max_path_depth: Some(DEFAULT_MAX_PATH_DEPTH),
+        }
+    }
+}
+
+impl Limits {
+    /// Limits that do not restrict anything, for archives the caller already trusts.
+    pub fn none() -> Self {
+        Self {
+            max_total_size: None,
+            max_ratio: None,
+            max_entries: None,
+            max_path_depth: None,
+        }
+    }
+}
+
+/// Which limit an archive exceeded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitExceeded {
+    TotalSize(u64),
+    Ratio {
+        name: String,
+        max: f64,
+    },
+    Entries(u64),
+    PathDepth {
+        name: String,
+        max: u32,
+    },
+    /// Entries share compressed data, the construction of overlapping zip bombs,
+    /// which multiply their output without a high ratio for any one entry.
+    Overlap {
+        name: String,
+    },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::TotalSize(max) => {
+                write!(f, "archive expands to more than {max} bytes")
+            }
+            LimitExceeded::Ratio { name, max } => {
+                write!(
+                    f,
+                    "{name:?} expands more than {max} times its compressed size"
+                )
+            }
+            LimitExceeded::Entries(max) => write!(f, "archive has more than {max} entries"),
+            LimitExceeded::PathDepth { name, max } => {
+                write!(f, "{name:?} has more than {max} path components")
+            }
+            LimitExceeded::Overlap { name } => {
+                write!(f, "{name:?} overlaps the data of another entry")
+            }
+        }
+    }
+}
+
+/// Tracks one extraction against its [`Limits`].
+pub(crate) struct Budget<'a> {
+    limits: &'a Limits,
+    /// Uncompressed bytes produced so far.
+    used: u64,
+}
+
+impl<'a> Budget<'a> {
+    pub fn new(limits: &'a Limits) -> Self {
+        Self { limits, used: 0 }
+    }
+
+    pub fn check_entries(&self, count: usize) -> Result<(), LimitExceeded> {
+        match self.limits.max_entries {
+            Some(max) if count as u64 > max => Err(LimitExceeded::Entries(max)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_path_depth(&self, name: &str) -> Result<(), LimitExceeded> {
+        let depth = name.trim_end_matches('/').split('/').count();
+        match self.limits.max_path_depth {
+            Some(max) if depth as u64 > u64::from(max) => Err(LimitExceeded::PathDepth {
+                name: name.to_string(),
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reject up front when entries declare more uncompressed bytes than the total
+    /// size still allows.
+    pub fn check_declared(&self, size: u64) -> Result<(), LimitExceeded> {
+        match self.limits.max_total_size {
+            Some(max) if self.used.saturating_add(size) > max => Err(LimitExceeded::TotalSize(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Most bytes an entry may still produce: past this, [`Budget::consume`] fails, so
+    /// decompressing one byte more is enough to know.
+    pub fn allowance(&self, compressed_size: u64) -> u64 {
+        let total = self
+            .limits
+            .max_total_size
+            .map_or(u64::MAX, |max| max.saturating_sub(self.used));
+        let ratio = self.limits.max_ratio.map_or(u64::MAX, |max| {
+            ((compressed_size as f64 * max) as u64).max(RATIO_GRACE)
+        });
+        total.min(ratio)
+    }
+
+    /// Count `produced` bytes of the entry `name` against the limits.
+    pub fn consume(
+        &mut self,
+        name: &str,
+        compressed_size: u64,
+        produced: u64,
+    ) -> Result<(), LimitExceeded> {
+        if let Some(max) = self.limits.max_total_size {
+            if self.used.saturating_add(produced) > max {
+                return Err(LimitExceeded::TotalSize(max));
+            }
+        }
+        if let Some(max) = self.limits.max_ratio {
+            if produced > RATIO_GRACE && produced as f64 > compressed_size as f64 * max {
+                return Err(LimitExceeded::Ratio {
+                    name: name.to_string(),
+                    max,
+                });
+            }
+        }
+        self.used += produced;
+        Ok(())
+    }
+
+    /// Read the whole entry `name` from `reader`, stopping as soon as it produces more
+    /// than the limits allow. `declared_size` is only a hint: it may lie, so it never
+    /// decides how much is read.
+    pub fn read(
+        &mut self,
+        reader: impl Read,
+        name: &str,
+        compressed_size: u64,
+        declared_size: u64,
+    ) -> error::Result<Vec<u8>> {
+        self.check_declared(declared_size)?;
+        let allowance = self.allowance(compressed_size);
+        let capacity = declared_size.min(allowance).min(MAX_PREALLOCATION);
+        let mut data = Vec::with_capacity(capacity as usize);
+        reader
+            .take(allowance.saturating_add(1))
+            .read_to_end(&mut data)?;
+        self.consume(name, compressed_size, data.len() as u64)?;
+        Ok(data)
+    }
+
+    /// [`Budget::read`] for data that is handed to `out` instead of kept, such as
+    /// entries that are only hashed. Returns the number of bytes produced.
+    pub fn copy(
+        &mut self,
+        reader: impl Read,
+        out: &mut impl Write,
+        name: &str,
+        compressed_size: u64,
+        declared_size: u64,
+    ) -> error::Result<u64> {
+        self.check_declared(declared_size)?;
+        let allowance = self.allowance(compressed_size);
+        let produced = io::copy(&mut reader.take(allowance.saturating_add(1)), out)?;
+        self.consume(name, compressed_size, produced)?;
+        Ok(produced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::error::Error;
+    use crate::read::{extract_all, extract_entry};
+    use crate::staged::{self, put_end_of_central_directory, relocate_central_header};
+    use crate::write::{ArchiveWriter, EntryOptions};
+
+    const BOMB_SIZE: usize = 64 << 20;
+
+    /// One deflated entry of zeros: about 64 KiB that expands to 64 MiB.
+    fn zeros_archive() -> Vec<u8> {
+        let mut writer = ArchiveWriter::new();
+        writer
+            .add_file("zeros.bin", &vec![0; BOMB_SIZE], &EntryOptions::default())
+            .unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn limit(result: crate::error::Result<impl std::fmt::Debug>) -> LimitExceeded {
+        match result {
+            Err(Error::LimitExceeded(limit)) => limit,
+            other => panic!("expected a limit error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn stops_highly_compressed_entries_early() {
+        let bytes = zeros_archive();
+        let small = Limits {
+            max_total_size: Some(8 << 20),
+            ..Limits::default()
+        };
+        assert_eq!(
+            limit(extract_all(&bytes, None, &small)),
+            LimitExceeded::TotalSize(8 << 20)
+        );
+        let ratio = Limits {
+            max_ratio: Some(100.0),
+            ..Limits::default()
+        };
+        assert!(matches!(
+            limit(extract_entry(&bytes, "zeros.bin", None, &ratio)),
+            LimitExceeded::Ratio { name, .. } if name == "zeros.bin"
+        ));
+        assert_eq!(
+            extract_all(&bytes, None, &Limits::default()).unwrap()[0]
+                .data
+                .len(),
+            BOMB_SIZE
+        );
+
+        // Headers that understate the size do not help: output is counted as it is produced.
+        let mut lying = bytes.clone();
+        let (local, central) = {
+            let mut archive = ZipArchive::new(Cursor::new(bytes.as_slice())).unwrap();
+            let file = archive.by_index_raw(0).unwrap();
+            (
+                file.header_start() as usize,
+                file.central_header_start() as usize,
+            )
+        };
+        staged::write_u32(&mut lying, local + 22, 1_000);
+        staged::write_u32(&mut lying, central + 24, 1_000);
+        assert_eq!(
+            limit(extract_all(&lying, None, &small)),
+            LimitExceeded::TotalSize(8 << 20)
+        );
+    }
+
+    #[test]
+    fn does_not_trust_declared_sizes_for_allocation() {
+        // A stored one-byte entry that claims to be exactly as large as the default
+        // limit allows, which on wasm32 is more than one allocation can hold.
+        let mut writer = ArchiveWriter::new();
+        let stored = EntryOptions {
+            method: crate::write::Method::Stored,
+            ..EntryOptions::default()
+        };
+        writer.add_file("x.bin", b"x", &stored).unwrap();
+        let mut bytes = writer.finish().unwrap();
+        let (local, central) = {
+            let mut archive = ZipArchive::new(Cursor::new(bytes.as_slice())).unwrap();
+            let file = archive.by_index_raw(0).unwrap();
+            (
+                file.header_start() as usize,
+                file.central_header_start() as usize,
+            )
+        };
+        staged::write_u32(&mut bytes, local + 22, DEFAULT_MAX_TOTAL_SIZE as u32);
+        staged::write_u32(&mut bytes, central + 24, DEFAULT_MAX_TOTAL_SIZE as u32);
+
+        let data = extract_entry(&bytes, "x.bin", None, &Limits::default()).unwrap();
+        assert_eq!(data, b"x");
+        assert!(data.capacity() as u64 <= MAX_PREALLOCATION);
+    }
+
+    #[test]
+    fn rejects_overlapping_entries() {
+        // Fifield's overlapping construction: many central records share one local
+        // entry, so the archive expands by the number of records without any one
+        // entry having an unusual ratio.
+        let kernel = zeros_archive();
+        let central = {
+            let archive = ZipArchive::new(Cursor::new(kernel.as_slice())).unwrap();
+            archive.central_directory_start() as usize
+        };
+        let end = kernel.len() - staged::END_OF_CENTRAL_DIRECTORY_LEN;
+        let mut bomb = kernel[..central].to_vec();
+        for idx in 0..100 {
+            let name = format!("copy{idx}.bin");
+            let record =
+                relocate_central_header(&kernel[central..end], Some(name.as_bytes()), 0, 0)
+                    .unwrap();
+            bomb.extend_from_slice(&record);
+        }
+        put_end_of_central_directory(&mut bomb, central, 100, b"");
+
+        assert_eq!(
+            limit(extract_all(&bomb, None, &Limits::default())),
+            LimitExceeded::Overlap {
+                name: "copy1.bin".into()
+            }
+        );
+        assert!(matches!(
+            limit(extract_entry(&bomb, "copy7.bin", None, &Limits::default())),
+            LimitExceeded::Overlap { .. }
+        ));
+    }
+
+    #[test]
+    fn limits_entry_count_and_depth() {
+        let mut writer = ArchiveWriter::new();
+        for idx in 0..20 {
+            writer
+                .add_file(&format!("f{idx}"), b"", &EntryOptions::default())
+                .unwrap();
+        }
+        writer
+            .add_file("a/b/c/d/e.txt", b"deep", &EntryOptions::default())
+            .unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let few = Limits {
+            max_entries: Some(10),
+            ..Limits::default()
+        };
+        assert_eq!(
+            limit(extract_all(&bytes, None, &few)),
+            LimitExceeded::Entries(10)
+        );
+        let shallow = Limits {
+            max_path_depth: Some(4),
+            ..Limits::default()
+        };
+        assert_eq!(
+            limit(extract_entry(&bytes, "a/b/c/d/e.txt", None, &shallow)),
+            LimitExceeded::PathDepth {
+                name: "a/b/c/d/e.txt".into(),
+                max: 4
+            }
+        );
+        assert_eq!(extract_entry(&bytes, "f3", None, &shallow).unwrap(), b"");
+        assert_eq!(
+            extract_all(&bytes, None, &Limits::none()).unwrap().len(),
+            21
+        );
+    }
+}
@@ -0,0 +1,608 @@
+//! Checksum manifests embedded in archives.
+//!
+//! [`ArchiveWriter`](crate::write::ArchiveWriter) hashes every file as it is added
+//! and stores the digests as the last entries of the archive, either as
+//! `sha256sum`-style files (`SHA256SUMS`, `B3SUMS`) or as one `manifest.json`.
+//! [`verify_manifest`] hashes the entries again and compares.
+
+use std::collections::HashMap;
+use std::io::{self, Cursor, Write};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::ZipArchive;
+
+use crate::error::{Error, Result};
+use crate::limits::{Budget, Limits};
+use crate::path::sanitize_name;
+use crate::read::{check_archive, open_file, read_file};
+
+pub const SHA256SUMS: &str = "SHA256SUMS";
+pub const B3SUMS: &str = "B3SUMS";
+pub const MANIFEST_JSON: &str = "manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+impl Algorithm {
+    /// Name of the `sha256sum`-style file holding this algorithm's digests.
+    fn sums_file(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => SHA256SUMS,
+            Algorithm::Blake3 => B3SUMS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestFormat {
+    /// One `<hex>  <name>` line per file, as written by `sha256sum` and `b3sum`, in a
+    /// separate file per algorithm.
+    #[default]
+    Sha256sum,
+    /// A single JSON file listing every algorithm's digests and each file's size.
+    Json,
+}
+
+/// The `manifest` archive option.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ManifestOptions {
+    pub format: ManifestFormat,
+    pub algorithms: Vec<Algorithm>,
+    /// Entry name of the manifest. Only for a single `sha256sum` file or for JSON;
+    /// defaults to `SHA256SUMS`, `B3SUMS` or `manifest.json`.
+    pub name: Option<String>,
+}
+
+impl Default for ManifestOptions {
+    fn default() -> Self {
+        Self {
+            format: ManifestFormat::default(),
+            algorithms: vec![Algorithm::Sha256],
+            name: None,
+        }
+    }
+}
+
+impl ManifestOptions {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.algorithms.is_empty() {
+            return Err(Error::InvalidOption(
+                "manifests need at least one algorithm",
+            ));
+        }
+        if self.name.is_some()
+            && self.format == ManifestFormat::Sha256sum
+            && self.algorithms.len() > 1
+        {
+            return Err(Error::InvalidOption(
+                "sha256sum manifests can only be renamed with a single algorithm",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Digests of one file, as lowercase hex.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Digests {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+}
+
+impl Digests {
+    fn get(&self, algorithm: Algorithm) -> Option<&str> {
+        match algorithm {
+            Algorithm::Sha256 => self.sha256.as_deref(),
+            Algorithm::Blake3 => self.blake3.as_deref(),
+        }
+    }
+}
+
+/// Hashes one file with every requested algorithm as its data goes by.
+pub struct Hasher {
+    sha256: Option<Sha256>,
+    blake3: Option<Box<blake3::Hasher>>,
+    size: u64,
+}
+
+impl Hasher {
+    pub fn new(algorithms: &[Algorithm]) -> Self {
+        Self {
+            sha256: algorithms.contains(&Algorithm::Sha256).then(Sha256::new),
+            blake3: algorithms
+                .contains(&Algorithm::Blake3)
+                .then(|| Box::new(blake3::Hasher::new())),
+            size: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        if let Some(sha256) = &mut self.sha256 {
+            sha256.update(data);
+        }
+        if let Some(blake3) = &mut self.blake3 {
+            blake3.update(data);
+        }
+        self.size += data.len() as u64;
+    }
+
+    pub fn finish(self) -> (u64, Digests) {
+        let digests = Digests {
+            sha256: self.sha256.map(|sha256| hex(&sha256.finalize())),
+            blake3: self.blake3.map(|blake3| hex(blake3.finalize().as_bytes())),
+        };
+        (self.size, digests)
+    }
+
+    /// Hash `data` in one go.
+    pub fn digest(algorithms: &[Algorithm], data: &[u8]) -> Digests {
+        let mut hasher = Self::new(algorithms);
+        hasher.update(data);
+        hasher.finish().1
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.update(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// One file listed in a manifest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub size: u64,
+    #[serde(flatten)]
+    pub digests: Digests,
+}
+
+/// Layout of `manifest.json`.
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonManifest {
+    algorithms: Vec<Algorithm>,
+    files: Vec<ManifestEntry>,
+}
+
+/// Digests collected while an archive is written.
+#[derive(Debug)]
+pub struct Manifest {
+    options: ManifestOptions,
+    entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new(options: &ManifestOptions) -> Result<Self> {
+        options.validate()?;
+        let mut options = options.clone();
+        options.name = options.name.as_deref().map(sanitize_name).transpose()?;
+        Ok(Self {
+            options,
+            entries: Vec::new(),
+        })
+    }
+
+    pub fn algorithms(&self) -> &[Algorithm] {
+        &self.options.algorithms
+    }
+
+    pub fn push(&mut self, name: &str, size: u64, digests: Digests) {
+        self.entries.push(ManifestEntry {
+            name: name.to_string(),
+            size,
+            digests,
+        });
+    }
+
+    /// List the files by name instead of in the order they were added, for archives
+    /// whose bytes must not depend on that order.
+    pub fn sort(&mut self) {
+        self.entries.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    /// The manifest files to append to the archive, as `(name, contents)`.
+    pub fn render(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        let files: Vec<(String, Vec<u8>)> = match self.options.format {
+            ManifestFormat::Json => {
+                let manifest = JsonManifest {
+                    algorithms: self.options.algorithms.clone(),
+                    files: self.entries.clone(),
+                };
+                let mut json = serde_json::to_vec_pretty(&manifest)
+                    .map_err(|_| Error::Internal("manifest did not serialize"))?;
+                json.push(b'\n');
+                let name = self.options.name.as_deref().unwrap_or(MANIFEST_JSON);
+                vec![(name.to_string(), json)]
+            }
+            ManifestFormat::Sha256sum => self
+                .options
+                .algorithms
+                .iter()
+                .map(|&algorithm| {
+                    let mut sums = String::new();
+                    for entry in &self.entries {
+                        let digest = entry.digests.get(algorithm).unwrap_or_default();
+                        sums.push_str(&format!("{digest}  {}\n", entry.name));
+                    }
+                    let name = self
+                        .options
+                        .name
+                        .as_deref()
+                        .unwrap_or(algorithm.sums_file());
+                    (name.to_string(), sums.into_bytes())
+                })
+                .collect(),
+        };
+        for (name, _) in &files {
+            if self.entries.iter().any(|entry| &entry.name == name) {
+                return Err(Error::NameCollision(name.clone()));
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Options for `verify_manifest`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct VerifyManifestOptions {
+    pub password: Option<String>,
+    /// Entry name of the manifest. By default `manifest.json`, `SHA256SUMS` and
+    /// `B3SUMS` are all used when present.
+    pub name: Option<String>,
+    /// Bounds on the manifests and the files hashed, as for extraction.
+    pub limits: Limits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ManifestStatus {
+    Ok,
+    /// The entry's data hashes to a different digest.
+    Mismatch,
+    /// The manifest lists a file the archive does not contain.
+    Missing,
+}
+
+/// One manifest line checked against the archive.
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestCheck {
+    pub name: String,
+    pub algorithm: Algorithm,
+    pub status: ManifestStatus,
+    pub expected: String,
+    /// The digest of the entry's data, unless it is missing.
+    pub actual: Option<String>,
+}
+
+/// Result of `verify_manifest`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestReport {
+    /// Whether every listed file is present and matches.
+    pub ok: bool,
+    /// The manifest entries that were read.
+    pub manifests: Vec<String>,
+    pub entries: Vec<ManifestCheck>,
+    /// Files in the archive that no manifest lists.
+    pub unlisted: Vec<String>,
+}
+
+/// Parse a manifest entry. JSON is recognised by its leading `{`; anything else is
+/// read as `sha256sum` lines, holding BLAKE3 digests when the file is named like
+/// `B3SUMS` or `*.b3` and SHA-256 otherwise.
+fn parse_manifest(name: &str, data: &[u8]) -> Result<Vec<(Algorithm, String, String)>> {
+    let text = std::str::from_utf8(data)
+        .map_err(|_| Error::InvalidOption("checksum manifest is not UTF-8"))?;
+    if text.trim_start().starts_with('{') {
+        let manifest: JsonManifest = serde_json::from_str(text)
+            .map_err(|_| Error::InvalidOption("checksum manifest is not valid JSON"))?;
+        let mut lines = Vec::new();
+        for entry in manifest.files {
+            for &algorithm in &manifest.algorithms {
+                if let Some(digest) = entry.digests.get(algorithm) {
+                    lines.push((algorithm, entry.name.clone(), digest.to_string()));
+                }
+            }
+        }
+        return Ok(lines);
+    }
+
+    let lower = name.to_ascii_lowercase();
+    let algorithm = if lower.contains("b3") || lower.contains("blake3") {
+        Algorithm::Blake3
+    } else {
+        Algorithm::Sha256
+    };
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            // `sha256sum -b` marks binary mode with `*` in place of the second space.
+            let (digest, file) = line
+                .split_once("  ")
+                .or_else(|| line.split_once(" *"))
+                .ok_or(Error::InvalidOption("malformed line in checksum manifest"))?;
+            Ok((algorithm, file.to_string(), digest.to_ascii_lowercase()))
+        })
+        .collect()
+}
+
+/// Hash every file in the archive and compare the digests with its manifest.
+pub fn verify_manifest(bytes: &[u8], options: &VerifyManifestOptions) -> Result<ManifestReport> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let mut budget = Budget::new(&options.limits);
+    check_archive(&mut archive, &budget)?;
+    let mut indices = HashMap::new();
+    for idx in 0..archive.len() {
+        let file = archive.by_index_raw(idx)?;
+        if !file.is_dir() {
+            indices.insert(sanitize_name(file.name())?, idx);
+        }
+    }
+
+    let manifests: Vec<String> = match &options.name {
+        Some(name) => vec![sanitize_name(name)?],
+        None => [MANIFEST_JSON, SHA256SUMS, B3SUMS]
+            .into_iter()
+            .filter(|name| indices.contains_key(*name))
+            .map(String::from)
+            .collect(),
+    };
+    if manifests.is_empty() {
+        return Err(Error::EntryNotFound(SHA256SUMS.to_string()));
+    }
+    let password = options.password.as_deref();
+
+    let mut lines = Vec::new();
+    for manifest in &manifests {
+        let idx = *indices
+            .get(manifest)
+            .ok_or_else(|| Error::EntryNotFound(manifest.clone()))?;
+        let data = read_file(&mut open_file(&mut archive, idx, password)?, &mut budget)?;
+        for (algorithm, name, digest) in parse_manifest(manifest, &data)? {
+            lines.push((algorithm, sanitize_name(&name)?, digest));
+        }
+    }
+    let mut algorithms: Vec<Algorithm> = lines.iter().map(|(algorithm, ..)| *algorithm).collect();
+    algorithms.sort_unstable();
+    algorithms.dedup();
+
+    // Hash each listed file once, with every algorithm any manifest uses.
+    let mut actual: HashMap<&str, Digests> = HashMap::new();
+    for (_, name, _) in &lines {
+        if let (Some(&idx), false) = (indices.get(name), actual.contains_key(name.as_str())) {
+            let mut hasher = Hasher::new(&algorithms);
+            let file = open_file(&mut archive, idx, password)?;
+            let (compressed_size, size) = (file.compressed_size(), file.size());
+            budget.copy(file, &mut hasher, name, compressed_size, size)?;
+            actual.insert(name, hasher.finish().1);
+        }
+    }
+
+    let entries: Vec<ManifestCheck> = lines
+        .iter()
+        .map(|(algorithm, name, digest)| {
+            let found = actual
+                .get(name.as_str())
+                .and_then(|digests| digests.get(*algorithm))
+                .map(String::from);
+            let status = match &found {
+                None => ManifestStatus::Missing,
+                Some(found) if found == digest => ManifestStatus::Ok,
+                Some(_) => ManifestStatus::Mismatch,
+            };
+            ManifestCheck {
+                name: name.clone(),
+                algorithm: *algorithm,
+                status,
+                expected: digest.clone(),
+                actual: found,
+            }
+        })
+        .collect();
+
+    let mut unlisted: Vec<(usize, String)> = indices
+        .into_iter()
+        .filter(|(name, _)| !actual.contains_key(name.as_str()) && !manifests.contains(name))
+        .map(|(name, idx)| (idx, name))
+        .collect();
+    unlisted.sort_unstable();
+
+    Ok(ManifestReport {
+        ok: entries
+            .iter()
+            .all(|entry| entry.status == ManifestStatus::Ok),
+        manifests,
+        entries,
+        unlisted: unlisted.into_iter().map(|(_, name)| name).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::{LimitExceeded, Limits};
+    use crate::read::extract_entry;
+    use crate::write::{ArchiveOptions, ArchiveWriter, EntryOptions};
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const ABC_BLAKE3: &str = "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85";
+
+    fn archive_with(manifest: ManifestOptions) -> Vec<u8> {
+        let options = ArchiveOptions {
+            manifest: Some(manifest),
+            ..ArchiveOptions::default()
+        };
+        let mut writer = ArchiveWriter::with_options(&options).unwrap();
+        writer
+            .add_file("abc.txt", b"abc", &EntryOptions::default())
+            .unwrap();
+        writer
+            .add_directory("docs/", &EntryOptions::default())
+            .unwrap();
+        writer
+            .start_file("docs/streamed.txt", &EntryOptions::default())
+            .unwrap();
+        writer.write_chunk(b"a").unwrap();
+        writer.write_chunk(b"bc").unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn entry(bytes: &[u8], name: &str) -> String {
+        String::from_utf8(extract_entry(bytes, name, None, &Limits::default()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn embeds_manifests_that_verify() {
+        let both = vec![Algorithm::Sha256, Algorithm::Blake3];
+        let bytes = archive_with(ManifestOptions {
+            algorithms: both.clone(),
+            ..ManifestOptions::default()
+        });
+        assert_eq!(
+            entry(&bytes, SHA256SUMS),
+            format!("{ABC_SHA256}  abc.txt\n{ABC_SHA256}  docs/streamed.txt\n")
+        );
+        assert_eq!(
+            entry(&bytes, B3SUMS),
+            format!("{ABC_BLAKE3}  abc.txt\n{ABC_BLAKE3}  docs/streamed.txt\n")
+        );
+        let report = verify_manifest(&bytes, &VerifyManifestOptions::default()).unwrap();
+        assert!(report.ok);
+        assert_eq!(report.manifests, [SHA256SUMS, B3SUMS]);
+        assert_eq!(report.entries.len(), 4);
+        assert!(report.unlisted.is_empty());
+
+        let bytes = archive_with(ManifestOptions {
+            format: ManifestFormat::Json,
+            algorithms: both,
+            name: Some("meta/checksums.json".into()),
+        });
+        let json: JsonManifest =
+            serde_json::from_str(&entry(&bytes, "meta/checksums.json")).unwrap();
+        assert_eq!(json.files.len(), 2);
+        assert_eq!(json.files[1].size, 3);
+        assert_eq!(json.files[1].digests.blake3.as_deref(), Some(ABC_BLAKE3));
+        let options = VerifyManifestOptions {
+            name: Some("meta/checksums.json".into()),
+            ..VerifyManifestOptions::default()
+        };
+        assert!(verify_manifest(&bytes, &options).unwrap().ok);
+
+        let taken = ManifestOptions {
+            name: Some("abc.txt".into()),
+            ..ManifestOptions::default()
+        };
+        let mut writer = ArchiveWriter::with_options(&ArchiveOptions {
+            manifest: Some(taken),
+            ..ArchiveOptions::default()
+        })
+        .unwrap();
+        writer
+            .add_file("abc.txt", b"abc", &EntryOptions::default())
+            .unwrap();
+        assert!(matches!(writer.finish(), Err(Error::NameCollision(_))));
+    }
+
+    #[test]
+    fn reports_mismatched_missing_and_unlisted_files() {
+        let original = archive_with(ManifestOptions::default());
+        let sums = entry(&original, SHA256SUMS);
+
+        // Same manifest, but one file changed, one gone and one added.
+        let mut writer = ArchiveWriter::new();
+        writer
+            .add_file("abc.txt", b"abd", &EntryOptions::default())
+            .unwrap();
+        writer
+            .add_file("extra.txt", b"", &EntryOptions::default())
+            .unwrap();
+        writer
+            .add_file(SHA256SUMS, sums.as_bytes(), &EntryOptions::default())
+            .unwrap();
+        let tampered = writer.finish().unwrap();
+
+        let report = verify_manifest(&tampered, &VerifyManifestOptions::default()).unwrap();
+        assert!(!report.ok);
+        let statuses: Vec<(&str, ManifestStatus)> = report
+            .entries
+            .iter()
+            .map(|check| (check.name.as_str(), check.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("abc.txt", ManifestStatus::Mismatch),
+                ("docs/streamed.txt", ManifestStatus::Missing),
+            ]
+        );
+        assert_eq!(report.entries[0].expected, ABC_SHA256);
+        assert_eq!(report.unlisted, ["extra.txt"]);
+
+        assert!(matches!(
+            verify_manifest(
+                &tampered,
+                &VerifyManifestOptions {
+                    name: Some("B3SUMS".into()),
+                    ..VerifyManifestOptions::default()
+                }
+            ),
+            Err(Error::EntryNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn verifies_within_limits() {
+        let bytes = archive_with(ManifestOptions::default());
+        let verify = |limits: Limits| {
+            verify_manifest(
+                &bytes,
+                &VerifyManifestOptions {
+                    limits,
+                    ..VerifyManifestOptions::default()
+                },
+            )
+        };
+        let sums_len = entry(&bytes, SHA256SUMS).len() as u64;
+
+        // The manifest fits, but hashing the files it lists goes over.
+        let small = Limits {
+            max_total_size: Some(sums_len + 4),
+            ..Limits::default()
+        };
+        assert!(matches!(
+            verify(small),
+            Err(Error::LimitExceeded(LimitExceeded::TotalSize(_)))
+        ));
+        let few = Limits {
+            max_entries: Some(2),
+            ..Limits::default()
+        };
+        assert!(matches!(
+            verify(few),
+            Err(Error::LimitExceeded(LimitExceeded::Entries(2)))
+        ));
+        let exact = Limits {
+            max_total_size: Some(sums_len + 6),
+            ..Limits::default()
+        };
+        assert!(verify(exact).unwrap().ok);
+    }
+}
@@ -0,0 +1,275 @@
+//! Compressing independent entries on a rayon thread pool.
+//!
+//! Every file is staged into its own one-entry archive by [`StagedFile::new`] on
+//! whichever thread is free, then the staged entries are appended to the
+//! [`ArchiveWriter`] in input order, so the archive lists entries exactly as the
+//! single-threaded path does. Inputs are staged in [`Batch`]es, so progress is
+//! reported and memory released as each batch is appended.
+//!
+//! Native builds use rayon's global pool. The threaded wasm build (`wasm-threads`)
+//! runs on Web Workers that JS starts with `init_threads`; until they are ready,
+//! callers fall back to the single-threaded path.
+
+use std::io::{Seek, Write};
+
+use rayon::prelude::*;
+
+use crate::error::{Error, Result};
+use crate::manifest::Algorithm;
+use crate::write::{ArchiveWriter, EntryOptions, StagedFile};
+
+/// Input bytes staged at once by default. Larger batches keep more threads busy;
+/// smaller ones report progress sooner and hold fewer copies in memory.
+pub const BATCH_SIZE: usize = 64 << 20;
+
+/// One entry to compress. `data` is `None` for directories.
+pub struct FileInput {
+    pub name: String,
+    pub data: Option<Vec<u8>>,
+    pub options: EntryOptions,
+}
+
+/// What [`stage_files`] produced for one input.
+pub enum Staged {
+    File(StagedFile),
+    /// Directories have nothing to compress and are added when their turn comes.
+    Directory,
+}
+
+/// Compress every file of `inputs` in parallel, hashing it with `algorithms` for the
+/// archive's manifest. The results keep the input order.
+pub fn stage_files(inputs: &[FileInput], algorithms: &[Algorithm]) -> Vec<Result<Staged>> {
+    inputs
+        .par_iter()
+        .map(|input| match &input.data {
+            Some(data) => Ok(Staged::File(
+                StagedFile::new(&input.name, data, &input.options)?.hashed(data, algorithms),
+            )),
+            None => Ok(Staged::Directory),
+        })
+        .collect()
+}
+
+/// Inputs admitted to an archive and waiting to be staged together.
+pub struct Batch {
+    inputs: Vec<FileInput>,
+    /// Position of each input among the caller's, for error messages.
+    indices: Vec<usize>,
+    size: usize,
+    limit: usize,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self::new(BATCH_SIZE)
+    }
+}
+
+impl Batch {
+    /// A batch that is full once its files hold `limit` bytes.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            inputs: Vec::new(),
+            indices: Vec::new(),
+            size: 0,
+            limit,
+        }
+    }
+
+    /// Queue `input`, the caller's input number `index`. Returns whether the batch is
+    /// now full and should be flushed.
+    pub fn push(&mut self, index: usize, input: FileInput) -> bool {
+        self.size += input.data.as_ref().map_or(0, Vec::len);
+        self.inputs.push(input);
+        self.indices.push(index);
+        self.size >= self.limit
+    }
+
+    /// Stage the queued inputs in parallel and append them to `writer` in order,
+    /// which reports their progress. Leaves the batch empty. An error comes with the
+    /// index of the input that caused it.
+    pub fn flush<W: Write + Seek>(
+        &mut self,
+        writer: &mut ArchiveWriter<W>,
+    ) -> std::result::Result<(), (usize, Error)> {
+        let staged = stage_files(&self.inputs, writer.manifest_algorithms());
+        let indices = std::mem::take(&mut self.indices);
+        self.size = 0;
+        for ((input, staged), index) in self.inputs.drain(..).zip(staged).zip(indices) {
+            staged
+                .and_then(|staged| match staged {
+                    Staged::File(file) => writer.add_staged(file),
+                    Staged::Directory => writer.add_admitted_directory(input.name, &input.options),
+                })
+                .map_err(|err| (index, err))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod pool {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static READY: AtomicBool = AtomicBool::new(false);
+
+    /// Start `threads` workers. Parallel compression is used once the promise resolves.
+    #[cfg(feature = "wasm-threads")]
+    pub fn init(threads: usize) -> js_sys::Promise {
+        use wasm_bindgen::prelude::*;
+
+        let ready = Closure::<dyn FnMut(JsValue)>::new(|_| READY.store(true, Ordering::Release));
+        let promise = wasm_bindgen_rayon::init_thread_pool(threads).then(&ready);
+        ready.forget();
+        promise
+    }
+
+    pub fn ready() -> bool {
+        READY.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "wasm-threads"))]
+pub use pool::init as init_threads;
+
+/// Whether a thread pool is available, so [`stage_files`] will not block forever.
+pub fn enabled() -> bool {
+    #[cfg(target_arch = "wasm32")]
+    {
+        pool::ready()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limits;
+    use crate::read;
+    use crate::write::{ArchiveWriter, Encryption, Method};
+
+    #[test]
+    fn matches_the_sequential_archive() {
+        let auto = EntryOptions {
+            method: Method::Auto,
+            last_modified: Some(1_700_000_000_000.0),
+            ..EntryOptions::default()
+        };
+        let secret = EntryOptions {
+            password: Some("pw".into()),
+            encryption: Encryption::ZipCrypto,
+            comment: Some("hidden".into()),
+            ..auto.clone()
+        };
+        let inputs: Vec<FileInput> = (0..24)
+            .map(|idx| FileInput {
+                name: format!("dir{}/file{idx}.txt", idx % 3),
+                data: (idx % 8 != 7).then(|| format!("line {idx}\n").repeat(idx * 50).into_bytes()),
+                options: if idx % 5 == 0 {
+                    secret.clone()
+                } else {
+                    auto.clone()
+                },
+            })
+            .collect();
+
+        let mut sequential = ArchiveWriter::new();
+        for input in &inputs {
+            match &input.data {
+                Some(data) => sequential.add_file(&input.name, data, &input.options),
+                None => sequential.add_directory(&input.name, &input.options),
+            }
+            .unwrap();
+        }
+        let mut parallel = ArchiveWriter::new();
+        for (input, staged) in inputs.iter().zip(stage_files(&inputs, &[])) {
+            match staged.unwrap() {
+                Staged::File(file) => parallel.add_staged(file).unwrap(),
+                Staged::Directory => parallel.add_directory(&input.name, &input.options).unwrap(),
+            }
+        }
+
+        let sequential = sequential.finish().unwrap();
+        let parallel = parallel.finish().unwrap();
+        let summary = |bytes: &[u8]| -> Vec<_> {
+            read::list_entries(bytes)
+                .unwrap()
+                .into_iter()
+                .map(|entry| {
+                    (
+                        entry.name,
+                        entry.method,
+                        entry.compressed_size,
+                        entry.crc32,
+                        entry.comment,
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(summary(&parallel), summary(&sequential));
+        let extracted = read::extract_all(&parallel, Some("pw"), &Limits::default()).unwrap();
+        assert_eq!(extracted.len(), inputs.len());
+        for (entry, input) in extracted.iter().zip(&inputs) {
+            assert_eq!(entry.data, input.data.clone().unwrap_or_default());
+        }
+    }
+
+    #[test]
+    fn reports_progress_per_batch() {
+        use crate::progress::{Progress, ProgressEvent};
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let processed = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&processed);
+        let progress = Progress::new(6_000, move |event: &ProgressEvent| {
+            seen.borrow_mut().push(event.bytes_processed);
+            if event.bytes_processed >= 3_000 {
+                return Err(Error::Aborted("stop".into()));
+            }
+            Ok(())
+        });
+        let mut writer = ArchiveWriter::new();
+        writer.set_progress(progress.with_interval(0.0));
+
+        let mut batch = Batch::new(2_500);
+        let mut result = Ok(());
+        for idx in 0..6 {
+            let input = FileInput {
+                name: format!("file{idx}.txt"),
+                data: Some(vec![b'a' + idx as u8; 1_000]),
+                options: EntryOptions::default(),
+            };
+            if batch.push(idx, input) {
+                result = batch.flush(&mut writer);
+                break;
+            }
+        }
+        // The first batch of three files is appended and reported before the rest are
+        // even queued, so the callback can stop the archive early.
+        assert!(matches!(result, Err((2, Error::Aborted(_)))));
+        assert_eq!(processed.borrow().last(), Some(&3_000));
+    }
+
+    #[test]
+    fn reports_errors_per_input() {
+        let inputs = [
+            FileInput {
+                name: "ok.txt".into(),
+                data: Some(b"fine".to_vec()),
+                options: EntryOptions::default(),
+            },
+            FileInput {
+                name: "../escape.txt".into(),
+                data: Some(b"nope".to_vec()),
+                options: EntryOptions::default(),
+            },
+        ];
+        let staged = stage_files(&inputs, &[]);
+        assert!(staged[0].is_ok());
+        assert!(staged[1].is_err());
+    }
+}
@@ -0,0 +1,433 @@
+//! Entry-name normalization.
+//!
+//! Names are cleaned one component at a time. Every name gets the same basic
+//! treatment: backslashes become `/`, drive letters and leading slashes are removed,
+//! `.` and empty components are dropped, and the result is put in Unicode NFC form.
+//! `..` components and control characters are rejected. On top of that, a
+//! [`NamePolicy`] decides what happens to names that cannot be extracted on Windows
+//! and to names that collide with an earlier entry on a case-insensitive file system.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+use crate::error::{Error, Result};
+
+/// Stems Windows reserves for devices, whatever the extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+/// Printable characters Windows does not allow in file names.
+const INVALID_CHARACTERS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// What to do with names that are unportable or collide with an earlier entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NamePolicy {
+    /// Only the basic cleanup. Unportable names are kept and collisions are not checked.
+    #[default]
+    Keep,
+    /// Fail on Windows reserved names, characters Windows rejects, components ending in
+    /// a dot or space, and names that collide with an earlier entry.
+    Reject,
+    /// Rewrite unportable components and number colliding names: `a.txt`, `a (2).txt`.
+    Rename,
+    /// Like `Rename`, but files colliding with an earlier entry are left out.
+    Dedupe,
+}
+
+/// Why a name was changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeReason {
+    /// Backslashes were turned into `/`.
+    Separator,
+    /// A drive letter or leading slash was removed.
+    Absolute,
+    /// `.` or empty components were removed.
+    DotSegment,
+    /// The name was converted to Unicode NFC.
+    Unicode,
+    /// Control characters were replaced with `_`.
+    ControlCharacter,
+    /// Characters Windows rejects were replaced with `_`.
+    InvalidCharacter,
+    /// `_` was appended to a Windows reserved name such as `CON`.
+    ReservedName,
+    /// Trailing dots and spaces, which Windows drops, were removed.
+    TrailingDotOrSpace,
+    /// The name matched an earlier entry, ignoring case, and was numbered or left out.
+    Collision,
+}
+
+/// One name the normalizer changed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NameChange {
+    pub original: String,
+    /// The name used instead, or `None` when the entry was left out.
+    pub name: Option<String>,
+    pub reasons: Vec<ChangeReason>,
+}
+
+/// Apply the basic cleanup to an entry name, as [`NamePolicy::Keep`] does.
+///
+/// `..` components, control characters and names that end up empty are errors. A
+/// trailing `/` is kept.
+pub fn sanitize_name(name: &str) -> Result<String> {
+    Ok(clean(name, NamePolicy::Keep)?.0)
+}
+
+/// Clean `name` component by component and apply `policy` to unportable components.
+fn clean(name: &str, policy: NamePolicy) -> Result<(String, Vec<ChangeReason>)> {
+    let mut reasons = Vec::new();
+    let mut note = |reason| {
+        if !reasons.contains(&reason) {
+            reasons.push(reason);
+        }
+    };
+
+    let mut path = if name.contains('\\') {
+        note(ChangeReason::Separator);
+        name.replace('\\', "/")
+    } else {
+        name.to_string()
+    };
+    if !is_nfc(&path) {
+        note(ChangeReason::Unicode);
+        path = path.nfc().collect();
+    }
+    let is_dir = path.ends_with('/');
+    let mut rest = path.as_str();
+    // Only a letter, a colon and a separator make a drive: `a:b.txt` is a file name
+    // on every system but Windows, where the policy deals with the colon.
+    if let [drive, b':', b'/', ..] = rest.as_bytes() {
+        if drive.is_ascii_alphabetic() {
+            note(ChangeReason::Absolute);
+            rest = &rest[2..];
+        }
+    }
+    if rest.starts_with('/') {
+        note(ChangeReason::Absolute);
+        rest = rest.trim_start_matches('/');
+    }
+
+    let rest = rest.strip_suffix('/').unwrap_or(rest);
+
+    let mut components = Vec::new();
+    for component in rest.split('/') {
+        match component {
+            ".." => return Err(Error::InvalidName("parent traversal is not allowed")),
+            "" | "." => note(ChangeReason::DotSegment),
+            component => components.push(portable(component, policy, &mut note)?),
+        }
+    }
+    if components.is_empty() {
+        return Err(Error::InvalidName("file name cannot be empty"));
+    }
+
+    let mut cleaned = components.join("/");
+    if is_dir {
+        cleaned.push('/');
+    }
+    Ok((cleaned, reasons))
+}
+
+/// Apply `policy` to one path component.
+fn portable(
+    component: &str,
+    policy: NamePolicy,
+    note: &mut impl FnMut(ChangeReason),
+) -> Result<String> {
+    let rename = matches!(policy, NamePolicy::Rename | NamePolicy::Dedupe);
+    let mut out = String::with_capacity(component.len());
+    for ch in component.chars() {
+        if ch.is_control() {
+            if !rename {
+                return Err(Error::InvalidName(
+                    "file names cannot contain control characters",
+                ));
+            }
+            note(ChangeReason::ControlCharacter);
+            out.push('_');
+        } else if INVALID_CHARACTERS.contains(&ch) && policy != NamePolicy::Keep {
+            if !rename {
+                return Err(Error::InvalidName(
+                    "file names cannot contain < > : \" | ? or *",
+                ));
+            }
+            note(ChangeReason::InvalidCharacter);
+            out.push('_');
+        } else {
+            out.push(ch);
+        }
+    }
+    if policy == NamePolicy::Keep {
+        return Ok(out);
+    }
+
+    let trimmed = out.trim_end_matches(['.', ' ']);
+    if trimmed.len() != out.len() {
+        if !rename {
+            return Err(Error::InvalidName(
+                "file names cannot end in a dot or a space",
+            ));
+        }
+        note(ChangeReason::TrailingDotOrSpace);
+        out = if trimmed.is_empty() {
+            "_".to_string()
+        } else {
+            trimmed.to_string()
+        };
+    }
+
+    let stem_len = out.find('.').unwrap_or(out.len());
+    let stem = out[..stem_len].trim_end_matches(' ');
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        if !rename {
+            return Err(Error::InvalidName(
+                "file names cannot be Windows reserved names such as CON or NUL",
+            ));
+        }
+        note(ChangeReason::ReservedName);
+        out.insert(stem.len(), '_');
+    }
+    Ok(out)
+}
+
+/// Key under which two names collide on a case-insensitive file system.
+fn collision_key(name: &str) -> String {
+    name.trim_end_matches('/').to_lowercase()
+}
+
+/// `name` with ` (n)` inserted before the extension of its last component.
+fn numbered(name: &str, n: usize) -> String {
+    let (path, slash) = match name.strip_suffix('/') {
+        Some(path) => (path, "/"),
+        None => (name, ""),
+    };
+    let base = path.rfind('/').map_or(0, |at| at + 1);
+    let dot = path[base..]
+        .rfind('.')
+        .filter(|&at| at > 0)
+        .map_or(path.len(), |at| base + at);
+    format!("{} ({n}){}{slash}", &path[..dot], &path[dot..])
+}
+
+/// Normalizes the names of an archive's entries under one [`NamePolicy`] and records
+/// every change it makes.
+#[derive(Debug, Default)]
+pub struct NameNormalizer {
+    policy: NamePolicy,
+    /// Collision keys of the names handed out so far, and whether each is a directory.
+    seen: HashMap<String, bool>,
+    changes: Vec<NameChange>,
+}
+
+impl NameNormalizer {
+    pub fn new(policy: NamePolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    /// Clean `name` without claiming it. Directory names get a trailing `/`.
+    pub fn clean(&self, name: &str, is_dir: bool) -> Result<String> {
+        let (mut cleaned, _) = clean(name, self.policy)?;
+        if is_dir && !cleaned.ends_with('/') {
+            cleaned.push('/');
+        }
+        Ok(cleaned)
+    }
+
+    /// The name to store for the entry `name`, or `None` when a collision leaves it out.
+    pub fn normalize(&mut self, name: &str, is_dir: bool) -> Result<Option<String>> {
+        let (mut cleaned, mut reasons) = clean(name, self.policy)?;
+        if is_dir && !cleaned.ends_with('/') {
+            cleaned.push('/');
+        }
+
+        let result = if self.policy == NamePolicy::Keep {
+            Some(cleaned)
+        } else {
+            let result = match self.seen.get(&collision_key(&cleaned)) {
+                None => Some(cleaned.clone()),
+                Some(_) if self.policy == NamePolicy::Reject => {
+                    return Err(Error::NameCollision(cleaned));
+                }
+                // Two directories with the same name merge on extraction anyway.
+                Some(&seen_dir) if seen_dir && is_dir => None,
+                Some(_) if self.policy == NamePolicy::Dedupe && !is_dir => None,
+                Some(_) => (2..)
+                    .map(|n| numbered(&cleaned, n))
+                    .find(|name| !self.seen.contains_key(&collision_key(name))),
+            };
+            if result.as_ref() != Some(&cleaned) {
+                reasons.push(ChangeReason::Collision);
+            }
+            if let Some(name) = &result {
+                self.seen.insert(collision_key(name), is_dir);
+            }
+            result
+        };
+
+        if !reasons.is_empty() {
+            self.changes.push(NameChange {
+                original: name.to_string(),
+                name: result.clone(),
+                reasons,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Every name changed so far, in the order the names were normalized.
+    pub fn changes(&self) -> &[NameChange] {
+        &self.changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleans_names_component_by_component() {
+        let cases = [
+            ("v1..2.txt", "v1..2.txt"),
+            ("/abs/path.txt", "abs/path.txt"),
+            ("C:\\Users\\me\\notes.txt", "Users/me/notes.txt"),
+            ("C:/x", "x"),
+            ("a:b.txt", "a:b.txt"),
+            ("c:x", "c:x"),
+            ("a/./b//c.txt", "a/b/c.txt"),
+            ("docs/", "docs/"),
+            ("re\u{301}sume\u{301}.txt", "r\u{e9}sum\u{e9}.txt"),
+            ("CON.txt", "CON.txt"),
+        ];
+        for (name, cleaned) in cases {
+            assert_eq!(sanitize_name(name).unwrap(), cleaned, "{name}");
+        }
+        for name in [
+            "../evil",
+            "a/../../b",
+            "a\\..\\b",
+            "",
+            "/",
+            "./",
+            "nul\0byte",
+        ] {
+            assert!(
+                matches!(sanitize_name(name), Err(Error::InvalidName(_))),
+                "{name:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn applies_the_policy_to_unportable_names() {
+        for name in ["CON", "aux.tar.gz", "what?.txt", "dots...", "lpt1 .txt"] {
+            let mut names = NameNormalizer::new(NamePolicy::Reject);
+            assert!(names.normalize(name, false).is_err(), "{name}");
+        }
+
+        let mut names = NameNormalizer::new(NamePolicy::Rename);
+        let renamed: Vec<_> = [
+            "CON",
+            "aux.tar.gz",
+            "what?.txt",
+            "dots...",
+            "tab\there",
+            "\\lib\\com1\\x.js",
+        ]
+        .into_iter()
+        .map(|name| names.normalize(name, false).unwrap().unwrap())
+        .collect();
+        assert_eq!(
+            renamed,
+            [
+                "CON_",
+                "aux_.tar.gz",
+                "what_.txt",
+                "dots",
+                "tab_here",
+                "lib/com1_/x.js"
+            ]
+        );
+        assert_eq!(
+            names.changes()[5].reasons,
+            [
+                ChangeReason::Separator,
+                ChangeReason::Absolute,
+                ChangeReason::ReservedName
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_collisions() {
+        let inputs = [
+            ("docs", true),
+            ("Docs/", true),
+            ("README.md", false),
+            ("readme.md", false),
+            ("readme.MD", false),
+            ("cafe\u{301}", false),
+            ("caf\u{e9}", false),
+            ("docs", false),
+        ];
+        let run = |policy| {
+            let mut names = NameNormalizer::new(policy);
+            let out: Vec<_> = inputs
+                .iter()
+                .map(|&(name, is_dir)| names.normalize(name, is_dir).unwrap())
+                .collect();
+            (out, names)
+        };
+
+        let (renamed, names) = run(NamePolicy::Rename);
+        assert_eq!(
+            renamed,
+            [
+                Some("docs/"),
+                None,
+                Some("README.md"),
+                Some("readme (2).md"),
+                Some("readme (3).MD"),
+                Some("caf\u{e9}"),
+                Some("caf\u{e9} (2)"),
+                Some("docs (2)"),
+            ]
+            .map(|name| name.map(String::from))
+        );
+        assert_eq!(
+            names.changes()[0],
+            NameChange {
+                original: "Docs/".into(),
+                name: None,
+                reasons: vec![ChangeReason::Collision],
+            }
+        );
+
+        let (deduped, _) = run(NamePolicy::Dedupe);
+        let kept: Vec<_> = deduped.into_iter().flatten().collect();
+        assert_eq!(kept, ["docs/", "README.md", "caf\u{e9}"]);
+
+        let mut names = NameNormalizer::new(NamePolicy::Reject);
+        names.normalize("a.txt", false).unwrap();
+        assert!(matches!(
+            names.normalize("A.TXT", false),
+            Err(Error::NameCollision(name)) if name == "A.TXT"
+        ));
+
+        let mut names = NameNormalizer::new(NamePolicy::Keep);
+        assert_eq!(names.normalize("a", false).unwrap().as_deref(), Some("a"));
+        assert_eq!(names.normalize("A", false).unwrap().as_deref(), Some("A"));
+        assert!(names.changes().is_empty());
+    }
+}
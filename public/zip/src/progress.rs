@@ -0,0 +1,153 @@
+//! Progress reporting while an archive is written.
+//!
+//! Writers feed every entry start and every slice of input through [`Progress`],
+//! which passes a snapshot to the callback at most once per interval. The first
+//! report and the final one at `finish` are never skipped, so a UI always starts
+//! and ends in the right state.
+
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::time::now_millis;
+
+/// Smallest gap between two reports, in milliseconds.
+pub const DEFAULT_INTERVAL_MS: f64 = 100.0;
+/// Input is fed to the compressor in slices of this size so large files report
+/// progress while they are being compressed.
+pub(crate) const SLICE_SIZE: usize = 1 << 20;
+
+/// What the progress callback receives.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProgressEvent {
+    /// Input bytes consumed so far, across all entries.
+    pub bytes_processed: u64,
+    /// Input bytes expected in total, as given by the caller.
+    pub total_bytes: u64,
+    /// Zero-based index of the entry being written.
+    pub entry_index: usize,
+    pub entry_name: String,
+}
+
+type Callback = Box<dyn FnMut(&ProgressEvent) -> Result<()>>;
+
+/// Rate-limited progress callback. An error returned by the callback stops the
+/// operation that reported it.
+pub struct Progress {
+    callback: Callback,
+    interval: f64,
+    clock: fn() -> f64,
+    last: Option<f64>,
+    event: ProgressEvent,
+}
+
+impl Progress {
+    pub fn new(
+        total_bytes: u64,
+        callback: impl FnMut(&ProgressEvent) -> Result<()> + 'static,
+    ) -> Self {
+        Self {
+            callback: Box::new(callback),
+            interval: DEFAULT_INTERVAL_MS,
+            clock: now_millis,
+            last: None,
+            event: ProgressEvent {
+                total_bytes,
+                ..ProgressEvent::default()
+            },
+        }
+    }
+
+    /// Change the smallest gap between reports.
+    pub fn with_interval(mut self, interval_ms: f64) -> Self {
+        self.interval = interval_ms;
+        self
+    }
+
+    pub(crate) fn start_entry(&mut self, index: usize, name: &str) -> Result<()> {
+        self.event.entry_index = index;
+        self.event.entry_name.clear();
+        self.event.entry_name.push_str(name);
+        self.report(false)
+    }
+
+    pub(crate) fn advance(&mut self, bytes: usize) -> Result<()> {
+        self.event.bytes_processed += bytes as u64;
+        self.report(false)
+    }
+
+    /// Report the final state, with all input processed, whatever the time since the
+    /// last report.
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        self.event.total_bytes = self.event.total_bytes.max(self.event.bytes_processed);
+        self.event.bytes_processed = self.event.total_bytes;
+        self.report(true)
+    }
+
+    fn report(&mut self, force: bool) -> Result<()> {
+        let now = (self.clock)();
+        let due = self.last.is_none_or(|last| now - last >= self.interval);
+        if force || due {
+            self.last = Some(now);
+            (self.callback)(&self.event)?;
+        }
+        Ok(())
+    }
+}
+
+/// Feed `data` to `write` in slices, reporting each one to `progress`.
+pub(crate) fn write_sliced(
+    data: &[u8],
+    progress: &mut Option<Progress>,
+    mut write: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    for slice in data.chunks(SLICE_SIZE) {
+        write(slice)?;
+        if let Some(progress) = progress {
+            progress.advance(slice.len())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    thread_local! {
+        static NOW: Cell<f64> = const { Cell::new(0.0) };
+    }
+
+    fn fake_clock() -> f64 {
+        NOW.with(Cell::get)
+    }
+
+    #[test]
+    fn reports_at_a_bounded_rate() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&events);
+        let mut progress = Progress::new(300, move |event: &ProgressEvent| {
+            seen.borrow_mut().push(event.clone());
+            Ok(())
+        });
+        progress.clock = fake_clock;
+
+        progress.start_entry(0, "a.txt").unwrap();
+        for _ in 0..10 {
+            NOW.with(|now| now.set(now.get() + 30.0));
+            progress.advance(10).unwrap();
+        }
+        progress.start_entry(1, "b.txt").unwrap();
+        progress.advance(200).unwrap();
+        progress.finish().unwrap();
+
+        let events = events.borrow();
+        let processed: Vec<u64> = events.iter().map(|event| event.bytes_processed).collect();
+        // Reports at 0, 120 and 240 ms, then the forced final one.
+        assert_eq!(processed, [0, 40, 80, 300]);
+        let last = events.last().unwrap();
+        assert_eq!((last.entry_index, last.entry_name.as_str()), (1, "b.txt"));
+        assert_eq!(last.total_bytes, 300);
+    }
+}
@@ -0,0 +1,619 @@
+//! Listing of RAR archives, both RAR 4 (`Rar!\x1A\x07\x00`) and RAR 5 (`Rar!\x1A\x07\x01\x00`).
+//!
+//! RAR's compression is proprietary and has no decoder here, so only the headers are
+//! parsed: every entry can be listed, and entries that were stored without compression
+//! can be extracted. Compressed and encrypted entries fail with
+//! [`Error::UnsupportedMethod`] instead of producing nothing.
+
+use std::ops::Range;
+
+use zip::DateTime;
+
+use crate::error::{Error, Result};
+use crate::limits::{Budget, Limits};
+use crate::path::sanitize_name;
+use crate::read::{EntryInfo, ExtractedEntry};
+use crate::time::{dos_to_unix_millis, filetime_to_unix_millis};
+
+pub const RAR4_SIGNATURE: &[u8] = b"Rar!\x1A\x07\x00";
+pub const RAR5_SIGNATURE: &[u8] = b"Rar!\x1A\x07\x01\x00";
+
+const TRUNCATED: Error = Error::InvalidArchive("RAR archive is truncated");
+const BAD_CHECKSUM: Error = Error::InvalidArchive("RAR header checksum mismatch");
+const ENCRYPTED_HEADERS: Error =
+    Error::Unsupported("RAR archives with encrypted headers cannot be read");
+
+/// An entry together with where its data sits in the archive.
+struct RarEntry {
+    info: EntryInfo,
+    data: Range<usize>,
+    /// Whether the data is stored as it is, without compression or encryption.
+    stored: bool,
+    /// Whether the data continues in another volume of a multi-volume set.
+    split: bool,
+}
+
+/// Little-endian reader over one header.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(bytes: &'a [u8], pos: usize) -> Self {
+        Self { bytes, pos }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or(TRUNCATED)?;
+        let slice = self.bytes.get(self.pos..end).ok_or(TRUNCATED)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// RAR 5 variable-length integer: 7 bits per byte, low bits first, high bit set on
+    /// all but the last byte.
+    fn vint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::InvalidArchive("RAR number is too long"))
+    }
+
+    fn vsize(&mut self) -> Result<usize> {
+        usize::try_from(self.vint()?).map_err(|_| TRUNCATED)
+    }
+}
+
+fn end_of(start: usize, len: usize, bytes: &[u8]) -> Result<usize> {
+    start
+        .checked_add(len)
+        .filter(|&end| end <= bytes.len())
+        .ok_or(TRUNCATED)
+}
+
+fn parse(bytes: &[u8]) -> Result<Vec<RarEntry>> {
+    if bytes.starts_with(RAR5_SIGNATURE) {
+        parse_rar5(bytes)
+    } else if bytes.starts_with(RAR4_SIGNATURE) {
+        parse_rar4(bytes)
+    } else {
+        Err(Error::InvalidArchive("not a RAR archive"))
+    }
+}
+
+/// Name the entry as the ZIP functions do: cleaned, with a trailing `/` for directories.
+fn entry_name(name: &str, is_dir: bool) -> Result<String> {
+    if is_dir && !name.ends_with('/') {
+        sanitize_name(&format!("{name}/"))
+    } else {
+        sanitize_name(name)
+    }
+}
+
+fn parse_rar5(bytes: &[u8]) -> Result<Vec<RarEntry>> {
+    const HEADER_EXTRA: u64 = 0x01;
+    const HEADER_DATA: u64 = 0x02;
+    const HEADER_SPLIT_BEFORE: u64 = 0x08;
+    const HEADER_SPLIT_AFTER: u64 = 0x10;
+    const FILE_DIRECTORY: u64 = 0x01;
+    const FILE_UNIX_TIME: u64 = 0x02;
+    const FILE_CRC32: u64 = 0x04;
+    const FILE_UNKNOWN_SIZE: u64 = 0x08;
+    const HOST_UNIX: u64 = 1;
+    const EXTRA_ENCRYPTION: u64 = 0x01;
+    const EXTRA_TIME: u64 = 0x03;
+    const TIME_UNIX: u64 = 0x01;
+    const TIME_MTIME: u64 = 0x02;
+
+    let mut entries = Vec::new();
+    let mut pos = RAR5_SIGNATURE.len();
+    while pos < bytes.len() {
+        let mut header = Cursor::new(bytes, pos);
+        let crc = header.u32()?;
+        let size = header.vsize()?;
+        let end = end_of(header.pos, size, bytes)?;
+        if crc32fast::hash(&bytes[pos + 4..end]) != crc {
+            return Err(BAD_CHECKSUM);
+        }
+        let kind = header.vint()?;
+        let flags = header.vint()?;
+        let extra_size = if flags & HEADER_EXTRA != 0 {
+            header.vsize()?
+        } else {
+            0
+        };
+        let data_size = if flags & HEADER_DATA != 0 {
+            header.vsize()?
+        } else {
+            0
+        };
+        let data = end..end_of(end, data_size, bytes)?;
+        pos = data.end;
+
+        match kind {
+            // File header; type 3 headers hold service data such as comments.
+            2 => {}
+            4 => return Err(ENCRYPTED_HEADERS),
+            5 => break,
+            _ => continue,
+        }
+        let file_flags = header.vint()?;
+        let unpacked_size = header.vint()?;
+        let attributes = header.vint()?;
+        let mut last_modified = if file_flags & FILE_UNIX_TIME != 0 {
+            f64::from(header.u32()?) * 1_000.0
+        } else {
+            0.0
+        };
+        let crc32 = if file_flags & FILE_CRC32 != 0 {
+            header.u32()?
+        } else {
+            0
+        };
+        let compression = header.vint()?;
+        let host_os = header.vint()?;
+        let name_len = header.vsize()?;
+        let name = String::from_utf8_lossy(header.take(name_len)?).into_owned();
+
+        let mut encrypted = false;
+        let mut extra = Cursor::new(&bytes[..end], end.checked_sub(extra_size).ok_or(TRUNCATED)?);
+        while extra.pos < end {
+            let record_size = extra.vsize()?;
+            let record_end = end_of(extra.pos, record_size, &bytes[..end])?;
+            match extra.vint()? {
+                EXTRA_ENCRYPTION => encrypted = true,
+                EXTRA_TIME => {
+                    let time_flags = extra.vint()?;
+                    if time_flags & TIME_MTIME != 0 {
+                        last_modified = if time_flags & TIME_UNIX != 0 {
+                            f64::from(extra.u32()?) * 1_000.0
+                        } else {
+                            filetime_to_unix_millis(extra.u64()?)
+                        };
+                    }
+                }
+                _ => {}
+            }
+            extra.pos = record_end;
+        }
+
+        let is_dir = file_flags & FILE_DIRECTORY != 0;
+        let method = (compression >> 7) & 0x07;
+        entries.push(RarEntry {
+            info: EntryInfo {
+                name: entry_name(&name, is_dir)?,
+                compressed_size: data_size as u64,
+                uncompressed_size: if file_flags & FILE_UNKNOWN_SIZE != 0 {
+                    0
+                } else {
+                    unpacked_size
+                },
+                method: if method == 0 { "stored" } else { "rar5" }.to_string(),
+                crc32,
+                last_modified,
+                is_dir,
+                encrypted,
+                unix_mode: (host_os == HOST_UNIX).then_some(attributes as u32),
+                comment: String::new(),
+            },
+            data,
+            stored: method == 0 && !encrypted,
+            split: flags & (HEADER_SPLIT_BEFORE | HEADER_SPLIT_AFTER) != 0,
+        });
+    }
+    Ok(entries)
+}
+
+fn parse_rar4(bytes: &[u8]) -> Result<Vec<RarEntry>> {
+    const MAIN_HEADER: u8 = 0x73;
+    const FILE_HEADER: u8 = 0x74;
+    const END_HEADER: u8 = 0x7B;
+    const LONG_BLOCK: u16 = 0x8000;
+    const MAIN_ENCRYPTED_HEADERS: u16 = 0x0080;
+    const FILE_SPLIT_BEFORE: u16 = 0x0001;
+    const FILE_SPLIT_AFTER: u16 = 0x0002;
+    const FILE_ENCRYPTED: u16 = 0x0004;
+    const FILE_DIRECTORY: u16 = 0x00E0;
+    const FILE_LARGE: u16 = 0x0100;
+    const FILE_UNICODE_NAME: u16 = 0x0200;
+    const HOST_UNIX: u8 = 3;
+    const METHOD_STORE: u8 = 0x30;
+
+    let mut entries = Vec::new();
+    let mut pos = RAR4_SIGNATURE.len();
+    while pos < bytes.len() {
+        let mut header = Cursor::new(bytes, pos);
+        let crc = header.u16()?;
+        let kind = header.u8()?;
+        let flags = header.u16()?;
+        let size = usize::from(header.u16()?);
+        if size < 7 {
+            return Err(Error::InvalidArchive("RAR header is too short"));
+        }
+        let end = end_of(pos, size, bytes)?;
+        if crc32fast::hash(&bytes[pos + 2..end]) as u16 != crc {
+            return Err(BAD_CHECKSUM);
+        }
+        // In file headers this field is the low half of the packed size.
+        let mut data_size = if flags & LONG_BLOCK != 0 {
+            u64::from(header.u32()?)
+        } else {
+            0
+        };
+
+        match kind {
+            MAIN_HEADER if flags & MAIN_ENCRYPTED_HEADERS != 0 => return Err(ENCRYPTED_HEADERS),
+            END_HEADER => break,
+            FILE_HEADER => {}
+            _ => {
+                pos = end_of(
+                    end,
+                    usize::try_from(data_size).map_err(|_| TRUNCATED)?,
+                    bytes,
+                )?;
+                continue;
+            }
+        }
+        let unpacked_low = header.u32()?;
+        let host_os = header.u8()?;
+        let crc32 = header.u32()?;
+        let dos_time = header.u32()?;
+        let _version = header.u8()?;
+        let method = header.u8()?;
+        let name_len = usize::from(header.u16()?);
+        let attributes = header.u32()?;
+        let mut unpacked_size = u64::from(unpacked_low);
+        if flags & FILE_LARGE != 0 {
+            data_size |= u64::from(header.u32()?) << 32;
+            unpacked_size |= u64::from(header.u32()?) << 32;
+        }
+        let raw_name = header.take(name_len)?;
+        let name = if flags & FILE_UNICODE_NAME != 0 {
+            decode_unicode_name(raw_name)
+        } else {
+            String::from_utf8_lossy(raw_name).into_owned()
+        };
+
+        let data = end..end_of(
+            end,
+            usize::try_from(data_size).map_err(|_| TRUNCATED)?,
+            bytes,
+        )?;
+        pos = data.end;
+        let is_dir = flags & FILE_DIRECTORY == FILE_DIRECTORY;
+        let encrypted = flags & FILE_ENCRYPTED != 0;
+        let last_modified = DateTime::try_from_msdos((dos_time >> 16) as u16, dos_time as u16)
+            .map_or(0.0, dos_to_unix_millis);
+        entries.push(RarEntry {
+            info: EntryInfo {
+                name: entry_name(&name, is_dir)?,
+                compressed_size: data_size,
+                uncompressed_size: unpacked_size,
+                method: if method == METHOD_STORE {
+                    "stored"
+                } else {
+                    "rar4"
+                }
+                .to_string(),
+                crc32,
+                last_modified,
+                is_dir,
+                encrypted,
+                unix_mode: (host_os == HOST_UNIX).then_some(attributes),
+                comment: String::new(),
+            },
+            data,
+            stored: method == METHOD_STORE && !encrypted,
+            split: flags & (FILE_SPLIT_BEFORE | FILE_SPLIT_AFTER) != 0,
+        });
+    }
+    Ok(entries)
+}
+
+/// Decode a RAR 4 Unicode name: an ASCII version, a zero byte, then UTF-16 packed
+/// relative to the ASCII version. Names without the zero byte are plain UTF-8.
+fn decode_unicode_name(raw: &[u8]) -> String {
+    let Some(split) = raw.iter().position(|&byte| byte == 0) else {
+        return String::from_utf8_lossy(raw).into_owned();
+    };
+    let (ascii, packed) = (&raw[..split], &raw[split + 1..]);
+    decode_packed_name(ascii, packed).unwrap_or_else(|| String::from_utf8_lossy(ascii).into_owned())
+}
+
+fn decode_packed_name(ascii: &[u8], packed: &[u8]) -> Option<String> {
+    let mut input = packed.iter().copied();
+    let high = u16::from(input.next()?) << 8;
+    let mut out: Vec<u16> = Vec::new();
+    let mut flags = 0u8;
+    let mut flag_bits = 0;
+    while let Some(byte) = input.next() {
+        if flag_bits == 0 {
+            flags = byte;
+            flag_bits = 8;
+            continue;
+        }
+        match flags >> 6 {
+            0 => out.push(u16::from(byte)),
+            1 => out.push(u16::from(byte) | high),
+            2 => out.push(u16::from(byte) | u16::from(input.next()?) << 8),
+            _ => {
+                // A run copied from the ASCII name, optionally shifted by a correction.
+                if byte & 0x80 != 0 {
+                    let correction = input.next()?;
+                    for _ in 0..(byte & 0x7F) + 2 {
+                        let base = *ascii.get(out.len())?;
+                        out.push(u16::from(base.wrapping_add(correction)) | high);
+                    }
+                } else {
+                    for _ in 0..byte + 2 {
+                        out.push(u16::from(*ascii.get(out.len())?));
+                    }
+                }
+            }
+        }
+        flags <<= 2;
+        flag_bits -= 2;
+    }
+    Some(String::from_utf16_lossy(&out))
+}
+
+/// List every entry in a RAR archive without reading any data.
+pub fn list_entries(bytes: &[u8]) -> Result<Vec<EntryInfo>> {
+    Ok(parse(bytes)?.into_iter().map(|entry| entry.info).collect())
+}
+
+/// The data of a stored entry, checked against its CRC-32 and `budget`.
+fn read_entry(bytes: &[u8], entry: &RarEntry, budget: &mut Budget) -> Result<Vec<u8>> {
+    let info = &entry.info;
+    budget.check_path_depth(&info.name)?;
+    if info.is_dir {
+        return Ok(Vec::new());
+    }
+    if entry.split {
+        return Err(Error::Unsupported(
+            "entries split across RAR volumes cannot be extracted",
+        ));
+    }
+    if !entry.stored {
+        let method = if info.encrypted {
+            "encryption".to_string()
+        } else {
+            info.method.clone()
+        };
+        return Err(Error::UnsupportedMethod {
+            name: info.name.clone(),
+            method,
+        });
+    }
+    let raw = &bytes[entry.data.clone()];
+    let data = budget.read(
+        raw,
+        &info.name,
+        info.compressed_size,
+        info.uncompressed_size,
+    )?;
+    if info.crc32 != 0 && crc32fast::hash(&data) != info.crc32 {
+        return Err(Error::InvalidArchive("RAR entry checksum mismatch"));
+    }
+    Ok(data)
+}
+
+/// Read the entry whose cleaned name matches `name`, within `limits`. Only entries
+/// stored without compression can be read.
+pub fn extract_entry(bytes: &[u8], name: &str, limits: &Limits) -> Result<Vec<u8>> {
+    let wanted = sanitize_name(name)?;
+    let entries = parse(bytes)?;
+    let mut budget = Budget::new(limits);
+    budget.check_entries(entries.len())?;
+    let entry = entries
+        .iter()
+        .find(|entry| entry.info.name == wanted)
+        .ok_or(Error::EntryNotFound(wanted))?;
+    read_entry(bytes, entry, &mut budget)
+}
+
+/// Read every entry in archive order, within `limits`. Fails on the first entry that
+/// is not stored.
+pub fn extract_all(bytes: &[u8], limits: &Limits) -> Result<Vec<ExtractedEntry>> {
+    let entries = parse(bytes)?;
+    let mut budget = Budget::new(limits);
+    budget.check_entries(entries.len())?;
+    entries
+        .iter()
+        .map(|entry| {
+            Ok(ExtractedEntry {
+                name: entry.info.name.clone(),
+                is_dir: entry.info.is_dir,
+                last_modified: entry.info.last_modified,
+                unix_mode: entry.info.unix_mode,
+                data: read_entry(bytes, entry, &mut budget)?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01T00:00:00Z.
+    const MTIME: u32 = 1_704_067_200;
+
+    fn vint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    /// Frame a RAR 5 header body with its size and CRC-32.
+    fn rar5_block(body: &[u8], out: &mut Vec<u8>) {
+        let mut sized = Vec::new();
+        vint(body.len() as u64, &mut sized);
+        sized.extend_from_slice(body);
+        out.extend_from_slice(&crc32fast::hash(&sized).to_le_bytes());
+        out.extend_from_slice(&sized);
+    }
+
+    /// A RAR 5 file header followed by `data`. `method` 0 is stored.
+    fn rar5_file(name: &str, data: &[u8], method: u64, out: &mut Vec<u8>) {
+        let is_dir = name.ends_with('/');
+        let name = name.trim_end_matches('/');
+        let mut fields = Vec::new();
+        vint(if is_dir { 0x01 } else { 0x02 | 0x04 }, &mut fields);
+        vint(data.len() as u64, &mut fields);
+        vint(if is_dir { 0o40755 } else { 0o100644 }, &mut fields);
+        if !is_dir {
+            fields.extend_from_slice(&MTIME.to_le_bytes());
+            fields.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+        }
+        vint(method << 7, &mut fields);
+        vint(1, &mut fields); // Unix
+        vint(name.len() as u64, &mut fields);
+        fields.extend_from_slice(name.as_bytes());
+
+        let mut body = Vec::new();
+        vint(2, &mut body);
+        vint(0x02, &mut body);
+        vint(data.len() as u64, &mut body);
+        body.extend_from_slice(&fields);
+        rar5_block(&body, out);
+        out.extend_from_slice(data);
+    }
+
+    fn rar5_sample() -> Vec<u8> {
+        let mut out = RAR5_SIGNATURE.to_vec();
+        rar5_block(&[1, 0, 0], &mut out);
+        rar5_file("docs/", b"", 0, &mut out);
+        rar5_file("docs/a.txt", b"stored text", 0, &mut out);
+        // The data is not valid RAR compression, but nothing tries to decode it.
+        rar5_file("packed.bin", b"\x0C\x00\x00\x00", 3, &mut out);
+        rar5_block(&[5, 0, 0], &mut out);
+        out
+    }
+
+    /// A RAR 4 block: CRC-16, type, flags and size, then `fields`.
+    fn rar4_block(kind: u8, flags: u16, fields: &[u8], out: &mut Vec<u8>) {
+        let mut header = vec![kind];
+        header.extend_from_slice(&flags.to_le_bytes());
+        header.extend_from_slice(&(7 + fields.len() as u16).to_le_bytes());
+        header.extend_from_slice(fields);
+        out.extend_from_slice(&(crc32fast::hash(&header) as u16).to_le_bytes());
+        out.extend_from_slice(&header);
+    }
+
+    fn rar4_file(name: &[u8], flags: u16, data: &[u8], method: u8, out: &mut Vec<u8>) {
+        let mut fields = Vec::new();
+        fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        fields.push(3); // Unix
+        fields.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+        // 2024-01-01 00:00:00 as a DOS date and time.
+        fields.extend_from_slice(&(((2024 - 1980) << 25 | 1 << 21 | 1 << 16) as u32).to_le_bytes());
+        fields.push(20);
+        fields.push(method);
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        let mode: u32 = if flags & 0x00E0 == 0x00E0 {
+            0o40755
+        } else {
+            0o100644
+        };
+        fields.extend_from_slice(&mode.to_le_bytes());
+        fields.extend_from_slice(name);
+        rar4_block(0x74, 0x8000 | flags, &fields, out);
+        out.extend_from_slice(data);
+    }
+
+    fn rar4_sample() -> Vec<u8> {
+        let mut out = RAR4_SIGNATURE.to_vec();
+        rar4_block(0x73, 0, &[0; 6], &mut out);
+        rar4_file(b"docs", 0x00E0, b"", 0x30, &mut out);
+        rar4_file(b"docs/a.txt", 0, b"stored text", 0x30, &mut out);
+        // "日記.txt": two UTF-16 units in full, then ".txt" copied from the ASCII name.
+        let mut name = b"??.txt\0".to_vec();
+        name.extend_from_slice(&[0x00, 0b10_10_11_00, 0xE5, 0x65, 0x18, 0x8A, 0x02]);
+        rar4_file(&name, 0x0200, b"diary", 0x30, &mut out);
+        rar4_file(b"packed.bin", 0, b"\x0C\x00\x00\x00", 0x33, &mut out);
+        rar4_block(0x7B, 0x4000, &[], &mut out);
+        out
+    }
+
+    #[test]
+    fn lists_rar5_and_extracts_stored_entries() {
+        let bytes = rar5_sample();
+        let entries = list_entries(&bytes).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["docs/", "docs/a.txt", "packed.bin"]);
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[0].unix_mode, Some(0o40755));
+        assert_eq!(entries[1].method, "stored");
+        assert_eq!(entries[1].unix_mode, Some(0o100644));
+        assert_eq!(entries[1].last_modified, f64::from(MTIME) * 1_000.0);
+        assert_eq!(entries[2].method, "rar5");
+
+        let limits = Limits::default();
+        assert_eq!(
+            extract_entry(&bytes, "docs/a.txt", &limits).unwrap(),
+            b"stored text"
+        );
+        assert!(matches!(
+            extract_entry(&bytes, "packed.bin", &limits),
+            Err(Error::UnsupportedMethod { name, method }) if name == "packed.bin" && method == "rar5"
+        ));
+        assert!(matches!(
+            extract_all(&bytes, &limits),
+            Err(Error::UnsupportedMethod { .. })
+        ));
+
+        let mut corrupt = bytes.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 1;
+        assert!(matches!(
+            list_entries(&corrupt),
+            Err(Error::InvalidArchive(_))
+        ));
+    }
+
+    #[test]
+    fn reads_rar4_headers_and_unicode_names() {
+        let bytes = rar4_sample();
+        let entries = list_entries(&bytes).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["docs/", "docs/a.txt", "日記.txt", "packed.bin"]);
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[1].last_modified, f64::from(MTIME) * 1_000.0);
+        assert_eq!(entries[3].method, "rar4");
+
+        assert_eq!(
+            extract_entry(&bytes, "日記.txt", &Limits::default()).unwrap(),
+            b"diary"
+        );
+        assert!(matches!(
+            extract_entry(&bytes, "packed.bin", &Limits::default()),
+            Err(Error::UnsupportedMethod { method, .. }) if method == "rar4"
+        ));
+    }
+}
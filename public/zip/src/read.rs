@@ -0,0 +1,322 @@
+use std::io::{self, Cursor, Read, Seek};
+
+use serde::Serialize;
+use zip::extra_fields::ExtraField;
+use zip::read::ZipFile;
+use zip::result::ZipError;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::error::{Error, Result};
+use crate::limits::{Budget, LimitExceeded, Limits};
+use crate::path::sanitize_name;
+use crate::time::dos_to_unix_millis;
+
+/// Metadata for a single archive entry, as returned to JS by `list_zip`.
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryInfo {
+    pub name: String,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub method: String,
+    pub crc32: u32,
+    /// Modification time in milliseconds since the Unix epoch, read as UTC.
+    pub last_modified: f64,
+    pub is_dir: bool,
+    /// Whether the entry needs a password to extract.
+    pub encrypted: bool,
+    /// Unix mode bits, including the file type, when the archive recorded them.
+    pub unix_mode: Option<u32>,
+    pub comment: String,
+}
+
+/// A decompressed entry produced by `extract_all`.
+#[derive(Debug, Clone)]
+pub struct ExtractedEntry {
+    pub name: String,
+    pub is_dir: bool,
+    /// Same as [`EntryInfo::last_modified`].
+    pub last_modified: f64,
+    pub unix_mode: Option<u32>,
+    pub data: Vec<u8>,
+}
+
+fn open(bytes: &[u8]) -> Result<ZipArchive<Cursor<&[u8]>>> {
+    Ok(ZipArchive::new(Cursor::new(bytes))?)
+}
+
+fn entry_info<R: Read>(file: &ZipFile<'_, R>) -> Result<EntryInfo> {
+    Ok(EntryInfo {
+        name: sanitize_name(file.name())?,
+        compressed_size: file.compressed_size(),
+        uncompressed_size: file.size(),
+        method: method_name(file.compression()),
+        crc32: file.crc32(),
+        last_modified: last_modified(file),
+        is_dir: file.is_dir(),
+        encrypted: file.encrypted(),
+        unix_mode: file.unix_mode(),
+        comment: file.comment().to_string(),
+    })
+}
+
+/// Modification time in milliseconds, preferring the one-second UTC timestamp of the
+/// extended timestamp extra field over the local-time, two-second DOS field.
+fn last_modified<R: Read>(file: &ZipFile<'_, R>) -> f64 {
+    let extended = file.extra_data_fields().find_map(|field| match field {
+        ExtraField::ExtendedTimestamp(timestamp) => timestamp.mod_time(),
+        _ => None,
+    });
+    match extended {
+        Some(seconds) => f64::from(seconds) * 1_000.0,
+        None => file.last_modified().map_or(0.0, dos_to_unix_millis),
+    }
+}
+
+/// Open entry `idx` for reading, decrypting it with `password` when it is encrypted.
+pub(crate) fn open_file<'a, R: Read + Seek>(
+    archive: &'a mut ZipArchive<R>,
+    idx: usize,
+    password: Option<&str>,
+) -> Result<ZipFile<'a, R>> {
+    let (encrypted, name) = {
+        let file = archive.by_index_raw(idx)?;
+        (file.encrypted(), file.name().to_string())
+    };
+
+    let opened = match (encrypted, password) {
+        (false, _) => archive.by_index(idx),
+        (true, Some(password)) => archive.by_index_decrypt(idx, password.as_bytes()),
+        (true, None) => return Err(Error::PasswordRequired(name)),
+    };
+    opened.map_err(|err| match err {
+        ZipError::InvalidPassword => Error::InvalidPassword(name),
+        err => err.into(),
+    })
+}
+
+/// Check the limits that apply to the archive as a whole before anything is
+/// decompressed: the entry count, and that no two entries share data, as the entries
+/// of overlapping zip bombs do. Returns the total uncompressed size the entries declare.
+pub(crate) fn check_archive<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    budget: &Budget,
+) -> Result<u64> {
+    budget.check_entries(archive.len())?;
+    let mut declared = 0u64;
+    let mut spans = Vec::with_capacity(archive.len());
+    for idx in 0..archive.len() {
+        let file = archive.by_index_raw(idx)?;
+        declared = declared.saturating_add(file.size());
+        let end = file.data_start().saturating_add(file.compressed_size());
+        spans.push((file.header_start(), end, idx));
+    }
+    spans.sort_unstable();
+    for pair in spans.windows(2) {
+        let ((_, end, _), (start, _, idx)) = (pair[0], pair[1]);
+        if start < end {
+            let name = archive.by_index_raw(idx)?.name().to_string();
+            return Err(LimitExceeded::Overlap { name }.into());
+        }
+    }
+    Ok(declared)
+}
+
+/// Read a whole entry, stopping as soon as it produces more than `budget` allows.
+/// For encrypted entries a checksum or MAC failure means the password passed the
+/// quick header check but is still wrong.
+pub(crate) fn read_file<R: Read>(
+    file: &mut ZipFile<'_, R>,
+    budget: &mut Budget,
+) -> Result<Vec<u8>> {
+    let name = file.name().to_string();
+    let (compressed_size, size, encrypted) =
+        (file.compressed_size(), file.size(), file.encrypted());
+    match budget.read(file, &name, compressed_size, size) {
+        Err(Error::Io(err)) if encrypted && err.kind() == io::ErrorKind::InvalidData => {
+            Err(Error::InvalidPassword(name))
+        }
+        result => result,
+    }
+}
+
+/// List every entry in a ZIP archive without decompressing any data.
+pub fn list_entries(bytes: &[u8]) -> Result<Vec<EntryInfo>> {
+    let mut archive = open(bytes)?;
+    let mut entries = Vec::with_capacity(archive.len());
+
+    for idx in 0..archive.len() {
+        let file = archive.by_index_raw(idx)?;
+        entries.push(entry_info(&file)?);
+    }
+
+    Ok(entries)
+}
+
+/// Decompress the entry whose sanitized name matches `name`, within `limits`.
+///
+/// `password` is only used for encrypted entries.
+pub fn extract_entry(
+    bytes: &[u8],
+    name: &str,
+    password: Option<&str>,
+    limits: &Limits,
+) -> Result<Vec<u8>> {
+    let wanted = sanitize_name(name)?;
+    let mut archive = open(bytes)?;
+    let mut budget = Budget::new(limits);
+    check_archive(&mut archive, &budget)?;
+
+    for idx in 0..archive.len() {
+        if sanitize_name(archive.by_index_raw(idx)?.name())? != wanted {
+            continue;
+        }
+
+        budget.check_path_depth(&wanted)?;
+        let mut file = open_file(&mut archive, idx, password)?;
+        return read_file(&mut file, &mut budget);
+    }
+
+    Err(Error::EntryNotFound(wanted))
+}
+
+/// Decompress every entry in archive order, within `limits`. Directories come back
+/// with empty data.
+pub fn extract_all(
+    bytes: &[u8],
+    password: Option<&str>,
+    limits: &Limits,
+) -> Result<Vec<ExtractedEntry>> {
+    let mut entries = Vec::new();
+    for_each_entry(bytes, password, limits, |entry| {
+        entries.push(entry);
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+/// Decompress the entries one at a time in archive order, handing each to `each`
+/// before the next is read.
+pub(crate) fn for_each_entry(
+    bytes: &[u8],
+    password: Option<&str>,
+    limits: &Limits,
+    mut each: impl FnMut(ExtractedEntry) -> Result<()>,
+) -> Result<()> {
+    let mut archive = open(bytes)?;
+    let mut budget = Budget::new(limits);
+    budget.check_declared(check_archive(&mut archive, &budget)?)?;
+
+    for idx in 0..archive.len() {
+        let mut file = open_file(&mut archive, idx, password)?;
+        let name = sanitize_name(file.name())?;
+        budget.check_path_depth(&name)?;
+        let is_dir = file.is_dir();
+        let last_modified = last_modified(&file);
+        let unix_mode = file.unix_mode();
+
+        let data = if is_dir {
+            Vec::new()
+        } else {
+            read_file(&mut file, &mut budget)?
+        };
+
+        each(ExtractedEntry {
+            name,
+            is_dir,
+            last_modified,
+            unix_mode,
+            data,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// The archive-level comment, decoded lossily as UTF-8.
+pub fn archive_comment(bytes: &[u8]) -> Result<String> {
+    Ok(String::from_utf8_lossy(open(bytes)?.comment()).into_owned())
+}
+
+pub(crate) fn method_name(method: CompressionMethod) -> String {
+    match method {
+        CompressionMethod::Stored => "stored".to_string(),
+        CompressionMethod::Deflated => "deflated".to_string(),
+        other => other.to_string().to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::SimpleFileOptions;
+    use zip::{DateTime, ZipWriter};
+
+    use super::*;
+
+    fn sample_archive() -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(DateTime::from_date_and_time(2024, 3, 1, 12, 30, 10).unwrap());
+        writer.add_directory("docs/", options).unwrap();
+        writer.start_file("docs/readme.txt", options).unwrap();
+        writer.write_all(b"hello hello hello").unwrap();
+        writer.start_file("/rooted.bin", options).unwrap();
+        writer.write_all(&[1, 2, 3]).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn lists_entries_with_metadata() {
+        let entries = list_entries(&sample_archive()).unwrap();
+        let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["docs/", "docs/readme.txt", "rooted.bin"]);
+
+        let readme = &entries[1];
+        assert!(!readme.is_dir);
+        assert_eq!(readme.uncompressed_size, 17);
+        assert_eq!(readme.method, "deflated");
+        assert_eq!(readme.crc32, crc32fast::hash(b"hello hello hello"));
+        assert_eq!(readme.last_modified, 1_709_296_210_000.0);
+        assert!(entries[0].is_dir);
+    }
+
+    #[test]
+    fn extracts_single_and_all_entries() {
+        let bytes = sample_archive();
+        assert_eq!(
+            extract_entry(&bytes, "docs/readme.txt", None, &Limits::default()).unwrap(),
+            b"hello hello hello"
+        );
+        assert_eq!(
+            extract_entry(&bytes, "/rooted.bin", None, &Limits::default()).unwrap(),
+            [1, 2, 3]
+        );
+        assert!(matches!(
+            extract_entry(&bytes, "missing.txt", None, &Limits::default()),
+            Err(Error::EntryNotFound(_))
+        ));
+
+        let all = extract_all(&bytes, None, &Limits::default()).unwrap();
+        assert_eq!(all.len(), 3);
+        assert!(all[0].is_dir && all[0].data.is_empty());
+        assert_eq!(all[2].data, [1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_traversal_names() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("../evil.txt", SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"x").unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert!(matches!(list_entries(&bytes), Err(Error::InvalidName(_))));
+        assert!(matches!(
+            extract_all(&bytes, None, &Limits::default()),
+            Err(Error::InvalidName(_))
+        ));
+    }
+}
@@ -0,0 +1,284 @@
+//! Conversion between archive formats: ZIP, 7z, RAR or tar in, ZIP or tar out.
+//!
+//! Entries pass from the source reader to the target writer one at a time, so only
+//! one decompressed entry is held at once for ZIP and tar sources. Names, modification
+//! times, Unix permissions and symbolic links are carried over. ZIP stores times with
+//! one-second resolution and tar with whole seconds, so sub-second parts are dropped.
+
+use serde::Deserialize;
+
+use crate::archive::{self, ArchiveFormat};
+use crate::error::{Error, Result};
+use crate::limits::Limits;
+use crate::read::ExtractedEntry;
+use crate::tarball::{self, TarCompression, TarEntryOptions, TarFormat, TarOptions, TarWriter};
+use crate::write::{ArchiveWriter, EntryOptions, Method};
+
+/// File-type bits of a Unix mode and the value marking a symbolic link.
+const S_IFMT: u32 = 0o170_000;
+const S_IFLNK: u32 = 0o120_000;
+
+/// The format `repack_archive` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepackFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl RepackFormat {
+    /// Parse a target name: `"zip"`, `"tar"`, `"tar.gz"` (or `"tgz"`) or `"tar.zst"`.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "zip" => Ok(RepackFormat::Zip),
+            "tar" => Ok(RepackFormat::Tar),
+            "tar.gz" | "tgz" => Ok(RepackFormat::TarGz),
+            "tar.zst" => Ok(RepackFormat::TarZst),
+            _ => Err(Error::InvalidOption(
+                "target format must be zip, tar, tar.gz or tar.zst",
+            )),
+        }
+    }
+}
+
+/// Options for `repack_archive`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RepackOptions {
+    /// Password for encrypted entries of a ZIP source. The output is never encrypted.
+    pub password: Option<String>,
+    /// Compression method of ZIP output. Defaults to deflate.
+    pub method: Option<Method>,
+    /// Level of the ZIP method, or of the gzip or zstd compression of tar output.
+    pub level: Option<u32>,
+    /// Header format of tar output.
+    pub tar_format: TarFormat,
+    /// Limits on what the source may expand to.
+    pub limits: Limits,
+}
+
+/// The writer entries are repacked into.
+enum Target {
+    Zip {
+        writer: Box<ArchiveWriter>,
+        method: Method,
+        level: Option<u32>,
+    },
+    Tar(TarWriter),
+}
+
+impl Target {
+    fn new(format: RepackFormat, options: &RepackOptions) -> Result<Self> {
+        let compression = match format {
+            RepackFormat::Zip => {
+                return Ok(Target::Zip {
+                    writer: Box::new(ArchiveWriter::new()),
+                    method: options.method.unwrap_or_default(),
+                    level: options.level,
+                })
+            }
+            RepackFormat::Tar => TarCompression::None,
+            RepackFormat::TarGz => TarCompression::Gzip,
+            RepackFormat::TarZst => TarCompression::Zstd,
+        };
+        if options.method.is_some() {
+            return Err(Error::InvalidOption("a method only applies to zip output"));
+        }
+        Ok(Target::Tar(TarWriter::new(&TarOptions {
+            format: options.tar_format,
+            compression,
+            level: options.level,
+            ..TarOptions::default()
+        })?))
+    }
+
+    fn add(&mut self, entry: ExtractedEntry) -> Result<()> {
+        let link_target = (!entry.is_dir
+            && entry.unix_mode.is_some_and(|mode| mode & S_IFMT == S_IFLNK))
+        .then(|| String::from_utf8_lossy(&entry.data).into_owned());
+        // Sources without a timestamp report 0, which is kept rather than replaced
+        // with the current time so repacking stays reproducible.
+        let last_modified = Some(entry.last_modified);
+
+        match self {
+            Target::Zip {
+                writer,
+                method,
+                level,
+            } => {
+                let options = EntryOptions {
+                    unix_permissions: entry.unix_mode,
+                    method: *method,
+                    level: *level,
+                    last_modified,
+                    ..EntryOptions::default()
+                };
+                match link_target {
+                    _ if entry.is_dir => writer.add_directory(&entry.name, &options),
+                    Some(target) => writer.add_symlink(&entry.name, &target, &options),
+                    None => writer.add_file(&entry.name, &entry.data, &options),
+                }
+            }
+            Target::Tar(writer) => {
+                let options = TarEntryOptions {
+                    unix_permissions: entry.unix_mode,
+                    last_modified,
+                    link_target,
+                };
+                if entry.is_dir {
+                    writer.add_directory(&entry.name, &options)
+                } else {
+                    writer.add_file(&entry.name, &entry.data, &options)
+                }
+            }
+        }
+    }
+
+    fn finish(self) -> Result<Vec<u8>> {
+        match self {
+            Target::Zip { writer, .. } => writer.finish(),
+            Target::Tar(writer) => writer.finish(),
+        }
+    }
+}
+
+/// Repack a ZIP, 7z, RAR or tar archive (plain, gzip or zstd) as `format`. Sources
+/// that are not ZIP, 7z or RAR are read as tar.
+pub fn repack(bytes: &[u8], format: RepackFormat, options: &RepackOptions) -> Result<Vec<u8>> {
+    let mut target = Target::new(format, options)?;
+    let add = |entry| target.add(entry);
+    if ArchiveFormat::detect(bytes).is_some() {
+        archive::for_each_entry(bytes, options.password.as_deref(), &options.limits, add)?;
+    } else {
+        tarball::for_each_entry(bytes, &options.limits, add)?;
+    }
+    target.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::LimitExceeded;
+    use crate::read;
+
+    /// 2024-01-01T00:00:00Z.
+    const MTIME: f64 = 1_704_067_200_000.0;
+
+    fn sample_zip() -> Vec<u8> {
+        let options = |mode| EntryOptions {
+            unix_permissions: Some(mode),
+            last_modified: Some(MTIME),
+            ..EntryOptions::default()
+        };
+        let mut writer = ArchiveWriter::new();
+        writer.add_directory("bin", &options(0o750)).unwrap();
+        writer
+            .add_file("bin/run.sh", b"#!/bin/sh\necho hi\n", &options(0o755))
+            .unwrap();
+        writer
+            .add_symlink("latest", "bin/run.sh", &options(0o777))
+            .unwrap();
+        writer
+            .add_file("notes.txt", &b"notes ".repeat(500), &options(0o600))
+            .unwrap();
+        writer.finish().unwrap()
+    }
+
+    /// Name, mode, time and data of every entry, whatever the format.
+    fn summary(bytes: &[u8]) -> Vec<(String, Option<u32>, f64, Vec<u8>)> {
+        let mut entries = Vec::new();
+        let collect = |entry: ExtractedEntry| {
+            entries.push((entry.name, entry.unix_mode, entry.last_modified, entry.data));
+            Ok(())
+        };
+        match ArchiveFormat::detect(bytes) {
+            Some(_) => archive::for_each_entry(bytes, None, &Limits::default(), collect),
+            None => tarball::for_each_entry(bytes, &Limits::default(), collect),
+        }
+        .unwrap();
+        entries
+    }
+
+    #[test]
+    fn round_trips_between_zip_and_tar() {
+        let zip = sample_zip();
+        let expected = vec![
+            ("bin/".to_string(), Some(0o040750), MTIME, Vec::new()),
+            (
+                "bin/run.sh".to_string(),
+                Some(0o100755),
+                MTIME,
+                b"#!/bin/sh\necho hi\n".to_vec(),
+            ),
+            (
+                "latest".to_string(),
+                Some(0o120777),
+                MTIME,
+                b"bin/run.sh".to_vec(),
+            ),
+            (
+                "notes.txt".to_string(),
+                Some(0o100600),
+                MTIME,
+                b"notes ".repeat(500),
+            ),
+        ];
+        assert_eq!(summary(&zip), expected);
+
+        let tar_gz = repack(&zip, RepackFormat::TarGz, &RepackOptions::default()).unwrap();
+        assert_eq!(&tar_gz[..2], b"\x1F\x8B");
+        assert_eq!(summary(&tar_gz), expected);
+
+        let zstd = RepackOptions {
+            method: Some(Method::Zstd),
+            level: Some(19),
+            ..RepackOptions::default()
+        };
+        let back = repack(&tar_gz, RepackFormat::Zip, &zstd).unwrap();
+        assert_eq!(summary(&back), expected);
+        let methods: Vec<String> = read::list_entries(&back)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.method)
+            .collect();
+        assert_eq!(methods, ["stored", "zstd", "stored", "zstd"]);
+    }
+
+    #[test]
+    fn rejects_bad_targets_and_sources() {
+        assert!(matches!(
+            RepackFormat::from_name("rar"),
+            Err(Error::InvalidOption(_))
+        ));
+        let zip = sample_zip();
+        let method = RepackOptions {
+            method: Some(Method::Xz),
+            ..RepackOptions::default()
+        };
+        assert!(matches!(
+            repack(&zip, RepackFormat::Tar, &method),
+            Err(Error::InvalidOption(_))
+        ));
+
+        let small = RepackOptions {
+            limits: Limits {
+                max_total_size: Some(1_000),
+                ..Limits::none()
+            },
+            ..RepackOptions::default()
+        };
+        assert!(matches!(
+            repack(&zip, RepackFormat::TarZst, &small),
+            Err(Error::LimitExceeded(LimitExceeded::TotalSize(1_000)))
+        ));
+        assert!(matches!(
+            repack(
+                b"just some text",
+                RepackFormat::Zip,
+                &RepackOptions::default()
+            ),
+            Err(Error::InvalidArchive(_))
+        ));
+    }
+}
@@ -0,0 +1,382 @@
+//! Read-only access to 7z archives.
+//!
+//! Entries are decoded with `sevenz-rust2` without its optional codecs, which covers
+//! what 7-Zip writes by default: LZMA and LZMA2, optionally behind the BCJ or delta
+//! filters, and stored data. Other methods, including AES encryption, fail with
+//! [`Error::UnsupportedMethod`] while the archive can still be listed.
+//!
+//! Solid archives compress many files as one block, so reading one of them decodes
+//! everything before it in the block.
+
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read, Seek};
+
+use sevenz_rust2::{Archive, ArchiveEntry, Block, BlockDecoder, EncoderMethod, Password};
+
+use crate::error::{Error, Result};
+use crate::limits::{Budget, Limits};
+use crate::path::sanitize_name;
+use crate::read::{EntryInfo, ExtractedEntry};
+use crate::time::filetime_to_unix_millis;
+
+pub const SIGNATURE: &[u8] = b"7z\xBC\xAF\x27\x1C";
+
+/// Set in the Windows attributes when the high 16 bits hold a Unix mode, as p7zip
+/// and 7-Zip on Linux write them.
+const FILE_ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+
+fn open(bytes: &[u8]) -> Result<(Archive, Cursor<&[u8]>)> {
+    let mut source = Cursor::new(bytes);
+    // A password is only needed for encrypted data, which is never decoded.
+    match Archive::read(&mut source, &Password::empty()) {
+        Ok(archive) => Ok((archive, source)),
+        Err(
+            sevenz_rust2::Error::PasswordRequired
+            | sevenz_rust2::Error::UnsupportedCompressionMethod(_),
+        ) => Err(Error::Unsupported(
+            "7z archives with encrypted headers cannot be read",
+        )),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Methods of a block's coders, compression first as 7-Zip shows them, e.g.
+/// `"lzma2+bcj_x86"`.
+fn method_name(block: &Block) -> String {
+    // Coders are stored starting with the one that produces the final output.
+    let names: Vec<String> = block
+        .coders
+        .iter()
+        .rev()
+        .map(
+            |coder| match EncoderMethod::by_id(coder.encoder_method_id()) {
+                Some(method) if method.id() == EncoderMethod::ID_COPY => "stored".to_string(),
+                Some(method) => method.name().to_lowercase(),
+                None => format!("{:02x?}", coder.encoder_method_id()),
+            },
+        )
+        .collect();
+    names.join("+")
+}
+
+fn is_encrypted(block: &Block) -> bool {
+    block
+        .coders
+        .iter()
+        .any(|coder| coder.encoder_method_id() == EncoderMethod::ID_AES256_SHA256)
+}
+
+/// Compressed size of every block, which 7z only records per block.
+fn block_sizes(archive: &Archive) -> Vec<u64> {
+    archive
+        .stream_map
+        .block_first_file_index
+        .iter()
+        .map(|&first| {
+            archive
+                .files
+                .get(first)
+                .map_or(0, |file| file.compressed_size)
+        })
+        .collect()
+}
+
+/// The entry's name as the ZIP functions report it: cleaned, and ending in `/` for
+/// directories.
+fn entry_name(file: &ArchiveEntry) -> Result<String> {
+    if file.is_directory && !file.name.ends_with('/') {
+        sanitize_name(&format!("{}/", file.name))
+    } else {
+        sanitize_name(&file.name)
+    }
+}
+
+fn last_modified(file: &ArchiveEntry) -> f64 {
+    if file.has_last_modified_date {
+        filetime_to_unix_millis(file.last_modified_date.into())
+    } else {
+        0.0
+    }
+}
+
+fn unix_mode(file: &ArchiveEntry) -> Option<u32> {
+    (file.has_windows_attributes && file.windows_attributes & FILE_ATTRIBUTE_UNIX_EXTENSION != 0)
+        .then_some(file.windows_attributes >> 16)
+}
+
+/// List every entry in a 7z archive without decompressing any data. Compressed sizes
+/// are per block: in solid archives the first file of a block carries the size of the
+/// whole block and the others report 0.
+pub fn list_entries(bytes: &[u8]) -> Result<Vec<EntryInfo>> {
+    let (archive, _) = open(bytes)?;
+    let mut entries = Vec::with_capacity(archive.files.len());
+    for (idx, file) in archive.files.iter().enumerate() {
+        if file.is_anti_item {
+            continue;
+        }
+        let block = archive.stream_map.file_block_index[idx].map(|block| &archive.blocks[block]);
+        entries.push(EntryInfo {
+            name: entry_name(file)?,
+            compressed_size: file.compressed_size,
+            uncompressed_size: file.size,
+            method: block.map_or_else(|| "stored".to_string(), method_name),
+            crc32: if file.has_crc { file.crc as u32 } else { 0 },
+            last_modified: last_modified(file),
+            is_dir: file.is_directory,
+            encrypted: block.is_some_and(is_encrypted),
+            unix_mode: unix_mode(file),
+            comment: String::new(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Decode `block`, handing each of its files with its archive index to `each` until
+/// it returns `false`. Files `each` does not read are skipped.
+fn for_each_in_block<R: Read + Seek>(
+    archive: &Archive,
+    block: usize,
+    source: &mut R,
+    mut each: impl FnMut(usize, &ArchiveEntry, &mut dyn Read) -> Result<bool>,
+) -> Result<()> {
+    let first = archive.stream_map.block_first_file_index[block];
+    let method = method_name(&archive.blocks[block]);
+    let mut idx = first;
+    // Errors of `each` cannot travel through the decoder's error type.
+    let mut failure = None;
+    let decoded = BlockDecoder::new(1, block, archive, &Password::empty(), source)
+        .for_each_entries(&mut |file, reader| {
+            let result = each(idx, file, reader).and_then(|more| {
+                if more {
+                    // The next file's data follows this one's in the same stream.
+                    io::copy(reader, &mut io::sink())?;
+                }
+                Ok(more)
+            });
+            idx += 1;
+            match result {
+                Ok(more) => Ok(more),
+                Err(err) => {
+                    failure = Some(err);
+                    Ok(false)
+                }
+            }
+        });
+    if let Some(err) = failure {
+        return Err(err);
+    }
+    match decoded {
+        Ok(_) => Ok(()),
+        Err(
+            sevenz_rust2::Error::UnsupportedCompressionMethod(_)
+            | sevenz_rust2::Error::PasswordRequired,
+        ) => Err(Error::UnsupportedMethod {
+            name: archive
+                .files
+                .get(first)
+                .map_or_else(String::new, |file| file.name.clone()),
+            method,
+        }),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Read one file's data from its block within `budget`.
+fn read_entry(
+    file: &ArchiveEntry,
+    name: &str,
+    block_size: u64,
+    reader: &mut dyn Read,
+    budget: &mut Budget,
+) -> Result<Vec<u8>> {
+    budget.check_path_depth(name)?;
+    if file.is_directory {
+        return Ok(Vec::new());
+    }
+    // Ratios are judged against the whole block, since files in it have no size of their own.
+    budget.read(reader, name, block_size, file.size)
+}
+
+/// Decompress the entry whose cleaned name matches `name`, within `limits`.
+pub fn extract_entry(bytes: &[u8], name: &str, limits: &Limits) -> Result<Vec<u8>> {
+    let wanted = sanitize_name(name)?;
+    let (archive, mut source) = open(bytes)?;
+    let mut budget = Budget::new(limits);
+    budget.check_entries(archive.files.len())?;
+
+    let mut found = None;
+    for (idx, file) in archive.files.iter().enumerate() {
+        if !file.is_anti_item && entry_name(file)? == wanted {
+            found = Some(idx);
+            break;
+        }
+    }
+    let target = found.ok_or_else(|| Error::EntryNotFound(wanted.clone()))?;
+    let Some(block) = archive.stream_map.file_block_index[target] else {
+        budget.check_path_depth(&wanted)?;
+        return Ok(Vec::new());
+    };
+
+    let block_size = block_sizes(&archive)[block];
+    let mut data = None;
+    for_each_in_block(&archive, block, &mut source, |idx, file, reader| {
+        if idx != target {
+            return Ok(true);
+        }
+        data = Some(read_entry(file, &wanted, block_size, reader, &mut budget)?);
+        Ok(false)
+    })
+    .map_err(|err| match err {
+        Error::UnsupportedMethod { method, .. } => Error::UnsupportedMethod {
+            name: wanted.clone(),
+            method,
+        },
+        err => err,
+    })?;
+    data.ok_or(Error::Internal("7z entry was not found in its block"))
+}
+
+/// Decompress every entry in archive order, within `limits`. Directories come back
+/// with empty data.
+pub fn extract_all(bytes: &[u8], limits: &Limits) -> Result<Vec<ExtractedEntry>> {
+    let (archive, mut source) = open(bytes)?;
+    let mut budget = Budget::new(limits);
+    budget.check_entries(archive.files.len())?;
+    budget.check_declared(
+        archive
+            .files
+            .iter()
+            .fold(0u64, |total, file| total.saturating_add(file.size)),
+    )?;
+
+    let sizes = block_sizes(&archive);
+    let mut data: HashMap<usize, Vec<u8>> = HashMap::new();
+    for (block, &block_size) in sizes.iter().enumerate() {
+        for_each_in_block(&archive, block, &mut source, |idx, file, reader| {
+            let name = entry_name(file)?;
+            data.insert(
+                idx,
+                read_entry(file, &name, block_size, reader, &mut budget)?,
+            );
+            Ok(true)
+        })?;
+    }
+
+    let mut entries = Vec::with_capacity(archive.files.len());
+    for (idx, file) in archive.files.iter().enumerate() {
+        if file.is_anti_item {
+            continue;
+        }
+        let name = entry_name(file)?;
+        budget.check_path_depth(&name)?;
+        entries.push(ExtractedEntry {
+            name,
+            is_dir: file.is_directory,
+            last_modified: last_modified(file),
+            unix_mode: unix_mode(file),
+            data: data.remove(&idx).unwrap_or_default(),
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use sevenz_rust2::{ArchiveWriter, EncoderMethod, SourceReader};
+
+    use super::*;
+    use crate::limits::LimitExceeded;
+
+    /// 2024-01-01T00:00:00Z.
+    const MTIME: f64 = 1_704_067_200_000.0;
+
+    fn file(name: &str) -> ArchiveEntry {
+        let mut entry = ArchiveEntry::new_file(name);
+        entry.has_last_modified_date = true;
+        entry.last_modified_date = ((MTIME as u64) * 10_000 + 116_444_736_000_000_000).into();
+        entry
+    }
+
+    /// A directory, one LZMA2 file of its own and a solid block of two more.
+    fn sample() -> Vec<u8> {
+        let mut writer = ArchiveWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.set_content_methods(vec![EncoderMethod::LZMA2.into()]);
+        writer
+            .push_archive_entry::<&[u8]>(ArchiveEntry::new_directory("docs"), None)
+            .unwrap();
+        let mut script = file("run.sh");
+        script.has_windows_attributes = true;
+        script.windows_attributes = FILE_ATTRIBUTE_UNIX_EXTENSION | (0o100755 << 16);
+        writer
+            .push_archive_entry(script, Some(&b"#!/bin/sh\n"[..]))
+            .unwrap();
+        let text = "hello 7z\n".repeat(1_000);
+        writer
+            .push_archive_entries(
+                vec![file("docs/a.txt"), file("docs/b.txt")],
+                vec![
+                    SourceReader::new(text.as_bytes()),
+                    SourceReader::new(&b"second"[..]),
+                ],
+            )
+            .unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn lists_and_extracts_7z_archives() {
+        let bytes = sample();
+        let entries = list_entries(&bytes).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["docs/", "run.sh", "docs/a.txt", "docs/b.txt"]);
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[1].method, "lzma2");
+        assert_eq!(entries[1].unix_mode, Some(0o100755));
+        assert_eq!(entries[2].last_modified, MTIME);
+        assert_eq!(entries[2].uncompressed_size, 9_000);
+        // The solid block's size is carried by its first file.
+        assert!(entries[2].compressed_size > 0);
+        assert_eq!(entries[3].compressed_size, 0);
+        assert_eq!(entries[3].crc32, crc32fast::hash(b"second"));
+
+        let limits = Limits::default();
+        assert_eq!(
+            extract_entry(&bytes, "docs/b.txt", &limits).unwrap(),
+            b"second"
+        );
+        assert!(matches!(
+            extract_entry(&bytes, "missing.txt", &limits),
+            Err(Error::EntryNotFound(_))
+        ));
+        let all = extract_all(&bytes, &limits).unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[1].data, b"#!/bin/sh\n");
+        assert_eq!(all[2].data, "hello 7z\n".repeat(1_000).as_bytes());
+        assert_eq!(all[3].unix_mode, None);
+    }
+
+    #[test]
+    fn applies_extraction_limits() {
+        let bytes = sample();
+        let few = Limits {
+            max_entries: Some(3),
+            ..Limits::none()
+        };
+        assert!(matches!(
+            extract_all(&bytes, &few),
+            Err(Error::LimitExceeded(LimitExceeded::Entries(_)))
+        ));
+        let small = Limits {
+            max_total_size: Some(100),
+            ..Limits::none()
+        };
+        assert!(matches!(
+            extract_entry(&bytes, "docs/a.txt", &small),
+            Err(Error::LimitExceeded(_))
+        ));
+        assert_eq!(
+            extract_entry(&bytes, "run.sh", &small).unwrap(),
+            b"#!/bin/sh\n"
+        );
+    }
+}
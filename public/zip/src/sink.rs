@@ -0,0 +1,172 @@
+//! Chunked output for archives streamed to a consumer.
+//!
+//! [`ArchiveWriter::streaming`](crate::write::ArchiveWriter::streaming) writes through
+//! many small calls: headers, compressor output and copied entries. [`ChunkSink`]
+//! gathers them into chunks of a fixed size, so a JS callback sees a few large
+//! buffers instead of thousands of tiny ones.
+
+use std::io::{self, Write};
+
+use crate::error::Result;
+
+/// Size of every chunk but the last.
+pub const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
+
+/// Collects written bytes and hands them to `emit` one full chunk at a time.
+pub struct ChunkSink<F: FnMut(&[u8]) -> Result<()>> {
+    emit: F,
+    buffer: Vec<u8>,
+    chunk_size: usize,
+    written: u64,
+}
+
+impl<F: FnMut(&[u8]) -> Result<()>> ChunkSink<F> {
+    pub fn new(chunk_size: usize, emit: F) -> Self {
+        Self {
+            emit,
+            buffer: Vec::with_capacity(chunk_size),
+            chunk_size: chunk_size.max(1),
+            written: 0,
+        }
+    }
+
+    /// Emit what is left as a final, shorter chunk and return the number of bytes
+    /// emitted in total.
+    pub fn finish(mut self) -> Result<u64> {
+        self.emit_buffer()?;
+        Ok(self.written)
+    }
+
+    fn emit_buffer(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            (self.emit)(&self.buffer)?;
+            self.written += self.buffer.len() as u64;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Result<()>> Write for ChunkSink<F> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let len = data.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..len]);
+        if self.buffer.len() == self.chunk_size {
+            self.emit_buffer().map_err(io::Error::other)?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::error::Error;
+    use crate::limits::Limits;
+    use crate::read::extract_all;
+    use crate::write::{ArchiveOptions, ArchiveWriter, Encryption, EntryOptions};
+
+    /// Write the same entries to `writer`, whichever way it outputs them.
+    fn add_entries<W: Write + io::Seek>(writer: &mut ArchiveWriter<W>, text: &[u8]) {
+        let options = EntryOptions::default();
+        writer.add_file("a.txt", text, &options).unwrap();
+        writer.add_directory("docs", &options).unwrap();
+        writer.start_file("docs/streamed.txt", &options).unwrap();
+        for chunk in text.chunks(10_000) {
+            writer.write_chunk(chunk).unwrap();
+        }
+        let aes = EntryOptions {
+            password: Some("secret".into()),
+            ..EntryOptions::default()
+        };
+        writer.add_file("aes.txt", b"hidden", &aes).unwrap();
+        let zipcrypto = EntryOptions {
+            encryption: Encryption::ZipCrypto,
+            comment: Some("legacy".into()),
+            ..aes
+        };
+        writer.add_file("zc.txt", b"hidden", &zipcrypto).unwrap();
+    }
+
+    #[test]
+    fn streams_archives_in_chunks() {
+        let text: Vec<u8> = (0..200_000u32)
+            .flat_map(|n| n.to_string().into_bytes())
+            .collect();
+        let mut chunks = Vec::new();
+        let sink = ChunkSink::new(64 << 10, |chunk: &[u8]| {
+            chunks.push(chunk.to_vec());
+            Ok(())
+        });
+        let mut writer = ArchiveWriter::streaming(sink, &ArchiveOptions::default()).unwrap();
+        add_entries(&mut writer, &text);
+        let written = writer.finish_stream().unwrap().finish().unwrap();
+
+        let (last, full) = chunks.split_last().unwrap();
+        assert!(full.iter().all(|chunk| chunk.len() == 64 << 10));
+        assert!(!last.is_empty() && last.len() <= 64 << 10);
+        let bytes = chunks.concat();
+        assert_eq!(bytes.len() as u64, written);
+
+        // Bit 3: sizes and CRC follow the data instead of sitting in the local header.
+        assert_ne!(bytes[6] & 0x08, 0);
+        let mut archive = ZipArchive::new(Cursor::new(bytes.as_slice())).unwrap();
+        assert_eq!(archive.len(), 5);
+        assert_eq!(archive.by_index_raw(4).unwrap().comment(), "legacy");
+        let entries = extract_all(&bytes, Some("secret"), &Limits::default()).unwrap();
+        let mut buffered = ArchiveWriter::new();
+        add_entries(&mut buffered, &text);
+        let expected = extract_all(
+            &buffered.finish().unwrap(),
+            Some("secret"),
+            &Limits::default(),
+        )
+        .unwrap();
+        let contents = |entries: &[crate::read::ExtractedEntry]| -> Vec<(String, Vec<u8>)> {
+            entries
+                .iter()
+                .map(|entry| (entry.name.clone(), entry.data.clone()))
+                .collect()
+        };
+        assert_eq!(contents(&entries), contents(&expected));
+    }
+
+    #[test]
+    fn rejects_what_a_stream_cannot_patch() {
+        let sink = || ChunkSink::new(DEFAULT_CHUNK_SIZE, |_: &[u8]| Ok(()));
+        let deterministic = ArchiveOptions {
+            deterministic: true,
+            ..ArchiveOptions::default()
+        };
+        assert!(matches!(
+            ArchiveWriter::streaming(sink(), &deterministic),
+            Err(Error::InvalidOption(_))
+        ));
+
+        let mut writer = ArchiveWriter::streaming(sink(), &ArchiveOptions::default()).unwrap();
+        let commented = EntryOptions {
+            comment: Some("café".into()),
+            ..EntryOptions::default()
+        };
+        assert!(matches!(
+            writer.add_file("a.txt", b"a", &commented),
+            Err(Error::InvalidOption(_))
+        ));
+
+        // Errors from the consumer stop the archive.
+        let failing = ChunkSink::new(16, |_: &[u8]| Err(Error::Aborted("disk full".into())));
+        let mut writer = ArchiveWriter::streaming(failing, &ArchiveOptions::default()).unwrap();
+        let err = writer
+            .add_file("a.txt", &[b'a'; 100], &EntryOptions::default())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "disk full");
+    }
+}
@@ -0,0 +1,28 @@
+/// Magic-byte signatures of formats that are already compressed and will not shrink further.
+const PRECOMPRESSED_SIGNATURES: &[(usize, &[u8])] = &[
+    // (offset, signature bytes)
+    (0, b"\xFF\xD8\xFF"),       // JPEG
+    (0, b"\x89PNG\r\n\x1A\n"),  // PNG
+    (0, b"GIF8"),               // GIF
+    (8, b"WEBP"),               // WebP (RIFF container)
+    (4, b"ftyp"),               // MP4 / MOV / HEIC / AVIF
+    (0, b"\x1A\x45\xDF\xA3"),   // WebM / MKV
+    (0, b"OggS"),               // Ogg
+    (0, b"ID3"),                // MP3 with ID3 tag
+    (0, b"fLaC"),               // FLAC
+    (0, b"PK\x03\x04"),         // ZIP, DOCX, JAR, APK
+    (0, b"\x1F\x8B"),           // gzip
+    (0, b"\x28\xB5\x2F\xFD"),   // zstd
+    (0, b"\xFD7zXZ\x00"),       // xz
+    (0, b"BZh"),                // bzip2
+    (0, b"7z\xBC\xAF\x27\x1C"), // 7z
+    (0, b"Rar!\x1A\x07"),       // RAR
+    (0, b"wOF2"),               // WOFF2
+];
+
+/// Returns true when `data` starts with the signature of an already-compressed format.
+pub fn is_precompressed(data: &[u8]) -> bool {
+    PRECOMPRESSED_SIGNATURES
+        .iter()
+        .any(|(offset, signature)| data.get(*offset..offset + signature.len()) == Some(*signature))
+}
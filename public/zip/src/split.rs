@@ -0,0 +1,527 @@
+//! Split (multi-volume) archives.
+//!
+//! A split set is one archive cut into volumes of at most a fixed size, named
+//! `.z01`, `.z02`, ... with the last one ending in `.zip`. This follows the layout
+//! Info-ZIP `zip -s` and 7-Zip write: the first volume starts with the spanning
+//! signature, entry data may continue on the next volume, and headers never do.
+//! Every central directory record stores the volume holding its local header and the
+//! offset within that volume.
+
+use std::io::Cursor;
+
+use serde::Deserialize;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+use crate::error::{Error, Result};
+use crate::staged::{
+    entry_records, put_end_of_central_directory, put_end_records, read_u16, read_u32, read_u64,
+    relocate_central_header, EndRecords, CENTRAL_HEADER_LEN, CENTRAL_HEADER_SIGNATURE,
+    END_OF_CENTRAL_DIRECTORY_LEN, END_OF_CENTRAL_DIRECTORY_SIGNATURE,
+    ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE, ZIP64_EXTRA_ID, ZIP64_LOCATOR_LEN,
+    ZIP64_LOCATOR_SIGNATURE,
+};
+
+const LOCAL_HEADER_LEN: usize = 30;
+/// Written at the start of the first volume of a split set.
+const SPANNING_SIGNATURE: u32 = 0x0807_4b50;
+/// Written by some tools instead when an archive meant to be split fit in one volume.
+const SPANNING_MARKER: u32 = 0x3030_4b50;
+/// Smallest volume size accepted, matching Info-ZIP's `zip -s` minimum.
+pub const MIN_VOLUME_SIZE: u64 = 64 * 1024;
+/// Disk numbers are 16-bit in the central directory records.
+const MAX_VOLUMES: usize = u16::MAX as usize - 1;
+
+/// Options for `split_zip`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SplitOptions {
+    /// Largest size of a volume in bytes, at least 65,536.
+    pub volume_size: u64,
+    /// File name of the last volume; the others are named after it. Defaults to
+    /// `archive.zip`.
+    pub name: Option<String>,
+}
+
+impl SplitOptions {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.volume_size < MIN_VOLUME_SIZE {
+            return Err(Error::InvalidOption(
+                "volume size must be at least 65,536 bytes",
+            ));
+        }
+        Ok(())
+    }
+
+    /// File names of the volumes of a `count`-volume set.
+    pub fn volume_names(&self, count: usize) -> Vec<String> {
+        let name = self.name.as_deref().unwrap_or("archive.zip");
+        let stem = match name.len().checked_sub(4) {
+            Some(at) if name.is_char_boundary(at) && name[at..].eq_ignore_ascii_case(".zip") => {
+                &name[..at]
+            }
+            _ => name,
+        };
+        (1..count)
+            .map(|volume| format!("{stem}.z{volume:02}"))
+            .chain(std::iter::once(format!("{stem}.zip")))
+            .collect()
+    }
+}
+
+/// Fills volumes up to the size cap.
+struct Volumes {
+    done: Vec<Vec<u8>>,
+    current: Vec<u8>,
+    cap: usize,
+}
+
+impl Volumes {
+    fn disk(&self) -> u16 {
+        self.done.len() as u16
+    }
+
+    fn next_volume(&mut self) -> Result<()> {
+        if self.done.len() + 1 >= MAX_VOLUMES {
+            return Err(Error::InvalidOption(
+                "volume size is too small; the archive would need more than 65,534 volumes",
+            ));
+        }
+        self.done.push(std::mem::take(&mut self.current));
+        Ok(())
+    }
+
+    /// Write a record that must not be split, starting a new volume when it does not
+    /// fit in this one. Returns the disk and offset it was written at.
+    fn place(&mut self, record: &[u8]) -> Result<(u16, u64)> {
+        if record.len() > self.cap {
+            return Err(Error::InvalidOption(
+                "volume size is too small to hold the archive headers",
+            ));
+        }
+        if self.current.len() + record.len() > self.cap {
+            self.next_volume()?;
+        }
+        let at = (self.disk(), self.current.len() as u64);
+        self.current.extend_from_slice(record);
+        Ok(at)
+    }
+
+    /// Write data that may continue on the following volumes.
+    fn stream(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            if self.current.len() == self.cap {
+                self.next_volume()?;
+            }
+            let take = data.len().min(self.cap - self.current.len());
+            self.current.extend_from_slice(&data[..take]);
+            data = &data[take..];
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Vec<Vec<u8>> {
+        self.done.push(self.current);
+        self.done
+    }
+}
+
+/// One file of a split set.
+#[derive(Debug, Clone)]
+pub struct Volume {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Split a ZIP archive into volumes of at most `options.volume_size` bytes. An archive
+/// that already fits is returned unchanged as the only volume.
+pub fn split_archive(bytes: &[u8], options: &SplitOptions) -> Result<Vec<Volume>> {
+    options.validate()?;
+    let volumes = split_volumes(bytes, options.volume_size)?;
+    Ok(options
+        .volume_names(volumes.len())
+        .into_iter()
+        .zip(volumes)
+        .map(|(name, data)| Volume { name, data })
+        .collect())
+}
+
+fn split_volumes(bytes: &[u8], volume_size: u64) -> Result<Vec<Vec<u8>>> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    if bytes.len() as u64 <= volume_size {
+        return Ok(vec![bytes.to_vec()]);
+    }
+
+    let records = entry_records(&mut archive, bytes)?;
+    let comment = archive.comment().to_vec();
+    let mut volumes = Volumes {
+        done: Vec::new(),
+        current: SPANNING_SIGNATURE.to_le_bytes().to_vec(),
+        cap: usize::try_from(volume_size).unwrap_or(usize::MAX),
+    };
+
+    // Local records keep their order, and so do the central directory records.
+    let mut order: Vec<usize> = (0..records.len()).collect();
+    order.sort_by_key(|&idx| records[idx].local.start);
+    let mut placed = vec![(0, 0); records.len()];
+    for idx in order {
+        let local = &bytes[records[idx].local.clone()];
+        let header_len = [26, 28]
+            .into_iter()
+            .map(|field| read_u16(local, field).map(usize::from))
+            .sum::<Result<usize>>()?
+            + LOCAL_HEADER_LEN;
+        let header = local
+            .get(..header_len)
+            .ok_or_else(|| invalid("local header runs past the entry data"))?;
+        placed[idx] = volumes.place(header)?;
+        volumes.stream(&local[header_len..])?;
+    }
+
+    let mut order: Vec<usize> = (0..records.len()).collect();
+    order.sort_by_key(|&idx| records[idx].central.start);
+    let mut central = None;
+    let mut central_size = 0;
+    let mut central_disks = Vec::with_capacity(records.len());
+    for idx in order {
+        let (disk, offset) = placed[idx];
+        let record =
+            relocate_central_header(&bytes[records[idx].central.clone()], None, disk, offset)?;
+        let at = volumes.place(&record)?;
+        central.get_or_insert(at);
+        central_size += record.len() as u64;
+        central_disks.push(at.0);
+    }
+
+    let (central_disk, central_offset) = central.unwrap_or((volumes.disk(), 0));
+    let mut end = EndRecords {
+        disk: 0,
+        central_disk: central_disk.into(),
+        central_offset,
+        central_size,
+        entries: central_disks.len() as u64,
+        entries_on_disk: 0,
+        comment: &comment,
+    };
+    if volumes.current.len() + end.len() > volumes.cap {
+        volumes.next_volume()?;
+    }
+    end.disk = volumes.disk().into();
+    end.entries_on_disk = central_disks
+        .iter()
+        .filter(|&&disk| disk == volumes.disk())
+        .count() as u64;
+    put_end_records(&mut volumes.current, &end);
+
+    Ok(volumes.finish())
+}
+
+fn invalid(message: &'static str) -> Error {
+    ZipError::InvalidArchive(message.into()).into()
+}
+
+/// Reassemble the volumes of a split set, given in order, into a single archive that
+/// any reader can open. Local records are copied as they are and the central directory
+/// is rewritten with offsets into the joined archive.
+pub fn join_volumes(volumes: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let end = EndOfCentralDirectory::find(volumes)?;
+    if end.disk as usize + 1 != volumes.len() {
+        return Err(Error::InvalidOption(
+            "a split archive needs every volume, in order",
+        ));
+    }
+
+    // Offsets on the first disk count the spanning signature, which is dropped here.
+    let skip = match read_u32(&volumes[0], 0) {
+        Ok(SPANNING_SIGNATURE | SPANNING_MARKER) => 4,
+        _ => 0,
+    };
+    let mut starts = Vec::with_capacity(volumes.len());
+    let mut joined = Vec::with_capacity(volumes.iter().map(Vec::len).sum());
+    let mut start = 0;
+    for (disk, volume) in volumes.iter().enumerate() {
+        starts.push(start);
+        start += volume.len() as u64;
+        joined.extend_from_slice(&volume[if disk == 0 { skip } else { 0 }..]);
+    }
+    let total = joined.len();
+    let absolute = |disk: u64, offset: u64| {
+        usize::try_from(disk)
+            .ok()
+            .and_then(|disk| starts.get(disk))
+            .and_then(|start| (start + offset).checked_sub(skip as u64))
+            .and_then(|at| usize::try_from(at).ok())
+            .filter(|&at| at < total)
+            .ok_or_else(|| invalid("split archive offset points outside its volumes"))
+    };
+
+    let central = absolute(end.central_disk.into(), end.central_offset)?;
+    let mut at = central;
+    // The count comes from the archive, so it only sizes the buffer once it is known
+    // to fit in the volumes.
+    if end.entries > (total / CENTRAL_HEADER_LEN) as u64 {
+        return Err(invalid("split archive declares more entries than it holds"));
+    }
+    let mut records = Vec::with_capacity(end.entries as usize);
+    for _ in 0..end.entries {
+        if read_u32(&joined, at).ok() != Some(CENTRAL_HEADER_SIGNATURE) {
+            return Err(invalid("split archive central directory is damaged"));
+        }
+        let variable = [28, 30, 32]
+            .into_iter()
+            .map(|field| read_u16(&joined, at + field).map(usize::from))
+            .sum::<Result<usize>>()?;
+        let record = joined
+            .get(at..at + CENTRAL_HEADER_LEN + variable)
+            .ok_or_else(|| invalid("split archive central directory is damaged"))?;
+        let (disk, offset) = local_header_location(record)?;
+        records.push(relocate_central_header(
+            record,
+            None,
+            0,
+            absolute(disk, offset)? as u64,
+        )?);
+        at += record.len();
+    }
+
+    joined.truncate(central);
+    for record in &records {
+        joined.extend_from_slice(record);
+    }
+    put_end_of_central_directory(&mut joined, central, records.len(), &end.comment);
+    Ok(joined)
+}
+
+/// The disk and offset of the local header a central directory record points to,
+/// taken from its Zip64 field when the 32-bit values are saturated.
+fn local_header_location(record: &[u8]) -> Result<(u64, u64)> {
+    let mut disk = u64::from(read_u16(record, 34)?);
+    let mut offset = u64::from(read_u32(record, 42)?);
+    if disk != u64::from(u16::MAX) && offset != u64::from(u32::MAX) {
+        return Ok((disk, offset));
+    }
+
+    let name_len = usize::from(read_u16(record, 28)?);
+    let extra_len = usize::from(read_u16(record, 30)?);
+    let extra_start = CENTRAL_HEADER_LEN + name_len;
+    let extra = record
+        .get(extra_start..extra_start + extra_len)
+        .unwrap_or_default();
+    let zip64 = crate::staged::extra_fields(extra)
+        .find(|&(id, _)| id == ZIP64_EXTRA_ID)
+        .map(|(_, data)| data)
+        .ok_or_else(|| invalid("central directory record is missing its Zip64 field"))?;
+    let mut at = [24, 20]
+        .into_iter()
+        .filter(|&field| read_u32(record, field).ok() == Some(u32::MAX))
+        .count()
+        * 8;
+    if offset == u64::from(u32::MAX) {
+        offset = read_u64(zip64, at)?;
+        at += 8;
+    }
+    if disk == u64::from(u16::MAX) {
+        disk = read_u32(zip64, at)?.into();
+    }
+    Ok((disk, offset))
+}
+
+/// The end-of-central-directory values of the last volume, from the Zip64 record
+/// when there is one.
+struct EndOfCentralDirectory {
+    disk: u32,
+    central_disk: u32,
+    central_offset: u64,
+    entries: u64,
+    comment: Vec<u8>,
+}
+
+impl EndOfCentralDirectory {
+    fn find(volumes: &[Vec<u8>]) -> Result<Self> {
+        let volume = volumes
+            .last()
+            .ok_or(Error::InvalidOption("no volumes provided"))?;
+        let lowest = volume
+            .len()
+            .saturating_sub(END_OF_CENTRAL_DIRECTORY_LEN + usize::from(u16::MAX));
+        let at = (lowest..=volume.len().saturating_sub(END_OF_CENTRAL_DIRECTORY_LEN))
+            .rev()
+            .find(|&at| {
+                read_u32(volume, at).ok() == Some(END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+                    && read_u16(volume, at + 20).map(usize::from).ok()
+                        == Some(volume.len() - at - END_OF_CENTRAL_DIRECTORY_LEN)
+            })
+            .ok_or_else(|| invalid("the last volume has no end of central directory"))?;
+        let comment = volume[at + END_OF_CENTRAL_DIRECTORY_LEN..].to_vec();
+
+        let locator = at
+            .checked_sub(ZIP64_LOCATOR_LEN)
+            .filter(|&locator| read_u32(volume, locator).ok() == Some(ZIP64_LOCATOR_SIGNATURE));
+        let Some(locator) = locator else {
+            return Ok(EndOfCentralDirectory {
+                disk: read_u16(volume, at + 4)?.into(),
+                central_disk: read_u16(volume, at + 6)?.into(),
+                entries: read_u16(volume, at + 10)?.into(),
+                central_offset: read_u32(volume, at + 16)?.into(),
+                comment,
+            });
+        };
+
+        let record_disk = read_u32(volume, locator + 4)? as usize;
+        let record_offset = read_u64(volume, locator + 8)?;
+        let record = volumes
+            .get(record_disk)
+            .zip(usize::try_from(record_offset).ok())
+            .and_then(|(volume, offset)| volume.get(offset..))
+            .filter(|record| {
+                read_u32(record, 0).ok() == Some(ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+            })
+            .ok_or_else(|| invalid("the Zip64 end of central directory is missing"))?;
+        Ok(EndOfCentralDirectory {
+            disk: read_u32(record, 16)?,
+            central_disk: read_u32(record, 20)?,
+            entries: read_u64(record, 32)?,
+            central_offset: read_u64(record, 48)?,
+            comment,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limits;
+    use crate::read;
+    use crate::staged::LOCAL_HEADER_SIGNATURE;
+    use crate::verify::verify_archive;
+    use crate::write::{ArchiveOptions, ArchiveWriter, EntryOptions, Method};
+
+    /// Incompressible bytes, so entries are larger than a volume.
+    fn noise(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect()
+    }
+
+    fn sample_archive() -> (Vec<u8>, Vec<(String, Vec<u8>)>) {
+        let files = vec![
+            ("small.txt".to_string(), b"tiny".to_vec()),
+            ("photo.raw".to_string(), noise(150_000, 1)),
+            ("naïve/notes.txt".to_string(), b"notes ".repeat(5_000)),
+            ("video.raw".to_string(), noise(90_000, 2)),
+        ];
+        let mut writer = ArchiveWriter::with_options(&ArchiveOptions {
+            comment: Some("split me".into()),
+            ..ArchiveOptions::default()
+        })
+        .unwrap();
+        let stored = EntryOptions {
+            method: Method::Stored,
+            ..EntryOptions::default()
+        };
+        for (name, data) in &files {
+            writer.add_file(name, data, &stored).unwrap();
+        }
+        (writer.finish().unwrap(), files)
+    }
+
+    fn options(volume_size: u64) -> SplitOptions {
+        SplitOptions {
+            volume_size,
+            name: Some("backup.ZIP".into()),
+        }
+    }
+
+    #[test]
+    fn splits_into_volumes_and_joins_them_back() {
+        let (bytes, files) = sample_archive();
+        let volumes = split_archive(&bytes, &options(MIN_VOLUME_SIZE)).unwrap();
+
+        let names: Vec<_> = volumes.iter().map(|volume| volume.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "backup.z01",
+                "backup.z02",
+                "backup.z03",
+                "backup.z04",
+                "backup.zip"
+            ]
+        );
+        assert_eq!(read_u32(&volumes[0].data, 0).unwrap(), SPANNING_SIGNATURE);
+        assert!(volumes
+            .iter()
+            .all(|volume| volume.data.len() as u64 <= MIN_VOLUME_SIZE));
+
+        // Every central record points at a whole local header on its own volume.
+        let data: Vec<_> = volumes.into_iter().map(|volume| volume.data).collect();
+        let end = EndOfCentralDirectory::find(&data).unwrap();
+        assert_eq!(end.disk, 4);
+        assert_eq!(end.entries, 4);
+        let mut at = end.central_offset as usize;
+        let mut disk = end.central_disk as usize;
+        for _ in 0..end.entries {
+            if at == data[disk].len() {
+                (disk, at) = (disk + 1, 0);
+            }
+            let record = &data[disk][at..];
+            assert_eq!(read_u32(record, 0).unwrap(), CENTRAL_HEADER_SIGNATURE);
+            let (local_disk, offset) = local_header_location(record).unwrap();
+            let local = &data[local_disk as usize][offset as usize..];
+            assert_eq!(read_u32(local, 0).unwrap(), LOCAL_HEADER_SIGNATURE);
+            let header_len = LOCAL_HEADER_LEN
+                + usize::from(read_u16(local, 26).unwrap())
+                + usize::from(read_u16(local, 28).unwrap());
+            assert!(header_len <= local.len());
+            at += [28, 30, 32]
+                .into_iter()
+                .map(|field| usize::from(read_u16(record, field).unwrap()))
+                .sum::<usize>()
+                + CENTRAL_HEADER_LEN;
+        }
+
+        let joined = join_volumes(&data).unwrap();
+        assert!(verify_archive(&joined, None).unwrap().ok);
+        assert_eq!(read::archive_comment(&joined).unwrap(), "split me");
+        let extracted = read::extract_all(&joined, None, &Limits::default()).unwrap();
+        assert_eq!(extracted.len(), files.len());
+        for (entry, (name, data)) in extracted.iter().zip(&files) {
+            assert_eq!(&entry.name, name);
+            assert_eq!(&entry.data, data);
+        }
+    }
+
+    #[test]
+    fn keeps_small_archives_in_one_volume() {
+        let (bytes, _) = sample_archive();
+        let volumes = split_archive(&bytes, &options(1 << 20)).unwrap();
+        assert_eq!(volumes.len(), 1);
+        assert_eq!(volumes[0].name, "backup.zip");
+        assert_eq!(volumes[0].data, bytes);
+        let joined = join_volumes(&[bytes]).unwrap();
+        assert!(verify_archive(&joined, None).unwrap().ok);
+    }
+
+    #[test]
+    fn rejects_bad_volume_sizes_and_incomplete_sets() {
+        let (bytes, _) = sample_archive();
+        assert!(matches!(
+            split_archive(&bytes, &options(1_000)),
+            Err(Error::InvalidOption(_))
+        ));
+
+        let mut data: Vec<_> = split_archive(&bytes, &options(MIN_VOLUME_SIZE))
+            .unwrap()
+            .into_iter()
+            .map(|volume| volume.data)
+            .collect();
+        data.remove(1);
+        assert!(matches!(join_volumes(&data), Err(Error::InvalidOption(_))));
+        assert!(join_volumes(&[]).is_err());
+    }
+}
@@ -0,0 +1,433 @@
+//! Byte-level access to one-entry "staging" archives.
+//!
+//! Some entry features (ZipCrypto, per-entry comments) have no `ZipWriter` option.
+//! Such entries are written alone into a scratch archive, patched here, and then
+//! merged into the output with `ZipWriter::merge_archive`, which keeps the patched
+//! headers intact apart from the general-purpose flags.
+//!
+//! The raw record helpers here are also used to verify and edit existing archives.
+
+use std::io::Cursor;
+use std::ops::Range;
+
+use zip::ZipArchive;
+
+use crate::error::{Error, Result};
+
+pub(crate) const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+pub(crate) const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+pub(crate) const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+pub(crate) const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+pub(crate) const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0606_4b50;
+pub(crate) const CENTRAL_HEADER_LEN: usize = 46;
+pub(crate) const END_OF_CENTRAL_DIRECTORY_LEN: usize = 22;
+pub(crate) const ZIP64_LOCATOR_LEN: usize = 20;
+pub(crate) const ZIP64_EXTRA_ID: u16 = 0x0001;
+pub(crate) const FLAG_UTF8: u16 = 0x0800;
+
+pub(crate) const STAGING_LAYOUT: Error = Error::Internal("unexpected staging archive layout");
+
+pub(crate) fn read_u16(bytes: &[u8], at: usize) -> Result<u16> {
+    bytes
+        .get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or(STAGING_LAYOUT)
+}
+
+pub(crate) fn read_u32(bytes: &[u8], at: usize) -> Result<u32> {
+    bytes
+        .get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(STAGING_LAYOUT)
+}
+
+pub(crate) fn read_u64(bytes: &[u8], at: usize) -> Result<u64> {
+    bytes
+        .get(at..at + 8)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap_or_default()))
+        .ok_or(STAGING_LAYOUT)
+}
+
+pub(crate) fn write_u16(bytes: &mut [u8], at: usize, value: u16) {
+    bytes[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_u32(bytes: &mut [u8], at: usize, value: u32) {
+    bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_u64(bytes: &mut [u8], at: usize, value: u64) {
+    bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// `(id, data)` pairs of an extra field block, stopping at the first malformed one.
+pub(crate) fn extra_fields(extra: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    let mut rest = extra;
+    std::iter::from_fn(move || {
+        let id = read_u16(rest, 0).ok()?;
+        let len = usize::from(read_u16(rest, 2).ok()?);
+        let data = rest.get(4..4 + len)?;
+        rest = &rest[4 + len..];
+        Some((id, data))
+    })
+}
+
+/// The 32-bit header value for `value`, moving `value` into the Zip64 field data when it
+/// does not fit.
+pub(crate) fn saturate(value: u64, zip64: &mut Vec<u8>) -> u32 {
+    match u32::try_from(value) {
+        Ok(value) if value != u32::MAX => value,
+        _ => {
+            zip64.extend_from_slice(&value.to_le_bytes());
+            u32::MAX
+        }
+    }
+}
+
+/// Append a Zip64 field holding `zip64`, unless it is empty, followed by the fields of
+/// `extra` other than its own Zip64 field.
+pub(crate) fn put_extra_fields(out: &mut Vec<u8>, zip64: &[u8], extra: &[u8]) {
+    if !zip64.is_empty() {
+        put_u16(out, ZIP64_EXTRA_ID);
+        put_u16(out, zip64.len() as u16);
+        out.extend_from_slice(zip64);
+    }
+    for (id, data) in extra_fields(extra).filter(|&(id, _)| id != ZIP64_EXTRA_ID) {
+        put_u16(out, id);
+        put_u16(out, data.len() as u16);
+        out.extend_from_slice(data);
+    }
+}
+
+/// Fields of the end-of-central-directory records.
+pub(crate) struct EndRecords<'a> {
+    /// Number of the disk holding these records, which is the last one.
+    pub disk: u32,
+    /// Disk on which the central directory starts, and its offset within that disk.
+    pub central_disk: u32,
+    pub central_offset: u64,
+    pub central_size: u64,
+    pub entries: u64,
+    pub entries_on_disk: u64,
+    pub comment: &'a [u8],
+}
+
+impl EndRecords<'_> {
+    /// Whether a value does not fit the classic record, so Zip64 records are needed.
+    fn large(&self) -> bool {
+        self.entries >= u64::from(u16::MAX)
+            || self.disk >= u32::from(u16::MAX)
+            || self.central_disk >= u32::from(u16::MAX)
+            || self.central_size >= u64::from(u32::MAX)
+            || self.central_offset >= u64::from(u32::MAX)
+    }
+
+    /// Total size of the records once written.
+    pub(crate) fn len(&self) -> usize {
+        let zip64 = if self.large() {
+            56 + ZIP64_LOCATOR_LEN
+        } else {
+            0
+        };
+        zip64 + END_OF_CENTRAL_DIRECTORY_LEN + self.comment.len()
+    }
+}
+
+/// Append the end-of-central-directory record, preceded by the Zip64 record and locator
+/// when a value does not fit. `out` is the disk the records are written to.
+pub(crate) fn put_end_records(out: &mut Vec<u8>, end: &EndRecords<'_>) {
+    let large = end.large();
+    if large {
+        let record = out.len() as u64;
+        put_u32(out, ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        put_u64(out, 44);
+        put_u16(out, 45);
+        put_u16(out, 45);
+        put_u32(out, end.disk);
+        put_u32(out, end.central_disk);
+        put_u64(out, end.entries_on_disk);
+        put_u64(out, end.entries);
+        put_u64(out, end.central_size);
+        put_u64(out, end.central_offset);
+
+        put_u32(out, ZIP64_LOCATOR_SIGNATURE);
+        put_u32(out, end.disk);
+        put_u64(out, record);
+        put_u32(out, end.disk + 1);
+    }
+
+    let short = |value: u64, max: u64| if large { max } else { value };
+    put_u32(out, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+    put_u16(out, short(end.disk.into(), u16::MAX.into()) as u16);
+    put_u16(out, short(end.central_disk.into(), u16::MAX.into()) as u16);
+    put_u16(out, short(end.entries_on_disk, u16::MAX.into()) as u16);
+    put_u16(out, short(end.entries, u16::MAX.into()) as u16);
+    put_u32(out, short(end.central_size, u32::MAX.into()) as u32);
+    put_u32(out, short(end.central_offset, u32::MAX.into()) as u32);
+    put_u16(out, end.comment.len() as u16);
+    out.extend_from_slice(end.comment);
+}
+
+/// Append the end records of a single-disk archive for `count` entries whose central
+/// directory starts at `central` and runs to the end of `out`.
+pub(crate) fn put_end_of_central_directory(
+    out: &mut Vec<u8>,
+    central: usize,
+    count: usize,
+    comment: &[u8],
+) {
+    let end = EndRecords {
+        disk: 0,
+        central_disk: 0,
+        central_offset: central as u64,
+        central_size: (out.len() - central) as u64,
+        entries: count as u64,
+        entries_on_disk: count as u64,
+        comment,
+    };
+    put_end_records(out, &end);
+}
+
+/// Where one entry's records sit in an archive.
+pub(crate) struct EntryRecords {
+    pub name: String,
+    /// Local header, data and any data descriptor.
+    pub local: Range<usize>,
+    /// Central directory record.
+    pub central: Range<usize>,
+}
+
+/// Locate the records of every entry. Each entry's local bytes run to the next local
+/// header, or to the central directory for the last one, so data descriptors are kept.
+pub(crate) fn entry_records(
+    archive: &mut ZipArchive<Cursor<&[u8]>>,
+    bytes: &[u8],
+) -> Result<Vec<EntryRecords>> {
+    let mut located = Vec::with_capacity(archive.len());
+    for idx in 0..archive.len() {
+        let file = archive.by_index_raw(idx)?;
+        let central = file.central_header_start() as usize;
+        let variable = [28, 30, 32]
+            .into_iter()
+            .map(|field| read_u16(bytes, central + field).map(usize::from))
+            .sum::<Result<usize>>()?;
+        located.push((
+            file.name().to_string(),
+            file.header_start() as usize,
+            central..central + CENTRAL_HEADER_LEN + variable,
+        ));
+    }
+
+    let mut starts: Vec<usize> = located.iter().map(|(_, start, _)| *start).collect();
+    starts.sort_unstable();
+    let central_start = archive.central_directory_start() as usize;
+    Ok(located
+        .into_iter()
+        .map(|(name, start, central)| {
+            let next = starts.partition_point(|&other| other <= start);
+            let end = starts.get(next).copied().unwrap_or(central_start);
+            EntryRecords {
+                name,
+                local: start..end.max(start),
+                central,
+            }
+        })
+        .collect())
+}
+
+/// Copy a central directory record for an entry whose local header now sits at
+/// `offset` on `disk`, writing `name` into it when set. The offset moves into the
+/// Zip64 field when it no longer fits in 32 bits.
+pub(crate) fn relocate_central_header(
+    record: &[u8],
+    name: Option<&[u8]>,
+    disk: u16,
+    offset: u64,
+) -> Result<Vec<u8>> {
+    let name_len = usize::from(read_u16(record, 28)?);
+    let extra_len = usize::from(read_u16(record, 30)?);
+    let extra_start = CENTRAL_HEADER_LEN + name_len;
+    let old_name = record
+        .get(CENTRAL_HEADER_LEN..extra_start)
+        .ok_or(STAGING_LAYOUT)?;
+    let extra = record
+        .get(extra_start..extra_start + extra_len)
+        .ok_or(STAGING_LAYOUT)?;
+    let comment = &record[extra_start + extra_len..];
+
+    // Keep the sizes from the Zip64 field and drop its old offset and disk number.
+    let saturated = [24, 20]
+        .into_iter()
+        .filter(|&at| read_u32(record, at).ok() == Some(u32::MAX))
+        .count();
+    let mut zip64 = match extra_fields(extra).find(|&(id, _)| id == ZIP64_EXTRA_ID) {
+        Some((_, data)) => data.get(..saturated * 8).ok_or(STAGING_LAYOUT)?.to_vec(),
+        None if saturated == 0 => Vec::new(),
+        None => return Err(STAGING_LAYOUT),
+    };
+    let offset = saturate(offset, &mut zip64);
+
+    let name = name.unwrap_or(old_name);
+    let mut out = record[..CENTRAL_HEADER_LEN].to_vec();
+    out.extend_from_slice(name);
+    put_extra_fields(&mut out, &zip64, extra);
+    let new_extra_len = out.len() - CENTRAL_HEADER_LEN - name.len();
+    out.extend_from_slice(comment);
+
+    write_u16(&mut out, 28, name.len() as u16);
+    write_u16(&mut out, 30, new_extra_len as u16);
+    write_u16(&mut out, 34, disk);
+    write_u32(&mut out, 42, offset);
+    if !name.is_ascii() {
+        let flags = read_u16(&out, 8)?;
+        write_u16(&mut out, 8, flags | FLAG_UTF8);
+    }
+    Ok(out)
+}
+
+/// Offsets of the records in a one-entry archive without an archive comment. The
+/// local header always starts at offset 0.
+pub(crate) struct Layout {
+    pub data_start: usize,
+    pub central: usize,
+    /// Offset of the Zip64 end-of-central-directory locator, when present.
+    pub zip64_locator: Option<usize>,
+    pub eocd: usize,
+}
+
+impl Layout {
+    pub(crate) fn parse(staged: &[u8]) -> Result<Layout> {
+        let eocd = staged
+            .len()
+            .checked_sub(END_OF_CENTRAL_DIRECTORY_LEN)
+            .ok_or(STAGING_LAYOUT)?;
+        if read_u32(staged, 0)? != LOCAL_HEADER_SIGNATURE
+            || read_u32(staged, eocd)? != END_OF_CENTRAL_DIRECTORY_SIGNATURE
+        {
+            return Err(STAGING_LAYOUT);
+        }
+
+        let zip64_locator = eocd
+            .checked_sub(ZIP64_LOCATOR_LEN)
+            .filter(|&at| read_u32(staged, at).ok() == Some(ZIP64_LOCATOR_SIGNATURE));
+        let central = match zip64_locator {
+            Some(locator) => {
+                let record = read_u64(staged, locator + 8)? as usize;
+                if read_u64(staged, record + 32)? != 1 {
+                    return Err(STAGING_LAYOUT);
+                }
+                read_u64(staged, record + 48)? as usize
+            }
+            None => {
+                if read_u16(staged, eocd + 10)? != 1 {
+                    return Err(STAGING_LAYOUT);
+                }
+                read_u32(staged, eocd + 16)? as usize
+            }
+        };
+        if read_u32(staged, central)? != CENTRAL_HEADER_SIGNATURE {
+            return Err(STAGING_LAYOUT);
+        }
+
+        let data_start = 30 + read_u16(staged, 26)? as usize + read_u16(staged, 28)? as usize;
+        Ok(Layout {
+            data_start,
+            central,
+            zip64_locator,
+            eocd,
+        })
+    }
+
+    /// End of the single central directory record.
+    pub(crate) fn central_end(&self, staged: &[u8]) -> Result<usize> {
+        let variable = read_u16(staged, self.central + 28)? as usize
+            + read_u16(staged, self.central + 30)? as usize
+            + read_u16(staged, self.central + 32)? as usize;
+        Ok(self.central + 46 + variable)
+    }
+}
+
+/// Set the per-entry comment of a staged entry.
+///
+/// Merging drops the UTF-8 flag again when the entry name is ASCII, so non-ASCII
+/// comments also need [`flag_utf8_comment`] on the finished archive.
+pub fn set_comment(staged: Vec<u8>, comment: &str) -> Result<Vec<u8>> {
+    let comment_len = u16::try_from(comment.len())
+        .map_err(|_| Error::InvalidOption("entry comments are limited to 65,535 bytes"))?;
+    let layout = Layout::parse(&staged)?;
+    let old_len = read_u16(&staged, layout.central + 32)? as usize;
+    let comment_start = layout.central_end(&staged)? - old_len;
+    let growth = comment.len() as i64 - old_len as i64;
+
+    let mut out = Vec::with_capacity(staged.len() + comment.len());
+    out.extend_from_slice(&staged[..comment_start]);
+    out.extend_from_slice(comment.as_bytes());
+    out.extend_from_slice(&staged[comment_start + old_len..]);
+    let shift = |offset: usize| (offset as i64 + growth) as usize;
+
+    write_u16(&mut out, layout.central + 32, comment_len);
+    if !comment.is_ascii() {
+        // `merge_archive` decodes the comment as CP437 unless the flag is set. Readers
+        // such as Info-ZIP reject entries whose local header disagrees.
+        for flags_at in [6, layout.central + 8] {
+            let flags = read_u16(&out, flags_at)?;
+            write_u16(&mut out, flags_at, flags | FLAG_UTF8);
+        }
+    }
+
+    let eocd = shift(layout.eocd);
+    let central_size = read_u32(&out, eocd + 12)?;
+    if central_size != u32::MAX {
+        write_u32(
+            &mut out,
+            eocd + 12,
+            (i64::from(central_size) + growth) as u32,
+        );
+    }
+    if let Some(locator) = layout.zip64_locator.map(shift) {
+        let record = shift(read_u64(&out, locator + 8)? as usize);
+        write_u64(&mut out, locator + 8, record as u64);
+        let central_size = read_u64(&out, record + 40)?;
+        write_u64(&mut out, record + 40, (central_size as i64 + growth) as u64);
+    }
+
+    Ok(out)
+}
+
+/// Set the UTF-8 flag on the central directory header at `central` and on the local
+/// header at `local` when the entry's comment is not plain ASCII. Without it, readers
+/// decode the comment as CP437.
+pub fn flag_utf8_comment(archive: &mut [u8], local: usize, central: usize) -> Result<()> {
+    if read_u32(archive, central)? != CENTRAL_HEADER_SIGNATURE
+        || read_u32(archive, local)? != LOCAL_HEADER_SIGNATURE
+    {
+        return Err(STAGING_LAYOUT);
+    }
+    let comment_start = central
+        + 46
+        + read_u16(archive, central + 28)? as usize
+        + read_u16(archive, central + 30)? as usize;
+    let comment_len = read_u16(archive, central + 32)? as usize;
+    let comment = archive
+        .get(comment_start..comment_start + comment_len)
+        .ok_or(STAGING_LAYOUT)?;
+    if !comment.is_ascii() {
+        for flags_at in [local + 6, central + 8] {
+            let flags = read_u16(archive, flags_at)?;
+            write_u16(archive, flags_at, flags | FLAG_UTF8);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,192 @@
+//! Dry-run compression statistics.
+//!
+//! Each entry is written into its own throwaway archive by [`ArchiveWriter`], the
+//! same code path `zip_files` uses, so the method and compressed size reported here
+//! are the ones a real run produces. Only the numbers are kept.
+
+use std::io::Cursor;
+
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+
+use crate::error::{Error, Result};
+use crate::read::method_name;
+use crate::time::now_millis;
+use crate::write::{ArchiveWriter, EntryOptions};
+
+/// Options for `zip_stats`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StatsOptions {
+    /// Compress only the first `sample_size` bytes of larger files and scale the
+    /// result, trading accuracy for speed. Whole files are compressed when unset.
+    /// Must be at least 1.
+    pub sample_size: Option<u64>,
+}
+
+/// What one entry costs in the archive.
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryStats {
+    pub name: String,
+    pub original_size: u64,
+    /// Size of the entry's data in the archive, including any encryption overhead.
+    /// Estimated from the sample when `sampled` is set.
+    pub compressed_size: u64,
+    /// `compressed_size / original_size`, or 1 for empty entries.
+    pub ratio: f64,
+    pub method: String,
+    pub elapsed_ms: f64,
+    /// Whether only a sample of the data was compressed.
+    pub sampled: bool,
+}
+
+/// Per-entry statistics and their totals.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StatsReport {
+    pub entries: Vec<EntryStats>,
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub ratio: f64,
+    pub elapsed_ms: f64,
+}
+
+impl StatsReport {
+    pub fn push(&mut self, entry: EntryStats) {
+        self.original_size += entry.original_size;
+        self.compressed_size += entry.compressed_size;
+        self.ratio = ratio(self.compressed_size, self.original_size);
+        self.elapsed_ms += entry.elapsed_ms;
+        self.entries.push(entry);
+    }
+}
+
+fn ratio(compressed: u64, original: u64) -> f64 {
+    if original == 0 {
+        1.0
+    } else {
+        compressed as f64 / original as f64
+    }
+}
+
+/// Compress one entry as `ArchiveWriter` would and report its size. `data` is `None`
+/// for directories.
+pub fn measure_entry(
+    name: &str,
+    data: Option<&[u8]>,
+    options: &EntryOptions,
+    stats: &StatsOptions,
+) -> Result<EntryStats> {
+    if stats.sample_size == Some(0) {
+        return Err(Error::InvalidOption("sample_size must be at least 1 byte"));
+    }
+    let started = now_millis();
+    let mut writer = ArchiveWriter::new();
+    let original_size = data.map_or(0, |data| data.len() as u64);
+    let sample = match (data, stats.sample_size) {
+        (Some(data), Some(limit)) if original_size > limit => Some(&data[..limit as usize]),
+        _ => None,
+    };
+    match (sample, data) {
+        (Some(data), _) | (None, Some(data)) => writer.add_file(name, data, options)?,
+        (None, None) => writer.add_directory(name, options)?,
+    }
+    let bytes = writer.finish()?;
+
+    let mut archive = ZipArchive::new(Cursor::new(bytes.as_slice()))?;
+    let file = archive.by_index_raw(0)?;
+    let compressed_size = match sample {
+        Some(sample) => {
+            let scale = original_size as f64 / sample.len() as f64;
+            (file.compressed_size() as f64 * scale).round() as u64
+        }
+        _ => file.compressed_size(),
+    };
+    Ok(EntryStats {
+        name: file.name().to_string(),
+        original_size,
+        compressed_size,
+        ratio: ratio(compressed_size, original_size),
+        method: method_name(file.compression()),
+        elapsed_ms: now_millis() - started,
+        sampled: sample.is_some(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read;
+    use crate::write::{Encryption, Method};
+
+    #[test]
+    fn matches_the_real_archive() {
+        let text = b"the quick brown fox jumps over the lazy dog\n".repeat(500);
+        let png = [b"\x89PNG\r\n\x1a\n".as_slice(), &[3u8; 2_000]].concat();
+        let auto = EntryOptions {
+            method: Method::Auto,
+            ..EntryOptions::default()
+        };
+        let secret = EntryOptions {
+            password: Some("pw".into()),
+            encryption: Encryption::ZipCrypto,
+            ..EntryOptions::default()
+        };
+        let inputs: [(&str, Option<&[u8]>, &EntryOptions); 4] = [
+            ("text.txt", Some(&text), &auto),
+            ("image.png", Some(&png), &auto),
+            ("secret.txt", Some(&text), &secret),
+            ("docs", None, &auto),
+        ];
+
+        let mut writer = ArchiveWriter::new();
+        let mut report = StatsReport::default();
+        for (name, data, options) in inputs {
+            match data {
+                Some(data) => writer.add_file(name, data, options).unwrap(),
+                None => writer.add_directory(name, options).unwrap(),
+            }
+            report.push(measure_entry(name, data, options, &StatsOptions::default()).unwrap());
+        }
+        let real = read::list_entries(&writer.finish().unwrap()).unwrap();
+
+        for (stats, real) in report.entries.iter().zip(&real) {
+            assert_eq!(stats.name, real.name);
+            assert_eq!(stats.original_size, real.uncompressed_size);
+            assert_eq!(stats.compressed_size, real.compressed_size);
+            assert_eq!(stats.method, real.method);
+            assert!(!stats.sampled);
+        }
+        assert_eq!(report.entries[1].method, "stored");
+        assert_eq!(report.entries[3].ratio, 1.0);
+        assert_eq!(
+            report.compressed_size,
+            real.iter().map(|entry| entry.compressed_size).sum::<u64>()
+        );
+        assert!(report.ratio < 0.5);
+    }
+
+    #[test]
+    fn scales_sampled_entries() {
+        let text = b"0123456789".repeat(10_000);
+        let stats = StatsOptions {
+            sample_size: Some(10_000),
+        };
+        let sampled =
+            measure_entry("a.txt", Some(&text), &EntryOptions::default(), &stats).unwrap();
+        assert!(sampled.sampled);
+        assert_eq!(sampled.original_size, 100_000);
+
+        let small =
+            measure_entry("b.txt", Some(b"tiny"), &EntryOptions::default(), &stats).unwrap();
+        assert!(!small.sampled);
+        assert_eq!(small.compressed_size, 6);
+
+        let empty = StatsOptions {
+            sample_size: Some(0),
+        };
+        assert!(matches!(
+            measure_entry("a.txt", Some(&text), &EntryOptions::default(), &empty),
+            Err(Error::InvalidOption(_))
+        ));
+    }
+}
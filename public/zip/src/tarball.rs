@@ -0,0 +1,564 @@
+//! Tar output, optionally gzip- or zstd-compressed as a whole ("solid" compression),
+//! and reading of such archives for repacking.
+
+use std::io::{self, Read, Write};
+
+use flate2::read::MultiGzDecoder;
+use serde::Deserialize;
+use tar::{Archive, Builder, EntryType, Header};
+
+use crate::codec::{CompressOptions, Format, StreamEncoder};
+use crate::dedupe::{self, DuplicateReport, Duplicates};
+use crate::error::{Error, Result};
+use crate::limits::{Budget, Limits};
+use crate::path::sanitize_name;
+use crate::read::ExtractedEntry;
+use crate::time::now_millis;
+use crate::write::{DEFAULT_DIR_PERMISSIONS, DEFAULT_FILE_PERMISSIONS, SYMLINK_PERMISSIONS};
+
+/// Largest value of the 11-digit octal mtime field in a ustar header.
+const USTAR_MAX_MTIME: u64 = 0o77_777_777_777;
+/// Room for a name in the `name` field of a ustar header.
+const USTAR_NAME_LEN: usize = 100;
+const BLOCK_SIZE: usize = 512;
+/// Position of the checksum field in a tar header.
+const CHECKSUM_FIELD: std::ops::Range<usize> = 148..156;
+/// File-type bits of a Unix mode, as `ExtractedEntry::unix_mode` carries them.
+const S_IFREG: u32 = 0o100_000;
+const S_IFDIR: u32 = 0o040_000;
+const S_IFLNK: u32 = 0o120_000;
+
+/// How names that do not fit a plain ustar header are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TarFormat {
+    /// ustar headers plus PAX extended headers for long names and link targets.
+    #[default]
+    Pax,
+    /// Strict ustar: names up to 255 bytes (split into prefix and name), link
+    /// targets up to 100 bytes. Longer ones are rejected.
+    Ustar,
+}
+
+/// Compression applied to the whole tar stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TarCompression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// Archive-wide options for `tar_files`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TarOptions {
+    pub format: TarFormat,
+    pub compression: TarCompression,
+    /// Compression level: 0-9 for gzip (default 6), 1-22 for zstd (default 3).
+    pub level: Option<u32>,
+    /// Store a file with the same contents as an earlier one as a symbolic link to it.
+    /// See [`crate::dedupe`].
+    pub dedupe_content: bool,
+}
+
+impl TarOptions {
+    fn output(&self) -> Result<Output> {
+        let format = match self.compression {
+            TarCompression::None if self.level.is_some() => {
+                return Err(Error::InvalidOption(
+                    "a level needs gzip or zstd compression",
+                ))
+            }
+            TarCompression::None => return Ok(Output::Plain(Vec::new())),
+            TarCompression::Gzip => Format::Gzip,
+            TarCompression::Zstd => Format::Zstd,
+        };
+        let encoder = StreamEncoder::new(&CompressOptions {
+            format,
+            level: self.level,
+        })?;
+        Ok(Output::Compressed(Box::new(encoder)))
+    }
+}
+
+/// Per-entry options for `tar_files`. Missing fields fall back to the same defaults as ZIP entries.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TarEntryOptions {
+    /// Unix permission bits, e.g. `0o755`. Defaults to `0o644` for files and `0o755` for
+    /// directories. File-type bits are ignored.
+    pub unix_permissions: Option<u32>,
+    /// Modification time in milliseconds since the Unix epoch. Defaults to the current time.
+    pub last_modified: Option<f64>,
+    /// Store the entry as a symbolic link pointing here instead of as a file.
+    pub link_target: Option<String>,
+}
+
+/// The stream the tar builder writes into.
+enum Output {
+    Plain(Vec<u8>),
+    Compressed(Box<StreamEncoder>),
+}
+
+impl Output {
+    fn finish(self) -> Result<Vec<u8>> {
+        match self {
+            Output::Plain(bytes) => Ok(bytes),
+            Output::Compressed(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(bytes) => bytes.write(buf),
+            Output::Compressed(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(bytes) => bytes.flush(),
+            Output::Compressed(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Incremental tar writer behind `tar_files`.
+pub struct TarWriter {
+    builder: Builder<Output>,
+    format: TarFormat,
+    entries: usize,
+    /// Contents of the files added so far, when `dedupe_content` is set.
+    duplicates: Option<Duplicates>,
+}
+
+impl TarWriter {
+    pub fn new(options: &TarOptions) -> Result<Self> {
+        Ok(Self {
+            builder: Builder::new(options.output()?),
+            format: options.format,
+            entries: 0,
+            duplicates: options.dedupe_content.then(Duplicates::new),
+        })
+    }
+
+    /// Number of entries added so far.
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// Files stored as links because an earlier file has the same contents, when the
+    /// writer was created with `dedupe_content`.
+    pub fn duplicates(&self) -> DuplicateReport {
+        self.duplicates
+            .as_ref()
+            .map(Duplicates::report)
+            .unwrap_or_default()
+    }
+
+    /// Add a regular file, or a symlink when `options.link_target` is set. With
+    /// `dedupe_content`, a file repeating an earlier one's contents becomes a symlink
+    /// to it.
+    pub fn add_file(&mut self, name: &str, data: &[u8], options: &TarEntryOptions) -> Result<()> {
+        let name = sanitize_name(name)?;
+        if let Some(target) = &options.link_target {
+            if target.is_empty() {
+                return Err(Error::InvalidOption("link target cannot be empty"));
+            }
+            let header = entry_header(EntryType::Symlink, SYMLINK_PERMISSIONS, options, 0);
+            return self.append(header, &name, Some(target), &[]);
+        }
+        let original = self
+            .duplicates
+            .as_mut()
+            .and_then(|duplicates| duplicates.check(&name, data));
+        if let Some(original) = original {
+            let target = dedupe::relative_link(&name, original);
+            let header = entry_header(EntryType::Symlink, SYMLINK_PERMISSIONS, options, 0);
+            return self.append(header, &name, Some(&target), &[]);
+        }
+
+        let header = entry_header(
+            EntryType::Regular,
+            options.unix_permissions.unwrap_or(DEFAULT_FILE_PERMISSIONS),
+            options,
+            data.len() as u64,
+        );
+        self.append(header, &name, None, data)
+    }
+
+    /// Add a directory entry. A trailing `/` is appended if missing.
+    pub fn add_directory(&mut self, name: &str, options: &TarEntryOptions) -> Result<()> {
+        let mut name = sanitize_name(name)?;
+        if !name.ends_with('/') {
+            name.push('/');
+        }
+        let header = entry_header(
+            EntryType::Directory,
+            options.unix_permissions.unwrap_or(DEFAULT_DIR_PERMISSIONS),
+            options,
+            0,
+        );
+        self.append(header, &name, None, &[])
+    }
+
+    /// Write the end-of-archive marker, finish compression and return the bytes.
+    pub fn finish(self) -> Result<Vec<u8>> {
+        self.builder.into_inner()?.finish()
+    }
+
+    /// Write `header` for `name`, preceded by a PAX extended header when the name
+    /// or link target does not fit the ustar fields.
+    fn append(
+        &mut self,
+        mut header: Header,
+        name: &str,
+        link_target: Option<&str>,
+        data: &[u8],
+    ) -> Result<()> {
+        let mut pax = Vec::new();
+        if header.set_path(name).is_err() {
+            if self.format == TarFormat::Ustar {
+                return Err(Error::InvalidName(
+                    "name is too long for a ustar header; use the pax format",
+                ));
+            }
+            pax_record(&mut pax, "path", name);
+            header.set_path(truncate_name(name))?;
+        }
+        if let Some(target) = link_target {
+            if header.set_link_name(target).is_err() {
+                if self.format == TarFormat::Ustar {
+                    return Err(Error::InvalidOption(
+                        "link target is too long for a ustar header; use the pax format",
+                    ));
+                }
+                pax_record(&mut pax, "linkpath", target);
+                header.set_link_name(truncate_name(target))?;
+            }
+        }
+
+        if !pax.is_empty() {
+            let mut extended = Header::new_ustar();
+            extended.set_entry_type(EntryType::XHeader);
+            extended.set_path(format!("PaxHeaders/{}", truncate_name(name)))?;
+            extended.set_mode(DEFAULT_FILE_PERMISSIONS);
+            extended.set_mtime(header.mtime()?);
+            extended.set_size(pax.len() as u64);
+            extended.set_cksum();
+            self.builder.append(&extended, pax.as_slice())?;
+        }
+
+        header.set_cksum();
+        self.builder.append(&header, data)?;
+        self.entries += 1;
+        Ok(())
+    }
+}
+
+/// Read a tar archive, plain or compressed as a whole with gzip or zstd, handing each
+/// entry to `each` in order within `limits`. Symbolic links come back with their
+/// target as data and the link file type in `unix_mode`.
+pub fn for_each_entry(
+    bytes: &[u8],
+    limits: &Limits,
+    mut each: impl FnMut(ExtractedEntry) -> Result<()>,
+) -> Result<()> {
+    let mut reader: Box<dyn Read + '_> = match Format::detect(bytes) {
+        Some(Format::Gzip) => Box::new(MultiGzDecoder::new(bytes)),
+        Some(Format::Zstd) => Box::new(zstd::stream::read::Decoder::with_buffer(bytes)?),
+        _ => Box::new(bytes),
+    };
+    // Tar has no signature, so anything with a valid first header is taken as tar.
+    let mut first = [0; BLOCK_SIZE];
+    if reader.read_exact(&mut first).is_err() || !is_tar_block(&first) {
+        return Err(Error::InvalidArchive("not a ZIP, 7z, RAR or tar archive"));
+    }
+    let mut archive = Archive::new(first.as_slice().chain(reader));
+    let mut budget = Budget::new(limits);
+    let mut count = 0;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let kind = entry.header().entry_type();
+        let file_type = match kind {
+            EntryType::Regular | EntryType::Continuous => S_IFREG,
+            EntryType::Directory => S_IFDIR,
+            EntryType::Symlink => S_IFLNK,
+            EntryType::XGlobalHeader => continue,
+            _ => {
+                return Err(Error::Unsupported(
+                    "only files, directories and symbolic links can be read from tar archives",
+                ))
+            }
+        };
+        count += 1;
+        budget.check_entries(count)?;
+
+        let mut name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        if file_type == S_IFDIR && !name.ends_with('/') {
+            name.push('/');
+        }
+        let name = sanitize_name(&name)?;
+        budget.check_path_depth(&name)?;
+        let header = entry.header();
+        let unix_mode = header.mode()? & 0o7777 | file_type;
+        let last_modified = header.mtime()? as f64 * 1_000.0;
+
+        let data = match file_type {
+            S_IFLNK => entry
+                .link_name_bytes()
+                .map(|target| target.into_owned())
+                .unwrap_or_default(),
+            S_IFDIR => Vec::new(),
+            _ => {
+                // The stream is compressed as a whole, so entries have no ratio of their own.
+                let size = entry.size();
+                budget.read(&mut entry, &name, size, size)?
+            }
+        };
+        each(ExtractedEntry {
+            name,
+            is_dir: file_type == S_IFDIR,
+            last_modified,
+            unix_mode: Some(unix_mode),
+            data,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Whether `block` is a tar header with a valid checksum, or the zero block ending an
+/// empty archive.
+fn is_tar_block(block: &[u8; BLOCK_SIZE]) -> bool {
+    if block.iter().all(|&byte| byte == 0) {
+        return true;
+    }
+    // The checksum is the sum of every byte, with its own field counted as spaces.
+    let sum: u32 = block
+        .iter()
+        .enumerate()
+        .map(|(idx, &byte)| {
+            if CHECKSUM_FIELD.contains(&idx) {
+                u32::from(b' ')
+            } else {
+                u32::from(byte)
+            }
+        })
+        .sum();
+    let stored = std::str::from_utf8(&block[CHECKSUM_FIELD])
+        .ok()
+        .and_then(|field| u32::from_str_radix(field.trim_matches(['\0', ' ']), 8).ok());
+    stored == Some(sum)
+}
+
+fn entry_header(kind: EntryType, permissions: u32, options: &TarEntryOptions, size: u64) -> Header {
+    let mut header = Header::new_ustar();
+    header.set_entry_type(kind);
+    header.set_mode(permissions & 0o7777);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_size(size);
+    let millis = options.last_modified.unwrap_or_else(now_millis);
+    header.set_mtime(((millis / 1_000.0).floor().max(0.0) as u64).min(USTAR_MAX_MTIME));
+    header
+}
+
+/// Append one `"<len> <key>=<value>\n"` record, where `len` counts the whole record.
+fn pax_record(out: &mut Vec<u8>, key: &str, value: &str) {
+    let body = key.len() + value.len() + 3;
+    let mut len = body + 1;
+    while len != body + len.to_string().len() {
+        len = body + len.to_string().len();
+    }
+    out.extend_from_slice(format!("{len} {key}={value}\n").as_bytes());
+}
+
+/// Fallback for the ustar field when the real value lives in a PAX record: the
+/// last path component, cut to fit on a character boundary.
+fn truncate_name(name: &str) -> &str {
+    let last = name
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(name);
+    let mut end = last.len().min(USTAR_NAME_LEN - 1);
+    while !last.is_char_boundary(end) {
+        end -= 1;
+    }
+    &last[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Listed {
+        path: String,
+        kind: EntryType,
+        link: Option<String>,
+        mode: u32,
+        mtime: u64,
+        data: Vec<u8>,
+    }
+
+    fn read_entries(bytes: &[u8]) -> Vec<Listed> {
+        let mut archive = tar::Archive::new(bytes);
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                let header = entry.header();
+                let kind = header.entry_type();
+                let mode = header.mode().unwrap();
+                let mtime = header.mtime().unwrap();
+                let link = entry
+                    .link_name()
+                    .unwrap()
+                    .map(|link| link.to_string_lossy().into_owned());
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                Listed {
+                    path,
+                    kind,
+                    link,
+                    mode,
+                    mtime,
+                    data,
+                }
+            })
+            .collect()
+    }
+
+    fn sample(options: &TarOptions) -> Result<Vec<u8>> {
+        let file = TarEntryOptions {
+            unix_permissions: Some(0o100_755),
+            last_modified: Some(1_709_296_210_999.0),
+            ..TarEntryOptions::default()
+        };
+        let link = TarEntryOptions {
+            link_target: Some("run.sh".into()),
+            ..TarEntryOptions::default()
+        };
+        let mut writer = TarWriter::new(options)?;
+        writer.add_directory("bin", &TarEntryOptions::default())?;
+        writer.add_file("/bin/run.sh", b"#!/bin/sh\n", &file)?;
+        writer.add_file("bin/run", b"ignored", &link)?;
+        writer.finish()
+    }
+
+    #[test]
+    fn writes_files_directories_and_symlinks() {
+        let entries = read_entries(&sample(&TarOptions::default()).unwrap());
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(entries[0].path, "bin/");
+        assert_eq!(entries[0].kind, EntryType::Directory);
+        assert_eq!(entries[0].mode, 0o755);
+
+        let script = &entries[1];
+        assert_eq!(script.path, "bin/run.sh");
+        assert_eq!(script.kind, EntryType::Regular);
+        assert_eq!(script.mode, 0o755);
+        assert_eq!(script.mtime, 1_709_296_210);
+        assert_eq!(script.data, b"#!/bin/sh\n");
+
+        assert_eq!(entries[2].kind, EntryType::Symlink);
+        assert_eq!(entries[2].link.as_deref(), Some("run.sh"));
+        assert!(entries[2].data.is_empty());
+    }
+
+    #[test]
+    fn compresses_with_gzip_and_zstd() {
+        let plain = sample(&TarOptions::default()).unwrap();
+
+        let gzip = sample(&TarOptions {
+            compression: TarCompression::Gzip,
+            level: Some(9),
+            ..TarOptions::default()
+        })
+        .unwrap();
+        let mut unpacked = Vec::new();
+        flate2::read::GzDecoder::new(gzip.as_slice())
+            .read_to_end(&mut unpacked)
+            .unwrap();
+        assert_eq!(read_entries(&unpacked), read_entries(&plain));
+
+        let zstd = sample(&TarOptions {
+            compression: TarCompression::Zstd,
+            ..TarOptions::default()
+        })
+        .unwrap();
+        let unpacked = zstd::decode_all(zstd.as_slice()).unwrap();
+        assert_eq!(read_entries(&unpacked), read_entries(&plain));
+
+        assert!(matches!(
+            TarWriter::new(&TarOptions {
+                compression: TarCompression::Zstd,
+                level: Some(0),
+                ..TarOptions::default()
+            }),
+            Err(Error::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn stores_long_names_with_pax_or_ustar_prefix() {
+        let options = TarEntryOptions::default();
+        let split = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        let long = format!("deep/{}.txt", "ü".repeat(150));
+        let target = "t".repeat(150);
+
+        let mut ustar = TarWriter::new(&TarOptions {
+            format: TarFormat::Ustar,
+            ..TarOptions::default()
+        })
+        .unwrap();
+        ustar.add_file(&split, b"x", &options).unwrap();
+        assert!(matches!(
+            ustar.add_file(&long, b"x", &options),
+            Err(Error::InvalidName(_))
+        ));
+        let entries = read_entries(&ustar.finish().unwrap());
+        assert_eq!(entries[0].path, split);
+
+        let mut pax = TarWriter::new(&TarOptions::default()).unwrap();
+        pax.add_file(&long, b"y", &options).unwrap();
+        pax.add_file(
+            "link",
+            &[],
+            &TarEntryOptions {
+                link_target: Some(target.clone()),
+                ..options
+            },
+        )
+        .unwrap();
+        let entries = read_entries(&pax.finish().unwrap());
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, long);
+        assert_eq!(entries[0].data, b"y");
+        assert_eq!(entries[1].link.as_deref(), Some(target.as_str()));
+    }
+
+    #[test]
+    fn pax_record_length_counts_itself() {
+        let mut out = Vec::new();
+        pax_record(&mut out, "path", &"a".repeat(93));
+        assert_eq!(out.len(), 103);
+        assert!(out.starts_with(b"103 path="));
+    }
+}
@@ -0,0 +1,111 @@
+use zip::DateTime;
+
+/// Milliseconds since the Unix epoch, the unit JS uses for `File.lastModified`.
+pub fn now_millis() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        js_sys::Date::now()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0.0, |elapsed| elapsed.as_millis() as f64)
+    }
+}
+
+/// Convert an MS-DOS timestamp, read as UTC, to milliseconds since the Unix epoch.
+pub fn dos_to_unix_millis(time: DateTime) -> f64 {
+    let days = days_from_civil(
+        i64::from(time.year()),
+        u32::from(time.month().max(1)),
+        u32::from(time.day().max(1)),
+    );
+    let seconds = days * 86_400
+        + i64::from(time.hour()) * 3_600
+        + i64::from(time.minute()) * 60
+        + i64::from(time.second());
+    (seconds * 1_000) as f64
+}
+
+/// 100 ns intervals between 1601-01-01, the Windows `FILETIME` epoch, and the Unix epoch.
+const FILETIME_UNIX_EPOCH: i128 = 116_444_736_000_000_000;
+
+/// Convert a Windows `FILETIME`, as 7z and RAR store times, to milliseconds since the
+/// Unix epoch.
+pub fn filetime_to_unix_millis(filetime: u64) -> f64 {
+    ((i128::from(filetime) - FILETIME_UNIX_EPOCH) / 10_000) as f64
+}
+
+/// Convert milliseconds since the Unix epoch to an MS-DOS timestamp in UTC.
+///
+/// DOS timestamps only cover 1980-2107 with two-second resolution, so times outside
+/// that range are clamped and odd seconds are rounded down.
+pub fn unix_millis_to_dos(millis: f64) -> DateTime {
+    let seconds = (millis / 1_000.0).floor() as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let second_of_day = seconds.rem_euclid(86_400);
+
+    if year < 1980 {
+        return DateTime::default();
+    }
+    if year > 2107 {
+        return DateTime::from_date_and_time(2107, 12, 31, 23, 59, 58).unwrap_or_default();
+    }
+
+    DateTime::from_date_and_time(
+        year as u16,
+        month as u8,
+        day as u8,
+        (second_of_day / 3_600) as u8,
+        (second_of_day % 3_600 / 60) as u8,
+        (second_of_day % 60) as u8,
+    )
+    .unwrap_or_default()
+}
+
+/// Days between 1970-01-01 and the given proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = i64::from((month + 9) % 12);
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of [`days_from_civil`]: the `(year, month, day)` that is `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_between_unix_and_dos_times() {
+        let dos = unix_millis_to_dos(1_709_296_211_999.0);
+        assert_eq!((dos.year(), dos.month(), dos.day()), (2024, 3, 1));
+        assert_eq!((dos.hour(), dos.minute(), dos.second()), (12, 30, 10));
+        assert_eq!(dos_to_unix_millis(dos), 1_709_296_210_000.0);
+
+        assert_eq!(unix_millis_to_dos(0.0), DateTime::default());
+        assert_eq!(unix_millis_to_dos(1e15).year(), 2107);
+    }
+}
@@ -0,0 +1,685 @@
+//! Integrity checks for existing archives.
+//!
+//! Entries listed in the central directory are checked against their local headers
+//! and fully decompressed to verify their CRC-32. When the central directory cannot be
+//! read, as in a truncated download, entries are recovered by scanning for local
+//! headers and checked the same way through a rebuilt central directory.
+
+use std::io::{self, Cursor, Read};
+
+use serde::Serialize;
+use zip::read::ZipFile;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+use crate::error::Result;
+use crate::read::method_name;
+use crate::staged::{
+    extra_fields, put_end_of_central_directory, put_extra_fields, put_u16, put_u32, read_u16,
+    read_u32, read_u64, saturate, CENTRAL_HEADER_LEN, CENTRAL_HEADER_SIGNATURE, FLAG_UTF8,
+    LOCAL_HEADER_SIGNATURE, ZIP64_EXTRA_ID,
+};
+
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+const FLAG_ENCRYPTED: u16 = 0x0001;
+const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+const LOCAL_HEADER_LEN: usize = 30;
+
+/// Outcome of checking one entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryStatus {
+    Ok,
+    /// The data decompressed but does not match the recorded CRC-32.
+    CrcMismatch,
+    /// The entry's data runs past the end of the file.
+    Truncated,
+    /// The compression method or encryption is not supported by this build.
+    UnsupportedMethod,
+    /// The local header is missing, disagrees with the central directory, or the
+    /// entry's data overlaps another entry.
+    HeaderMismatch,
+    /// The compressed stream itself is invalid.
+    Corrupt,
+    /// The entry is encrypted and no password was given, so its data was not checked.
+    PasswordRequired,
+    /// The password is wrong, or the encrypted data is damaged.
+    InvalidPassword,
+}
+
+/// Result of checking one entry, as returned to JS by `verify_zip`.
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryReport {
+    pub name: String,
+    /// Offset of the local header. Sizes and offset are 0 when the central directory
+    /// points at a local header that cannot be read.
+    pub offset: u64,
+    pub method: String,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub status: EntryStatus,
+    /// What went wrong, for any status other than `Ok`.
+    pub message: Option<String>,
+}
+
+/// Result of `verify_archive`.
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    /// Whether the central directory was intact and every entry is `Ok`.
+    pub ok: bool,
+    /// Whether the central directory was unreadable and `entries` were recovered by
+    /// scanning local headers.
+    pub recovered: bool,
+    /// Problems with the archive as a whole, such as an unreadable central directory.
+    pub issues: Vec<String>,
+    /// Entries in the order of the central directory, or of the file when recovered.
+    pub entries: Vec<EntryReport>,
+}
+
+/// Check every entry of a ZIP archive. `password` is used for encrypted entries;
+/// without it their data is reported as unchecked rather than failing the call.
+pub fn verify_archive(bytes: &[u8], password: Option<&str>) -> Result<VerifyReport> {
+    let mut issues = Vec::new();
+    let (recovered, entries) = match ZipArchive::new(Cursor::new(bytes)) {
+        Ok(mut archive) => {
+            let records = count_central_records(bytes, archive.central_directory_start());
+            if records > archive.len() {
+                issues.push(format!(
+                    "{} central directory records repeat the name of another entry",
+                    records - archive.len()
+                ));
+            }
+            (false, check_entries(&mut archive, bytes, password))
+        }
+        Err(err) => {
+            issues.push(format!("central directory is unreadable: {err}"));
+            let (found, mut entries) = scan_local_headers(bytes);
+            let rebuilt = rebuild_central_directory(bytes, &found);
+            let mut archive = ZipArchive::new(Cursor::new(rebuilt.as_slice()))?;
+            entries.extend(check_entries(&mut archive, &rebuilt, password));
+            entries.sort_by_key(|entry| entry.offset);
+            (true, entries)
+        }
+    };
+
+    Ok(VerifyReport {
+        ok: issues.is_empty() && entries.iter().all(|entry| entry.status == EntryStatus::Ok),
+        recovered,
+        issues,
+        entries,
+    })
+}
+
+fn check_entries(
+    archive: &mut ZipArchive<Cursor<&[u8]>>,
+    bytes: &[u8],
+    password: Option<&str>,
+) -> Vec<EntryReport> {
+    let mut reports = Vec::with_capacity(archive.len());
+    let mut spans = Vec::with_capacity(archive.len());
+    for idx in 0..archive.len() {
+        let (report, span) = check_entry(archive, idx, bytes, password);
+        spans.extend(span.map(|(start, end)| (start, end, reports.len())));
+        reports.push(report);
+    }
+
+    spans.sort_unstable();
+    for pair in spans.windows(2) {
+        let ((_, end, idx), (next_start, _, _)) = (pair[0], pair[1]);
+        let report = &mut reports[idx];
+        if end > next_start && report.status == EntryStatus::Ok {
+            report.status = EntryStatus::HeaderMismatch;
+            report.message = Some(format!("data overlaps the entry at offset {next_start}"));
+        }
+    }
+    reports
+}
+
+/// Check one entry, returning its report and the byte range from its local header to
+/// the end of its data.
+fn check_entry(
+    archive: &mut ZipArchive<Cursor<&[u8]>>,
+    idx: usize,
+    bytes: &[u8],
+    password: Option<&str>,
+) -> (EntryReport, Option<(u64, u64)>) {
+    let name = archive.name_for_index(idx).unwrap_or_default().to_string();
+    let (mut report, span, mismatch, encrypted) = match archive.by_index_raw(idx) {
+        Ok(file) => {
+            let report = EntryReport {
+                name,
+                offset: file.header_start(),
+                method: method_name(file.compression()),
+                compressed_size: file.compressed_size(),
+                uncompressed_size: file.size(),
+                status: EntryStatus::Ok,
+                message: None,
+            };
+            let span = (
+                file.header_start(),
+                file.data_start() + file.compressed_size(),
+            );
+            (
+                report,
+                span,
+                header_mismatch(bytes, &file),
+                file.encrypted(),
+            )
+        }
+        Err(err) => {
+            let (status, message) = classify(err, false);
+            let report = EntryReport {
+                name,
+                offset: 0,
+                method: String::new(),
+                compressed_size: 0,
+                uncompressed_size: 0,
+                status: match status {
+                    EntryStatus::Truncated => status,
+                    _ => EntryStatus::HeaderMismatch,
+                },
+                message: Some(format!("local header is unreadable: {message}")),
+            };
+            return (report, None);
+        }
+    };
+
+    let failure = if span.1 > bytes.len() as u64 {
+        Some((
+            EntryStatus::Truncated,
+            "data runs past the end of the file".to_string(),
+        ))
+    } else if let Some(message) = mismatch {
+        Some((EntryStatus::HeaderMismatch, message))
+    } else {
+        let opened = match (encrypted, password) {
+            (false, _) => archive.by_index(idx),
+            (true, Some(password)) => archive.by_index_decrypt(idx, password.as_bytes()),
+            (true, None) => Err(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED)),
+        };
+        opened
+            .and_then(|mut file| Ok(io::copy(&mut file, &mut io::sink())?))
+            .err()
+            .map(|err| classify(err, encrypted))
+    };
+    if let Some((status, message)) = failure {
+        report.status = status;
+        report.message = Some(message);
+    }
+    (report, Some(span))
+}
+
+fn classify(err: ZipError, encrypted: bool) -> (EntryStatus, String) {
+    let status = match &err {
+        ZipError::InvalidPassword => EntryStatus::InvalidPassword,
+        ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => EntryStatus::PasswordRequired,
+        ZipError::UnsupportedArchive(_) => EntryStatus::UnsupportedMethod,
+        ZipError::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof => EntryStatus::Truncated,
+        // A checksum or MAC failure on encrypted data usually means the password
+        // passed the quick header check but is still wrong.
+        ZipError::Io(err) if encrypted && err.kind() == io::ErrorKind::InvalidData => {
+            EntryStatus::InvalidPassword
+        }
+        // `zip` has no dedicated error for this; its CRC reader only sets the message.
+        ZipError::Io(err) if err.to_string() == "Invalid checksum" => EntryStatus::CrcMismatch,
+        _ => EntryStatus::Corrupt,
+    };
+    (status, err.to_string())
+}
+
+/// Compare an entry's local header with its central directory record.
+fn header_mismatch<R: Read>(bytes: &[u8], file: &ZipFile<'_, R>) -> Option<String> {
+    let Some(local) = LocalHeader::parse(bytes, file.header_start() as usize) else {
+        return Some("local header is cut off".to_string());
+    };
+    let central = file.central_header_start() as usize;
+    let central_flags = read_u16(bytes, central + 8).unwrap_or_default();
+    let central_method = read_u16(bytes, central + 10).unwrap_or_default();
+
+    if local.name != file.name_raw() {
+        return Some(format!(
+            "local header names the entry {:?}",
+            String::from_utf8_lossy(local.name)
+        ));
+    }
+    if local.method != central_method {
+        return Some(format!(
+            "local header uses method {}, central directory uses {central_method}",
+            local.method
+        ));
+    }
+    if (local.flags ^ central_flags) & FLAG_ENCRYPTED != 0 {
+        return Some("local header and central directory disagree on encryption".to_string());
+    }
+    if (local.flags ^ central_flags) & FLAG_UTF8 != 0 {
+        return Some("local header and central directory disagree on the UTF-8 flag".to_string());
+    }
+    // With a data descriptor the local header may leave the CRC and sizes at zero.
+    if local.flags & FLAG_DATA_DESCRIPTOR == 0 {
+        if local.crc32 != file.crc32() {
+            return Some("local header has a different CRC-32".to_string());
+        }
+        if local.compressed_size != file.compressed_size() || local.uncompressed_size != file.size()
+        {
+            return Some("local header has different sizes".to_string());
+        }
+    }
+    None
+}
+
+/// Number of consecutive records in the central directory starting at `start`.
+/// `ZipArchive` keeps only the last of several entries with the same name.
+fn count_central_records(bytes: &[u8], start: u64) -> usize {
+    let mut at = start as usize;
+    let mut count = 0;
+    while read_u32(bytes, at).ok() == Some(CENTRAL_HEADER_SIGNATURE) {
+        let variable = [28, 30, 32]
+            .into_iter()
+            .map(|field| read_u16(bytes, at + field).map_or(0, usize::from))
+            .sum::<usize>();
+        at += CENTRAL_HEADER_LEN + variable;
+        count += 1;
+    }
+    count
+}
+
+/// A local file header, with sizes from its Zip64 field when it has one.
+struct LocalHeader<'a> {
+    version: u16,
+    flags: u16,
+    method: u16,
+    /// DOS time and date, kept as one little-endian value.
+    modified: u32,
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    name: &'a [u8],
+    extra: &'a [u8],
+    data_start: usize,
+}
+
+impl<'a> LocalHeader<'a> {
+    fn parse(bytes: &'a [u8], at: usize) -> Option<LocalHeader<'a>> {
+        if read_u32(bytes, at).ok()? != LOCAL_HEADER_SIGNATURE {
+            return None;
+        }
+        let name_start = at + LOCAL_HEADER_LEN;
+        let extra_start = name_start + usize::from(read_u16(bytes, at + 26).ok()?);
+        let data_start = extra_start + usize::from(read_u16(bytes, at + 28).ok()?);
+        let extra = bytes.get(extra_start..data_start)?;
+
+        let mut compressed_size = u64::from(read_u32(bytes, at + 18).ok()?);
+        let mut uncompressed_size = u64::from(read_u32(bytes, at + 22).ok()?);
+        if let Some(mut values) = zip64_values(extra) {
+            // The field holds only the sizes whose 32-bit field is saturated.
+            if uncompressed_size == u64::from(u32::MAX) {
+                uncompressed_size = values.next()?;
+            }
+            if compressed_size == u64::from(u32::MAX) {
+                compressed_size = values.next()?;
+            }
+        }
+
+        Some(LocalHeader {
+            version: read_u16(bytes, at + 4).ok()?,
+            flags: read_u16(bytes, at + 6).ok()?,
+            method: read_u16(bytes, at + 8).ok()?,
+            modified: read_u32(bytes, at + 10).ok()?,
+            crc32: read_u32(bytes, at + 14).ok()?,
+            compressed_size,
+            uncompressed_size,
+            name: bytes.get(name_start..extra_start)?,
+            extra,
+            data_start,
+        })
+    }
+}
+
+fn zip64_values(extra: &[u8]) -> Option<impl Iterator<Item = u64> + '_> {
+    let (_, data) = extra_fields(extra).find(|&(id, _)| id == ZIP64_EXTRA_ID)?;
+    Some((0..data.len() / 8).filter_map(move |idx| read_u64(data, idx * 8).ok()))
+}
+
+fn find_signature(bytes: &[u8], from: usize, signature: u32) -> Option<usize> {
+    let needle = signature.to_le_bytes();
+    bytes
+        .get(from..)?
+        .windows(4)
+        .position(|window| window == needle)
+        .map(|pos| from + pos)
+}
+
+/// A complete entry found by scanning for local headers.
+struct Recovered<'a> {
+    offset: usize,
+    header: LocalHeader<'a>,
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    /// End of the entry's data, including any data descriptor.
+    end: usize,
+}
+
+/// Find entries by their local headers. Scanning stops at the first entry whose data
+/// does not fit in the file, which is reported as truncated.
+fn scan_local_headers(bytes: &[u8]) -> (Vec<Recovered<'_>>, Vec<EntryReport>) {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(offset) = find_signature(bytes, from, LOCAL_HEADER_SIGNATURE) {
+        let Some(header) = LocalHeader::parse(bytes, offset) else {
+            return (
+                found,
+                vec![truncated(bytes, offset, "local header is cut off")],
+            );
+        };
+
+        let entry = if header.flags & FLAG_DATA_DESCRIPTOR == 0 {
+            let end = header.data_start as u64 + header.compressed_size;
+            (end <= bytes.len() as u64).then_some(Recovered {
+                offset,
+                crc32: header.crc32,
+                compressed_size: header.compressed_size,
+                uncompressed_size: header.uncompressed_size,
+                end: end as usize,
+                header,
+            })
+        } else {
+            find_data_descriptor(bytes, offset, header)
+        };
+        let Some(entry) = entry else {
+            let report = truncated(bytes, offset, "data runs past the end of the file");
+            return (found, vec![report]);
+        };
+        from = entry.end;
+        found.push(entry);
+    }
+    (found, Vec::new())
+}
+
+/// Complete an entry written with a trailing data descriptor by looking for a
+/// descriptor whose compressed size matches its distance from the start of the data.
+fn find_data_descriptor<'a>(
+    bytes: &[u8],
+    offset: usize,
+    header: LocalHeader<'a>,
+) -> Option<Recovered<'a>> {
+    let zip64 = zip64_values(header.extra).is_some();
+    let mut from = header.data_start;
+    loop {
+        let at = find_signature(bytes, from, DATA_DESCRIPTOR_SIGNATURE)?;
+        let distance = (at - header.data_start) as u64;
+        let (compressed_size, uncompressed_size, end) = if zip64 {
+            (read_u64(bytes, at + 8), read_u64(bytes, at + 16), at + 24)
+        } else {
+            let size = |at| read_u32(bytes, at).map(u64::from);
+            (size(at + 8), size(at + 12), at + 16)
+        };
+        if let (Ok(compressed_size), Ok(uncompressed_size)) = (compressed_size, uncompressed_size) {
+            if compressed_size == distance {
+                return Some(Recovered {
+                    offset,
+                    crc32: read_u32(bytes, at + 4).ok()?,
+                    compressed_size,
+                    uncompressed_size,
+                    end,
+                    header,
+                });
+            }
+        }
+        from = at + 1;
+    }
+}
+
+/// Report for the entry at `offset` whose header or data is cut off by the end of the
+/// file. Whatever part of the name survives is kept.
+fn truncated(bytes: &[u8], offset: usize, message: &str) -> EntryReport {
+    let header = LocalHeader::parse(bytes, offset);
+    let name = match &header {
+        Some(header) => header.name,
+        None => {
+            let name_start = (offset + LOCAL_HEADER_LEN).min(bytes.len());
+            let name_len = read_u16(bytes, offset + 26).map_or(0, usize::from);
+            &bytes[name_start..(name_start + name_len).min(bytes.len())]
+        }
+    };
+    EntryReport {
+        name: String::from_utf8_lossy(name).into_owned(),
+        offset: offset as u64,
+        method: header.as_ref().map_or_else(String::new, |header| {
+            #[allow(deprecated)]
+            method_name(zip::CompressionMethod::from_u16(header.method))
+        }),
+        compressed_size: header.as_ref().map_or(0, |header| header.compressed_size),
+        uncompressed_size: header.as_ref().map_or(0, |header| header.uncompressed_size),
+        status: EntryStatus::Truncated,
+        message: Some(message.to_string()),
+    }
+}
+
+/// Copy the recovered entries and append a central directory listing them, so they can
+/// be checked like the entries of an intact archive.
+fn rebuild_central_directory(bytes: &[u8], found: &[Recovered<'_>]) -> Vec<u8> {
+    let data_end = found.last().map_or(0, |entry| entry.end);
+    let mut out = bytes[..data_end].to_vec();
+    let central = out.len();
+
+    for entry in found {
+        let header = &entry.header;
+        let mut zip64 = Vec::new();
+        let uncompressed_size = saturate(entry.uncompressed_size, &mut zip64);
+        let compressed_size = saturate(entry.compressed_size, &mut zip64);
+        let offset = saturate(entry.offset as u64, &mut zip64);
+
+        let mut extra = Vec::new();
+        put_extra_fields(&mut extra, &zip64, header.extra);
+
+        put_u32(&mut out, CENTRAL_HEADER_SIGNATURE);
+        put_u16(&mut out, header.version);
+        put_u16(&mut out, header.version);
+        put_u16(&mut out, header.flags);
+        put_u16(&mut out, header.method);
+        put_u32(&mut out, header.modified);
+        put_u32(&mut out, entry.crc32);
+        put_u32(&mut out, compressed_size);
+        put_u32(&mut out, uncompressed_size);
+        put_u16(&mut out, header.name.len() as u16);
+        put_u16(&mut out, extra.len() as u16);
+        // Comment length, disk number and internal attributes.
+        out.extend_from_slice(&[0; 6]);
+        // External attributes.
+        put_u32(&mut out, 0);
+        put_u32(&mut out, offset);
+        out.extend_from_slice(header.name);
+        out.extend_from_slice(&extra);
+    }
+
+    put_end_of_central_directory(&mut out, central, found.len(), &[]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    use super::*;
+    use crate::write::{ArchiveWriter, Encryption, EntryOptions, Method};
+
+    fn sample_archive() -> Vec<u8> {
+        let text = b"integrity matters ".repeat(50);
+        let mut writer = ArchiveWriter::new();
+        writer
+            .add_directory("docs/", &EntryOptions::default())
+            .unwrap();
+        writer
+            .add_file("docs/a.txt", &text, &EntryOptions::default())
+            .unwrap();
+        let stored = EntryOptions {
+            method: Method::Stored,
+            ..EntryOptions::default()
+        };
+        writer.add_file("docs/b.txt", &text, &stored).unwrap();
+        let zstd = EntryOptions {
+            method: Method::Zstd,
+            ..EntryOptions::default()
+        };
+        // Streamed without a size hint, so the headers carry Zip64 sizes.
+        writer.start_file("docs/c.txt", &zstd).unwrap();
+        writer.write_chunk(&text).unwrap();
+        let secret = EntryOptions {
+            password: Some("pw".into()),
+            encryption: Encryption::ZipCrypto,
+            ..EntryOptions::default()
+        };
+        writer.add_file("secret.txt", &text, &secret).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn statuses(report: &VerifyReport) -> Vec<(&str, EntryStatus)> {
+        report
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.status))
+            .collect()
+    }
+
+    fn entry<'a>(report: &'a VerifyReport, name: &str) -> &'a EntryReport {
+        report
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn reports_every_entry_of_an_intact_archive() {
+        let bytes = sample_archive();
+        let report = verify_archive(&bytes, Some("pw")).unwrap();
+        assert!(report.ok, "{report:?}");
+        assert!(!report.recovered);
+        assert_eq!(report.entries.len(), 5);
+        assert_eq!(entry(&report, "docs/c.txt").method, "zstd");
+
+        let report = verify_archive(&bytes, None).unwrap();
+        assert!(!report.ok);
+        assert_eq!(
+            entry(&report, "secret.txt").status,
+            EntryStatus::PasswordRequired
+        );
+        let report = verify_archive(&bytes, Some("nope")).unwrap();
+        assert_eq!(
+            entry(&report, "secret.txt").status,
+            EntryStatus::InvalidPassword
+        );
+    }
+
+    #[test]
+    fn detects_crc_and_header_mismatches() {
+        let bytes = sample_archive();
+        let report = verify_archive(&bytes, Some("pw")).unwrap();
+        let stored = entry(&report, "docs/b.txt").offset as usize;
+        let deflated = entry(&report, "docs/a.txt").offset as usize;
+
+        let mut damaged = bytes.clone();
+        let data_start = LocalHeader::parse(&damaged, stored).unwrap().data_start;
+        damaged[data_start + 5] ^= 0xff;
+        // The local CRC-32 of the deflated entry no longer matches the central one.
+        damaged[deflated + 14] ^= 0xff;
+
+        let report = verify_archive(&damaged, Some("pw")).unwrap();
+        assert!(!report.ok);
+        assert_eq!(
+            entry(&report, "docs/b.txt").status,
+            EntryStatus::CrcMismatch
+        );
+        assert_eq!(
+            entry(&report, "docs/a.txt").status,
+            EntryStatus::HeaderMismatch
+        );
+        assert_eq!(entry(&report, "docs/c.txt").status, EntryStatus::Ok);
+
+        // Info-ZIP rejects entries whose headers disagree on the UTF-8 flag.
+        let mut flagged = bytes.clone();
+        flagged[stored + 7] ^= (FLAG_UTF8 >> 8) as u8;
+        let report = verify_archive(&flagged, Some("pw")).unwrap();
+        assert_eq!(
+            entry(&report, "docs/b.txt").status,
+            EntryStatus::HeaderMismatch
+        );
+    }
+
+    #[test]
+    fn reports_unsupported_methods() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.start_file("a.wv", options).unwrap();
+        writer.write_all(b"not really wavpack").unwrap();
+        let mut bytes = writer.finish().unwrap().into_inner();
+
+        // Relabel the entry as WavPack (97) in both headers.
+        let central = find_signature(&bytes, 0, CENTRAL_HEADER_SIGNATURE).unwrap();
+        bytes[8..10].copy_from_slice(&97u16.to_le_bytes());
+        bytes[central + 10..central + 12].copy_from_slice(&97u16.to_le_bytes());
+
+        let report = verify_archive(&bytes, None).unwrap();
+        assert_eq!(
+            statuses(&report),
+            [("a.wv", EntryStatus::UnsupportedMethod)]
+        );
+    }
+
+    #[test]
+    fn recovers_entries_when_the_central_directory_is_missing() {
+        let bytes = sample_archive();
+        let report = verify_archive(&bytes, Some("pw")).unwrap();
+        let cut = entry(&report, "docs/c.txt").offset as usize + 60;
+
+        let report = verify_archive(&bytes[..cut], Some("pw")).unwrap();
+        assert!(!report.ok);
+        assert!(report.recovered);
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(
+            statuses(&report),
+            [
+                ("docs/", EntryStatus::Ok),
+                ("docs/a.txt", EntryStatus::Ok),
+                ("docs/b.txt", EntryStatus::Ok),
+                ("docs/c.txt", EntryStatus::Truncated),
+            ]
+        );
+
+        // Without the central directory and trailing records, every entry survives.
+        let central = find_signature(&bytes, 0, CENTRAL_HEADER_SIGNATURE).unwrap();
+        let report = verify_archive(&bytes[..central], Some("pw")).unwrap();
+        assert!(report.recovered);
+        assert!(report
+            .entries
+            .iter()
+            .all(|entry| entry.status == EntryStatus::Ok));
+        assert_eq!(report.entries.len(), 5);
+    }
+
+    #[test]
+    fn recovers_entries_written_with_data_descriptors() {
+        let mut writer = ZipWriter::new_stream(Vec::new());
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        for name in ["one.txt", "two.txt"] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(&name.as_bytes().repeat(100)).unwrap();
+        }
+        let bytes = writer.finish().unwrap().into_inner();
+        assert_ne!(read_u16(&bytes, 6).unwrap() & FLAG_DATA_DESCRIPTOR, 0);
+        assert!(verify_archive(&bytes, None).unwrap().ok);
+
+        let central = find_signature(&bytes, 0, CENTRAL_HEADER_SIGNATURE).unwrap();
+        let report = verify_archive(&bytes[..central], None).unwrap();
+        assert!(report.recovered);
+        assert_eq!(
+            statuses(&report),
+            [("one.txt", EntryStatus::Ok), ("two.txt", EntryStatus::Ok)]
+        );
+        assert_eq!(report.entries[1].uncompressed_size, 700);
+    }
+}
@@ -0,0 +1,1537 @@
+use std::borrow::Cow;
+use std::io::{Cursor, Seek, Write};
+
+use serde::Deserialize;
+use zip::write::{FileOptions, FullFileOptions, StreamWriter};
+use zip::{AesMode, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::dedupe::{DuplicateReport, Duplicates, DUPLICATES_JSON};
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::manifest::{Algorithm, Digests, Hasher, Manifest, ManifestOptions};
+use crate::path::{sanitize_name, NameChange, NameNormalizer, NamePolicy};
+use crate::progress::{write_sliced, Progress};
+use crate::sniff::is_precompressed;
+use crate::time::{now_millis, unix_millis_to_dos};
+use crate::{staged, zipcrypto};
+
+pub(crate) const DEFAULT_FILE_PERMISSIONS: u32 = 0o644;
+pub(crate) const DEFAULT_DIR_PERMISSIONS: u32 = 0o755;
+pub(crate) const SYMLINK_PERMISSIONS: u32 = 0o777;
+/// Salt, password verifier and authentication code added to every AES-256 entry.
+const AES256_OVERHEAD: u64 = 16 + 2 + 10;
+/// Files at least this large get Zip64 size fields. It sits below 4 GiB to leave
+/// headroom for deflate expansion and encryption overhead on incompressible data.
+const ZIP64_SIZE_THRESHOLD: u64 = 0xF000_0000;
+/// Modification time of every entry in deterministic archives without a
+/// `source_date_epoch`: 1980-01-01T00:00:00Z, the earliest DOS timestamp.
+const DETERMINISTIC_MTIME: f64 = 315_532_800_000.0;
+/// Header ID of the "UT" extended timestamp extra field.
+const EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+
+/// How an entry's data is stored in the archive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    Stored,
+    #[default]
+    Deflated,
+    /// Store formats that are already compressed (sniffed from magic bytes) and
+    /// fall back to `Stored` whenever deflate output would be larger than the input.
+    Auto,
+    /// Method 93. Opened by 7-Zip and recent Info-ZIP builds.
+    Zstd,
+    /// Method 12.
+    Bzip2,
+    /// Method 95. The `zip` crate can only decode method 14 (LZMA), so xz is the
+    /// LZMA-family method used for writing.
+    Xz,
+}
+
+impl Method {
+    /// Accepted levels and the error for anything outside them. `Stored` and `Auto`
+    /// take deflate levels, which only apply when they end up deflating.
+    fn levels(self) -> (u32, u32, &'static str) {
+        match self {
+            Method::Stored | Method::Deflated | Method::Auto => (0, 9, "deflate level must be 0-9"),
+            Method::Zstd => (1, 22, "zstd level must be 1-22"),
+            Method::Bzip2 => (1, 9, "bzip2 level must be 1-9"),
+            Method::Xz => (0, 9, "xz level must be 0-9"),
+        }
+    }
+}
+
+/// Cipher used when an entry has a password.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    /// WinZip AES-256 (AE-2).
+    #[default]
+    Aes256,
+    /// Legacy PKWARE encryption. Weak, but readable by every unzip tool.
+    ZipCrypto,
+}
+
+/// Per-entry options accepted from JS. Missing fields fall back to the defaults `zip_files` uses.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EntryOptions {
+    /// Unix permission bits, e.g. `0o755`. Defaults to `0o644` for files and `0o755` for
+    /// directories. File-type bits are ignored, so a full `st_mode` can be passed as is.
+    pub unix_permissions: Option<u32>,
+    pub method: Method,
+    /// Compression level, from fastest to smallest: 0-9 for deflate and xz, 1-9 for
+    /// bzip2 and 1-22 for zstd. Defaults to each method's own default.
+    pub level: Option<u32>,
+    /// Encrypt the entry with this password. Directories are never encrypted.
+    pub password: Option<String>,
+    pub encryption: Encryption,
+    /// Expected size of a file streamed with `start_file`. Streamed files without a
+    /// hint reserve Zip64 size fields so they can grow past 4 GiB.
+    pub size_hint: Option<u64>,
+    /// Modification time in milliseconds since the Unix epoch, as in `File.lastModified`.
+    /// Defaults to the current time.
+    pub last_modified: Option<f64>,
+    pub comment: Option<String>,
+}
+
+/// Archive-wide options accepted from JS.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ArchiveOptions {
+    pub comment: Option<String>,
+    /// Make identical inputs produce identical bytes: entries are sorted by name, every
+    /// timestamp is fixed and permissions are normalized to `0o644` or `0o755`.
+    /// Encrypted entries are rejected because their salts are random.
+    pub deterministic: bool,
+    /// Timestamp in seconds since the Unix epoch for every entry, as in the
+    /// `SOURCE_DATE_EPOCH` convention. Setting it turns on `deterministic`.
+    pub source_date_epoch: Option<f64>,
+    /// `.gitignore`-style rules; when any are given, only matching entries are added.
+    pub include: Vec<String>,
+    /// `.gitignore`-style rules for entries to leave out.
+    pub exclude: Vec<String>,
+    /// Leave out `.DS_Store`, `Thumbs.db`, `__MACOSX/`, `node_modules/` and similar
+    /// clutter, as listed in [`crate::filter::JUNK_RULES`].
+    pub skip_junk: bool,
+    /// How unportable and colliding entry names are handled. See [`NamePolicy`].
+    pub name_policy: NamePolicy,
+    /// Add a checksum manifest of every file. See [`ManifestOptions`].
+    pub manifest: Option<ManifestOptions>,
+    /// Store files with the same contents only once and list the left-out copies in
+    /// `duplicates.json`. See [`crate::dedupe`]. Not allowed in deterministic archives.
+    pub dedupe_content: bool,
+}
+
+impl ArchiveOptions {
+    fn deterministic(&self) -> bool {
+        self.deterministic || self.source_date_epoch.is_some()
+    }
+
+    /// `options` as they apply in this archive, with the fields deterministic mode fixes
+    /// replaced.
+    pub fn entry_options<'a>(
+        &self,
+        options: &'a EntryOptions,
+        is_dir: bool,
+    ) -> Result<Cow<'a, EntryOptions>> {
+        if !self.deterministic() {
+            return Ok(Cow::Borrowed(options));
+        }
+        if options.password.is_some() && !is_dir {
+            return Err(Error::InvalidOption(
+                "deterministic archives cannot contain encrypted entries",
+            ));
+        }
+        let executable = options
+            .unix_permissions
+            .is_some_and(|mode| mode & 0o111 != 0);
+        let permissions = if is_dir || executable {
+            DEFAULT_DIR_PERMISSIONS
+        } else {
+            DEFAULT_FILE_PERMISSIONS
+        };
+        let mtime = self
+            .source_date_epoch
+            .map_or(DETERMINISTIC_MTIME, |seconds| seconds * 1_000.0);
+        Ok(Cow::Owned(EntryOptions {
+            unix_permissions: Some(permissions),
+            last_modified: Some(mtime),
+            ..options.clone()
+        }))
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if matches!(&self.comment, Some(comment) if comment.len() > usize::from(u16::MAX)) {
+            return Err(Error::InvalidOption(
+                "archive comments are limited to 65,535 bytes",
+            ));
+        }
+        // Which copy is kept depends on the order files arrive in, which sorting by
+        // name does not undo.
+        if self.dedupe_content && self.deterministic() {
+            return Err(Error::InvalidOption(
+                "deterministic archives cannot dedupe content",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl EntryOptions {
+    fn validate(&self) -> Result<()> {
+        if let Some(level) = self.level {
+            let (min, max, message) = self.method.levels();
+            if !(min..=max).contains(&level) {
+                return Err(Error::InvalidOption(message));
+            }
+        }
+        if self.password.as_deref() == Some("") {
+            return Err(Error::InvalidOption("password cannot be empty"));
+        }
+        if matches!(&self.comment, Some(comment) if comment.len() > usize::from(u16::MAX)) {
+            return Err(Error::InvalidOption(
+                "entry comments are limited to 65,535 bytes",
+            ));
+        }
+        Ok(())
+    }
+
+    fn zipcrypto_password(&self) -> Option<&str> {
+        match self.encryption {
+            Encryption::ZipCrypto => self.password.as_deref(),
+            Encryption::Aes256 => None,
+        }
+    }
+
+    /// Whether the entry has to go through a staging archive to be patched before merging.
+    fn needs_staging(&self) -> bool {
+        self.zipcrypto_password().is_some() || self.comment.is_some()
+    }
+
+    /// Whether an entry of `size` bytes (unknown when `None`) needs Zip64 size fields.
+    /// ZipCrypto entries are capped at 4 GiB and never use them.
+    fn needs_zip64(&self, size: Option<u64>) -> bool {
+        self.zipcrypto_password().is_none() && size.is_none_or(|size| size >= ZIP64_SIZE_THRESHOLD)
+    }
+
+    /// Bytes the cipher adds on top of the compressed data inside the staging archive.
+    fn staged_overhead(&self) -> u64 {
+        match (&self.password, self.encryption) {
+            (Some(_), Encryption::Aes256) => AES256_OVERHEAD,
+            _ => 0,
+        }
+    }
+}
+
+/// The file entry currently accepting `write_chunk` data.
+enum OpenEntry {
+    Closed,
+    /// Streaming straight into the output archive.
+    Direct,
+    /// An `Auto` entry whose method is decided once its first chunk arrives.
+    Pending {
+        name: String,
+        options: EntryOptions,
+        large: bool,
+    },
+    /// A file left out by the archive's filter rules; its chunks are dropped.
+    Skipped,
+    /// A ZipCrypto or commented entry buffered in a one-entry archive until it is complete.
+    Staged {
+        writer: Box<ZipWriter<Cursor<Vec<u8>>>>,
+        options: EntryOptions,
+    },
+}
+
+/// Incremental ZIP writer shared by `zip_files` and the JS `ZipBuilder`.
+///
+/// Files can be added whole with [`ArchiveWriter::add_file`] or streamed with
+/// [`ArchiveWriter::start_file`] followed by any number of [`ArchiveWriter::write_chunk`] calls.
+/// The archive is built in memory unless it is created with [`ArchiveWriter::streaming`].
+pub struct ArchiveWriter<W: Write + Seek = Cursor<Vec<u8>>> {
+    writer: ZipWriter<W>,
+    /// Whether `writer` passes its output on as it goes, so nothing written can be
+    /// patched later.
+    streaming: bool,
+    open: OpenEntry,
+    entries: usize,
+    /// Set once an entry comment needs the UTF-8 flag patched in by `finish`.
+    utf8_comments: bool,
+    progress: Option<Progress>,
+    options: ArchiveOptions,
+    filter: Filter,
+    /// Names of the entries left out by `filter`.
+    skipped: Vec<String>,
+    names: NameNormalizer,
+    manifest: Option<Manifest>,
+    /// The streamed entry being hashed for `manifest`.
+    hashing: Option<(String, Hasher)>,
+    /// Contents of the files added so far, when `dedupe_content` is set.
+    duplicates: Option<Duplicates>,
+}
+
+impl Default for ArchiveWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArchiveWriter {
+    pub fn new() -> Self {
+        Self::from_zip(ZipWriter::new(Cursor::new(Vec::new())))
+    }
+
+    pub fn with_options(options: &ArchiveOptions) -> Result<Self> {
+        Self::new().configure(options)
+    }
+
+    /// Write the central directory and return the finished archive bytes.
+    pub fn finish(self) -> Result<Vec<u8>> {
+        let utf8_comments = self.utf8_comments;
+        let deterministic = self.options.deterministic();
+        let mut bytes = self.complete()?.into_inner();
+        if utf8_comments {
+            let headers: Vec<(u64, u64)> = {
+                let mut archive = ZipArchive::new(Cursor::new(bytes.as_slice()))?;
+                (0..archive.len())
+                    .map(|idx| {
+                        let file = archive.by_index_raw(idx)?;
+                        Ok((file.header_start(), file.central_header_start()))
+                    })
+                    .collect::<Result<_>>()?
+            };
+            for (local, central) in headers {
+                staged::flag_utf8_comment(&mut bytes, local as usize, central as usize)?;
+            }
+        }
+        if deterministic {
+            bytes = sort_entries(&bytes)?;
+        }
+        Ok(bytes)
+    }
+}
+
+impl<W: Write> ArchiveWriter<StreamWriter<W>> {
+    /// Write the archive to `out` as it is produced instead of keeping it in memory.
+    /// Sizes and checksums follow each entry's data in a data descriptor, since the
+    /// header before it has already been written. AES-encrypted and commented entries
+    /// are still compressed in memory one at a time. Deterministic archives and
+    /// non-ASCII entry comments need the whole archive and are rejected.
+    pub fn streaming(out: W, options: &ArchiveOptions) -> Result<Self> {
+        if options.deterministic() {
+            return Err(Error::InvalidOption(
+                "deterministic archives cannot be streamed",
+            ));
+        }
+        let mut archive = Self::from_zip(ZipWriter::new_stream(out));
+        archive.streaming = true;
+        archive.configure(options)
+    }
+
+    /// Write the central directory and return `out`.
+    pub fn finish_stream(self) -> Result<W> {
+        Ok(self.complete()?.into_inner())
+    }
+}
+
+impl<W: Write + Seek> ArchiveWriter<W> {
+    fn from_zip(writer: ZipWriter<W>) -> Self {
+        Self {
+            writer,
+            streaming: false,
+            open: OpenEntry::Closed,
+            entries: 0,
+            utf8_comments: false,
+            progress: None,
+            options: ArchiveOptions::default(),
+            filter: Filter::default(),
+            skipped: Vec::new(),
+            names: NameNormalizer::default(),
+            manifest: None,
+            hashing: None,
+            duplicates: None,
+        }
+    }
+
+    fn configure(mut self, options: &ArchiveOptions) -> Result<Self> {
+        options.validate()?;
+        if let Some(comment) = &options.comment {
+            self.writer.set_comment(comment.as_str());
+        }
+        self.options = options.clone();
+        self.filter = Filter::new(&options.include, &options.exclude, options.skip_junk)?;
+        self.names = NameNormalizer::new(options.name_policy);
+        self.manifest = options.manifest.as_ref().map(Manifest::new).transpose()?;
+        self.duplicates = options.dedupe_content.then(Duplicates::new);
+        Ok(self)
+    }
+
+    /// Number of entries (files and directories) added so far.
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// Names of the entries the archive's filter rules left out, in the order they
+    /// were offered.
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    /// Every entry name the name policy changed, in the order they were added.
+    pub fn name_changes(&self) -> &[NameChange] {
+        self.names.changes()
+    }
+
+    /// Files left out because an earlier file has the same contents, when the archive
+    /// was created with `dedupe_content`.
+    pub fn duplicates(&self) -> DuplicateReport {
+        self.duplicates
+            .as_ref()
+            .map(Duplicates::report)
+            .unwrap_or_default()
+    }
+
+    /// [`ArchiveWriter::admit`] for the whole file `data`, which is also left out when
+    /// `dedupe_content` finds the same contents earlier in the archive. The bytes of a
+    /// file left out count as processed, so progress still reaches the total.
+    pub fn admit_file(&mut self, name: &str, data: &[u8]) -> Result<Option<String>> {
+        match self.admit(name, false)? {
+            Some(name) if !self.is_duplicate(&name, data) => Ok(Some(name)),
+            _ => {
+                self.skip_bytes(data.len())?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Whether the file `name`, as [`ArchiveWriter::admit`] returned it, repeats the
+    /// contents of an earlier file and is to be left out. Always false unless the
+    /// archive was created with `dedupe_content`.
+    fn is_duplicate(&mut self, name: &str, data: &[u8]) -> bool {
+        self.duplicates
+            .as_mut()
+            .is_some_and(|duplicates| duplicates.check(name, data).is_some())
+    }
+
+    /// The name to store the entry `name` under, or `None` when the filter rules or the
+    /// name policy leave it out. Both outcomes are recorded, for
+    /// [`ArchiveWriter::skipped`] and [`ArchiveWriter::name_changes`].
+    pub fn admit(&mut self, name: &str, is_dir: bool) -> Result<Option<String>> {
+        if !self.filter.is_empty() {
+            let cleaned = self.names.clean(name, is_dir)?;
+            if self.filter.skips(&cleaned, is_dir) {
+                self.skipped.push(cleaned);
+                return Ok(None);
+            }
+        }
+        self.names.normalize(name, is_dir)
+    }
+
+    /// Report entry starts and consumed input bytes to `progress` from now on.
+    pub fn set_progress(&mut self, progress: Progress) {
+        self.progress = Some(progress);
+    }
+
+    /// Begin a new file entry; its data is supplied by subsequent `write_chunk` calls.
+    ///
+    /// With [`Method::Auto`] the method is chosen by sniffing the first chunk. The
+    /// larger-than-input fallback needs the whole file and only applies to `add_file`,
+    /// as does leaving out duplicate contents.
+    pub fn start_file(&mut self, name: &str, options: &EntryOptions) -> Result<()> {
+        let Some(name) = self.admit(name, false)? else {
+            self.close_entry()?;
+            self.open = OpenEntry::Skipped;
+            return Ok(());
+        };
+        if let Some(duplicates) = &mut self.duplicates {
+            duplicates.record(&name);
+        }
+        let options = self.options.entry_options(options, false)?;
+        self.begin_file(&name, &options, options.size_hint)?;
+        self.start_hashing(&name);
+        Ok(())
+    }
+
+    /// Append bytes to the file most recently started with `start_file`.
+    pub fn write_chunk(&mut self, chunk: &[u8]) -> Result<()> {
+        if let OpenEntry::Pending { .. } = self.open {
+            let OpenEntry::Pending {
+                name,
+                options,
+                large,
+            } = std::mem::replace(&mut self.open, OpenEntry::Closed)
+            else {
+                unreachable!()
+            };
+            self.open_entry(name, &options, sniffed_method(chunk), large)?;
+        }
+
+        let writer = match &mut self.open {
+            OpenEntry::Closed => return Err(Error::NoOpenEntry),
+            OpenEntry::Skipped => return self.skip_bytes(chunk.len()),
+            OpenEntry::Direct => &mut self.writer as &mut dyn Write,
+            OpenEntry::Staged { writer, .. } => writer.as_mut(),
+            OpenEntry::Pending { .. } => unreachable!("pending entries are opened above"),
+        };
+        write_sliced(chunk, &mut self.progress, |slice| {
+            Ok(writer.write_all(slice)?)
+        })?;
+        if let Some((_, hasher)) = &mut self.hashing {
+            hasher.update(chunk);
+        }
+        Ok(())
+    }
+
+    /// Add a complete file in one call.
+    pub fn add_file(&mut self, name: &str, data: &[u8], options: &EntryOptions) -> Result<()> {
+        let Some(name) = self.admit_file(name, data)? else {
+            return Ok(());
+        };
+        let options = &*self.options.entry_options(options, false)?;
+        if options.method != Method::Auto || is_precompressed(data) {
+            self.begin_file(&name, options, Some(data.len() as u64))?;
+            self.start_hashing(&name);
+            self.write_chunk(data)?;
+            return self.close_entry();
+        }
+
+        options.validate()?;
+        self.close_entry()?;
+        self.report_entry(&name)?;
+        let staged = stage_file(&name, options, data, &mut self.progress)?;
+        self.commit_staged(staged, options)?;
+        if let Some(manifest) = &mut self.manifest {
+            let digests = Hasher::digest(manifest.algorithms(), data);
+            manifest.push(&name, data.len() as u64, digests);
+        }
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Append a file compressed ahead of time with [`StagedFile::new`]. In deterministic
+    /// archives it must have been staged with [`ArchiveOptions::entry_options`].
+    pub fn add_staged(&mut self, file: StagedFile) -> Result<()> {
+        self.close_entry()?;
+        self.report_entry(&file.name)?;
+        self.commit_staged(file.staged, &file.options)?;
+        if let Some(manifest) = &mut self.manifest {
+            let digests = file.digests.ok_or(Error::Internal(
+                "staged file was not hashed for the manifest",
+            ))?;
+            manifest.push(&file.name, file.size as u64, *digests);
+        }
+        if let Some(progress) = &mut self.progress {
+            progress.advance(file.size)?;
+        }
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Add an explicit directory entry. A trailing `/` is appended if missing.
+    pub fn add_directory(&mut self, name: &str, options: &EntryOptions) -> Result<()> {
+        match self.admit(name, true)? {
+            Some(name) => self.add_admitted_directory(name, options),
+            None => Ok(()),
+        }
+    }
+
+    /// Add a directory under a name [`ArchiveWriter::admit`] already returned.
+    pub(crate) fn add_admitted_directory(
+        &mut self,
+        name: String,
+        options: &EntryOptions,
+    ) -> Result<()> {
+        let options = &*self.options.entry_options(options, true)?;
+        options.validate()?;
+        self.close_entry()?;
+        self.report_entry(&name)?;
+        let zip_options = plain_options(options, Method::Stored, DEFAULT_DIR_PERMISSIONS)?;
+
+        if options.comment.is_some() {
+            let options = EntryOptions {
+                password: None,
+                ..options.clone()
+            };
+            let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+            writer.add_directory(name, zip_options)?;
+            self.commit_staged(writer.finish()?.into_inner(), &options)?;
+        } else {
+            self.writer.add_directory(name, zip_options)?;
+        }
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Add a symbolic link to `target`. As Unix `zip` stores them, the target is the
+    /// entry's data and the file type in its mode marks it as a link. Links are never
+    /// encrypted and do not appear in the manifest.
+    pub fn add_symlink(&mut self, name: &str, target: &str, options: &EntryOptions) -> Result<()> {
+        if target.is_empty() {
+            return Err(Error::InvalidOption("link target cannot be empty"));
+        }
+        let Some(name) = self.admit(name, false)? else {
+            return Ok(());
+        };
+        let options = EntryOptions {
+            password: None,
+            unix_permissions: Some(SYMLINK_PERMISSIONS),
+            ..options.clone()
+        };
+        let options = &*self.options.entry_options(&options, false)?;
+        options.validate()?;
+        self.close_entry()?;
+        self.report_entry(&name)?;
+        let zip_options = plain_options(options, Method::Stored, SYMLINK_PERMISSIONS)?;
+
+        if options.comment.is_some() {
+            let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+            writer.add_symlink(name, target, zip_options)?;
+            self.commit_staged(writer.finish()?.into_inner(), options)?;
+        } else {
+            self.writer.add_symlink(name, target, zip_options)?;
+        }
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Close the last entry, append `duplicates.json` and the manifest and write the
+    /// central directory.
+    fn complete(mut self) -> Result<W> {
+        self.close_entry()?;
+        // These files are not part of the caller's input, so progress does not see them.
+        let progress = self.progress.take();
+        let duplicates = self
+            .duplicates
+            .as_ref()
+            .map(Duplicates::render)
+            .transpose()?;
+        if let Some(json) = duplicates.flatten() {
+            // Listed in the manifest like any other file, so it verifies as part of the
+            // archive.
+            self.add_generated(DUPLICATES_JSON, &json, true)?;
+        }
+        if let Some(mut manifest) = self.manifest.take() {
+            if self.options.deterministic() {
+                manifest.sort();
+            }
+            for (name, data) in manifest.render()? {
+                self.add_generated(&name, &data, false)?;
+            }
+        }
+        self.progress = progress;
+        if let Some(progress) = &mut self.progress {
+            progress.finish()?;
+        }
+        Ok(self.writer.finish()?)
+    }
+
+    /// Add a file the writer produced itself, hashing it for the manifest if `hashed`.
+    fn add_generated(&mut self, name: &str, data: &[u8], hashed: bool) -> Result<()> {
+        let defaults = EntryOptions::default();
+        let options = self.options.entry_options(&defaults, false)?;
+        self.begin_file(name, &options, Some(data.len() as u64))?;
+        if hashed {
+            self.start_hashing(name);
+        }
+        self.write_chunk(data)?;
+        self.close_entry()
+    }
+
+    /// Shared by `start_file` and `add_file`; `size` decides whether Zip64 fields are reserved.
+    fn begin_file(&mut self, name: &str, options: &EntryOptions, size: Option<u64>) -> Result<()> {
+        options.validate()?;
+        let name = sanitize_name(name)?;
+        self.close_entry()?;
+        self.report_entry(&name)?;
+
+        let large = options.needs_zip64(size);
+        if options.method == Method::Auto {
+            self.open = OpenEntry::Pending {
+                name,
+                options: options.clone(),
+                large,
+            };
+        } else {
+            self.open_entry(name, options, options.method, large)?;
+        }
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Hash the data of the entry `name` for the manifest until the entry is closed.
+    fn start_hashing(&mut self, name: &str) {
+        self.hashing = self
+            .manifest
+            .as_ref()
+            .map(|manifest| (name.to_string(), Hasher::new(manifest.algorithms())));
+    }
+
+    /// Algorithms the manifest needs digests for; empty when there is no manifest.
+    /// Files staged with [`StagedFile::hashed`] must use these.
+    pub fn manifest_algorithms(&self) -> &[Algorithm] {
+        self.manifest
+            .as_ref()
+            .map_or(&[], |manifest| manifest.algorithms())
+    }
+
+    /// Count the input of a skipped file as processed, so progress still reaches the total.
+    fn skip_bytes(&mut self, len: usize) -> Result<()> {
+        match &mut self.progress {
+            Some(progress) => progress.advance(len),
+            None => Ok(()),
+        }
+    }
+
+    /// Tell the progress callback that the next entry has begun.
+    fn report_entry(&mut self, name: &str) -> Result<()> {
+        match &mut self.progress {
+            Some(progress) => progress.start_entry(self.entries, name),
+            None => Ok(()),
+        }
+    }
+
+    /// Start writing an entry whose method has been resolved.
+    fn open_entry(
+        &mut self,
+        name: String,
+        options: &EntryOptions,
+        method: Method,
+        large: bool,
+    ) -> Result<()> {
+        let zip_options =
+            file_options(options, method, DEFAULT_FILE_PERMISSIONS)?.large_file(large);
+        // AES patches its header once the entry is complete, which a stream cannot do.
+        if options.needs_staging() || (self.streaming && options.password.is_some()) {
+            let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+            writer.start_file(name, zip_options)?;
+            self.open = OpenEntry::Staged {
+                writer: Box::new(writer),
+                options: options.clone(),
+            };
+        } else {
+            self.writer.start_file(name, zip_options)?;
+            self.open = OpenEntry::Direct;
+        }
+        Ok(())
+    }
+
+    /// Complete the open entry, if any, so the next one can begin.
+    fn close_entry(&mut self) -> Result<()> {
+        if let (Some((name, hasher)), Some(manifest)) = (self.hashing.take(), &mut self.manifest) {
+            let (size, digests) = hasher.finish();
+            manifest.push(&name, size, digests);
+        }
+        match std::mem::replace(&mut self.open, OpenEntry::Closed) {
+            OpenEntry::Closed | OpenEntry::Direct | OpenEntry::Skipped => Ok(()),
+            // A streamed `Auto` entry that never received data becomes an empty stored file.
+            OpenEntry::Pending { name, options, .. } => {
+                self.open_entry(name, &options, Method::Stored, false)?;
+                self.close_entry()
+            }
+            OpenEntry::Staged { writer, options } => {
+                let staged = writer.finish()?.into_inner();
+                self.commit_staged(staged, &options)
+            }
+        }
+    }
+
+    /// Apply the entry comment and ZipCrypto to a staged one-entry archive as needed,
+    /// then append it to the output.
+    fn commit_staged(&mut self, staged: Vec<u8>, options: &EntryOptions) -> Result<()> {
+        let staged = match &options.comment {
+            Some(comment) => {
+                if !comment.is_ascii() && self.streaming {
+                    return Err(Error::InvalidOption(
+                        "streamed archives cannot have non-ASCII entry comments",
+                    ));
+                }
+                self.utf8_comments |= !comment.is_ascii();
+                staged::set_comment(staged, comment)?
+            }
+            None => staged,
+        };
+        let staged = match options.zipcrypto_password() {
+            Some(password) => zipcrypto::encrypt_single_entry(&staged, password.as_bytes())?,
+            None => staged,
+        };
+        self.writer
+            .merge_archive(ZipArchive::new(Cursor::new(staged))?)?;
+        Ok(())
+    }
+}
+
+/// Reassemble `bytes` with its entries in name order. Entries are copied as they are,
+/// so only offsets in the central directory change.
+fn sort_entries(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let comment = archive.comment().to_vec();
+    let mut records = staged::entry_records(&mut archive, bytes)?;
+    records.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut offsets = Vec::with_capacity(records.len());
+    for record in &records {
+        offsets.push(out.len() as u64);
+        out.extend_from_slice(&bytes[record.local.clone()]);
+    }
+    let central = out.len();
+    for (record, offset) in records.iter().zip(offsets) {
+        let header = &bytes[record.central.clone()];
+        out.extend(staged::relocate_central_header(header, None, 0, offset)?);
+    }
+    staged::put_end_of_central_directory(&mut out, central, records.len(), &comment);
+    Ok(out)
+}
+
+/// A file compressed on its own into a one-entry archive, ready to be appended with
+/// [`ArchiveWriter::add_staged`]. Staging needs no writer, so many files can be
+/// compressed at once on different threads.
+pub struct StagedFile {
+    name: String,
+    staged: Vec<u8>,
+    options: EntryOptions,
+    size: usize,
+    digests: Option<Box<Digests>>,
+}
+
+impl StagedFile {
+    /// Compress `data` into the entry [`ArchiveWriter::add_file`] would write.
+    pub fn new(name: &str, data: &[u8], options: &EntryOptions) -> Result<Self> {
+        options.validate()?;
+        let name = sanitize_name(name)?;
+        let staged = stage_file(&name, options, data, &mut None)?;
+        Ok(Self {
+            name,
+            staged,
+            options: options.clone(),
+            size: data.len(),
+            digests: None,
+        })
+    }
+
+    /// Attach the digests of `data`, the file's contents, for archives with a manifest.
+    pub fn hashed(mut self, data: &[u8], algorithms: &[Algorithm]) -> Self {
+        if !algorithms.is_empty() {
+            self.digests = Some(Box::new(Hasher::digest(algorithms, data)));
+        }
+        self
+    }
+}
+
+/// Stage `data` with the method `options` asks for, resolving [`Method::Auto`].
+fn stage_file(
+    name: &str,
+    options: &EntryOptions,
+    data: &[u8],
+    progress: &mut Option<Progress>,
+) -> Result<Vec<u8>> {
+    match options.method {
+        Method::Auto if is_precompressed(data) => {
+            stage(name, options, Method::Stored, data, progress)
+        }
+        Method::Auto => {
+            // Deflate first so the result can be measured, and only store the data
+            // again when deflate made it larger.
+            let deflated = stage(name, options, Method::Deflated, data, progress)?;
+            let entry_size = {
+                let mut archive = ZipArchive::new(Cursor::new(deflated.as_slice()))?;
+                let entry = archive.by_index_raw(0)?;
+                entry.compressed_size() - options.staged_overhead()
+            };
+            if entry_size < data.len() as u64 {
+                Ok(deflated)
+            } else {
+                stage(name, options, Method::Stored, data, &mut None)
+            }
+        }
+        method => stage(name, options, method, data, progress),
+    }
+}
+
+/// Write `data` as the only entry of a fresh archive. Comments and ZipCrypto are applied
+/// later by `commit_staged`.
+fn stage(
+    name: &str,
+    options: &EntryOptions,
+    method: Method,
+    data: &[u8],
+    progress: &mut Option<Progress>,
+) -> Result<Vec<u8>> {
+    let large = options.needs_zip64(Some(data.len() as u64));
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    writer.start_file(
+        name,
+        file_options(options, method, DEFAULT_FILE_PERMISSIONS)?.large_file(large),
+    )?;
+    write_sliced(data, progress, |slice| Ok(writer.write_all(slice)?))?;
+    Ok(writer.finish()?.into_inner())
+}
+
+fn sniffed_method(data: &[u8]) -> Method {
+    if is_precompressed(data) {
+        Method::Stored
+    } else {
+        Method::Deflated
+    }
+}
+
+/// Build zip options for a concrete method, including AES encryption when a password is set.
+/// `Auto` must be resolved by the caller.
+fn file_options(
+    options: &EntryOptions,
+    method: Method,
+    default_permissions: u32,
+) -> Result<FullFileOptions<'_>> {
+    let zip_options = plain_options(options, method, default_permissions)?;
+    Ok(match (&options.password, options.encryption) {
+        (Some(password), Encryption::Aes256) => {
+            zip_options.with_aes_encryption(AesMode::Aes256, password)
+        }
+        _ => zip_options,
+    })
+}
+
+/// Build zip options for a concrete method without any encryption.
+///
+/// The modification time goes into the DOS fields and, when it fits, into an extended
+/// timestamp field that keeps it in UTC with one-second resolution.
+fn plain_options(
+    options: &EntryOptions,
+    method: Method,
+    default_permissions: u32,
+) -> Result<FullFileOptions<'static>> {
+    let compression = match method {
+        Method::Stored => CompressionMethod::Stored,
+        Method::Deflated | Method::Auto => CompressionMethod::Deflated,
+        Method::Zstd => CompressionMethod::Zstd,
+        Method::Bzip2 => CompressionMethod::Bzip2,
+        Method::Xz => CompressionMethod::Xz,
+    };
+    let level = match method {
+        Method::Stored => None,
+        _ => options.level.map(i64::from),
+    };
+    let last_modified = options.last_modified.unwrap_or_else(now_millis);
+    let mut zip_options = FileOptions::default()
+        .compression_method(compression)
+        .compression_level(level)
+        .unix_permissions(options.unix_permissions.unwrap_or(default_permissions))
+        .last_modified_time(unix_millis_to_dos(last_modified));
+
+    let seconds = (last_modified / 1_000.0).floor();
+    if (0.0..=f64::from(i32::MAX)).contains(&seconds) {
+        let mut field = vec![0x01];
+        field.extend_from_slice(&(seconds as u32).to_le_bytes());
+        zip_options.add_extra_data(EXTENDED_TIMESTAMP_ID, field, false)?;
+    }
+    Ok(zip_options)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::limits::Limits;
+    use crate::progress::{ProgressEvent, SLICE_SIZE};
+    use crate::read;
+    use crate::staged::{read_u16, FLAG_UTF8};
+    use crate::verify::verify_archive;
+
+    #[test]
+    fn streams_chunks_into_one_entry() {
+        let mut writer = ArchiveWriter::new();
+        writer
+            .start_file("big.bin", &EntryOptions::default())
+            .unwrap();
+        for chunk in [b"abc".as_slice(), b"def", b"ghi"] {
+            writer.write_chunk(chunk).unwrap();
+        }
+        writer
+            .add_directory("empty", &EntryOptions::default())
+            .unwrap();
+        writer
+            .add_file("small.txt", b"x", &EntryOptions::default())
+            .unwrap();
+        assert_eq!(writer.len(), 3);
+
+        let bytes = writer.finish().unwrap();
+        let entries = read::extract_all(&bytes, None, &Limits::default()).unwrap();
+        assert_eq!(entries[0].data, b"abcdefghi");
+        assert_eq!(entries[1].name, "empty/");
+        assert!(entries[1].is_dir);
+        assert_eq!(entries[2].data, b"x");
+    }
+
+    #[test]
+    fn rejects_chunk_without_open_file() {
+        let mut writer = ArchiveWriter::new();
+        assert!(matches!(writer.write_chunk(b"x"), Err(Error::NoOpenEntry)));
+
+        writer
+            .add_directory("dir/", &EntryOptions::default())
+            .unwrap();
+        assert!(matches!(writer.write_chunk(b"x"), Err(Error::NoOpenEntry)));
+    }
+
+    #[test]
+    fn auto_mode_stores_incompressible_data() {
+        let auto = EntryOptions {
+            method: Method::Auto,
+            ..EntryOptions::default()
+        };
+        let jpeg = [b"\xFF\xD8\xFF\xE0".as_slice(), &[7; 64]].concat();
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..512)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let text = b"the quick brown fox ".repeat(50);
+
+        let mut writer = ArchiveWriter::new();
+        writer.add_file("photo.jpg", &jpeg, &auto).unwrap();
+        writer.add_file("noise.bin", &noise, &auto).unwrap();
+        writer.add_file("notes.txt", &text, &auto).unwrap();
+        writer.start_file("streamed.jpg", &auto).unwrap();
+        writer.write_chunk(&jpeg).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let methods: Vec<_> = read::list_entries(&bytes)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.method)
+            .collect();
+        assert_eq!(methods, ["stored", "stored", "deflated", "stored"]);
+        assert_eq!(
+            read::extract_entry(&bytes, "notes.txt", None, &Limits::default()).unwrap(),
+            text
+        );
+        assert_eq!(
+            read::extract_entry(&bytes, "noise.bin", None, &Limits::default()).unwrap(),
+            noise
+        );
+    }
+
+    #[test]
+    fn honours_explicit_method_and_level() {
+        let stored = EntryOptions {
+            method: Method::Stored,
+            ..EntryOptions::default()
+        };
+        let mut writer = ArchiveWriter::new();
+        writer.add_file("a.txt", &[b'a'; 100], &stored).unwrap();
+        let bytes = writer.finish().unwrap();
+        assert_eq!(read::list_entries(&bytes).unwrap()[0].compressed_size, 100);
+
+        let too_high = EntryOptions {
+            level: Some(10),
+            ..EntryOptions::default()
+        };
+        let mut writer = ArchiveWriter::new();
+        assert!(matches!(
+            writer.add_file("a.txt", b"a", &too_high),
+            Err(Error::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn writes_zstd_bzip2_and_xz_entries() {
+        let text = b"fn main() { println!(\"hello\"); }\n".repeat(200);
+        let mut writer = ArchiveWriter::new();
+        for (name, method, level) in [
+            ("a.zst.txt", Method::Zstd, Some(19)),
+            ("b.bz2.txt", Method::Bzip2, None),
+            ("c.xz.txt", Method::Xz, Some(9)),
+        ] {
+            let options = EntryOptions {
+                method,
+                level,
+                ..EntryOptions::default()
+            };
+            writer.add_file(name, &text, &options).unwrap();
+        }
+        let streamed = EntryOptions {
+            method: Method::Xz,
+            password: Some("pw".into()),
+            encryption: Encryption::ZipCrypto,
+            ..EntryOptions::default()
+        };
+        writer.start_file("d.xz.txt", &streamed).unwrap();
+        writer.write_chunk(&text[..1000]).unwrap();
+        writer.write_chunk(&text[1000..]).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let entries = read::list_entries(&bytes).unwrap();
+        let methods: Vec<_> = entries.iter().map(|entry| entry.method.as_str()).collect();
+        assert_eq!(methods, ["zstd", "bzip2", "xz", "xz"]);
+        assert!(entries.iter().all(|entry| entry.compressed_size < 1000));
+        for entry in &entries {
+            let password = entry.encrypted.then_some("pw");
+            assert_eq!(
+                read::extract_entry(&bytes, &entry.name, password, &Limits::default()).unwrap(),
+                text
+            );
+        }
+
+        let mut writer = ArchiveWriter::new();
+        for (method, level) in [(Method::Zstd, 0), (Method::Zstd, 23), (Method::Bzip2, 0)] {
+            let options = EntryOptions {
+                method,
+                level: Some(level),
+                ..EntryOptions::default()
+            };
+            assert!(matches!(
+                writer.add_file("a.txt", b"a", &options),
+                Err(Error::InvalidOption(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn encrypts_with_aes_and_zipcrypto() {
+        let text = b"secret notes ".repeat(20);
+        let mut writer = ArchiveWriter::new();
+        for (name, encryption, method) in [
+            ("aes.txt", Encryption::Aes256, Method::Deflated),
+            ("legacy.txt", Encryption::ZipCrypto, Method::Deflated),
+            ("legacy-auto.txt", Encryption::ZipCrypto, Method::Auto),
+        ] {
+            let options = EntryOptions {
+                password: Some("hunter2".into()),
+                encryption,
+                method,
+                ..EntryOptions::default()
+            };
+            writer.add_file(name, &text, &options).unwrap();
+        }
+        writer
+            .start_file(
+                "legacy-streamed.txt",
+                &EntryOptions {
+                    password: Some("hunter2".into()),
+                    encryption: Encryption::ZipCrypto,
+                    ..EntryOptions::default()
+                },
+            )
+            .unwrap();
+        writer.write_chunk(&text[..100]).unwrap();
+        writer.write_chunk(&text[100..]).unwrap();
+        writer
+            .add_file("plain.txt", b"open", &EntryOptions::default())
+            .unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let listing = read::list_entries(&bytes).unwrap();
+        let encrypted: Vec<_> = listing.iter().map(|entry| entry.encrypted).collect();
+        assert_eq!(encrypted, [true, true, true, true, false]);
+
+        for entry in &listing[..4] {
+            assert_eq!(
+                read::extract_entry(&bytes, &entry.name, Some("hunter2"), &Limits::default())
+                    .unwrap(),
+                text
+            );
+            assert!(matches!(
+                read::extract_entry(&bytes, &entry.name, None, &Limits::default()),
+                Err(Error::PasswordRequired(_))
+            ));
+            assert!(matches!(
+                read::extract_entry(&bytes, &entry.name, Some("wrong"), &Limits::default()),
+                Err(Error::InvalidPassword(_))
+            ));
+        }
+        assert_eq!(
+            read::extract_entry(&bytes, "plain.txt", Some("hunter2"), &Limits::default()).unwrap(),
+            b"open"
+        );
+    }
+
+    #[test]
+    fn reports_progress_per_slice_and_aborts_on_callback_error() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&events);
+        let mut writer = ArchiveWriter::new();
+        let big = vec![7u8; SLICE_SIZE * 2 + 10];
+        let total = big.len() as u64 + 3;
+        writer.set_progress(
+            Progress::new(total, move |event: &ProgressEvent| {
+                seen.borrow_mut().push(event.clone());
+                Ok(())
+            })
+            .with_interval(0.0),
+        );
+        let auto = EntryOptions {
+            method: Method::Auto,
+            ..EntryOptions::default()
+        };
+        writer.add_file("big.bin", &big, &auto).unwrap();
+        writer
+            .add_directory("dir", &EntryOptions::default())
+            .unwrap();
+        writer
+            .add_file("dir/small.txt", b"abc", &EntryOptions::default())
+            .unwrap();
+        writer.finish().unwrap();
+
+        let events = events.borrow();
+        let summary: Vec<_> = events
+            .iter()
+            .map(|event| (event.entry_index, event.bytes_processed))
+            .collect();
+        let slices = SLICE_SIZE as u64;
+        assert_eq!(
+            summary,
+            [
+                (0, 0),
+                (0, slices),
+                (0, slices * 2),
+                (0, slices * 2 + 10),
+                (1, slices * 2 + 10),
+                (2, slices * 2 + 10),
+                (2, total),
+                (2, total),
+            ]
+        );
+        assert_eq!(events[5].entry_name, "dir/small.txt");
+
+        let mut writer = ArchiveWriter::new();
+        writer.set_progress(Progress::new(1, |_: &ProgressEvent| {
+            Err(Error::Aborted("cancelled".into()))
+        }));
+        let err = writer
+            .add_file("a.txt", b"a", &EntryOptions::default())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "cancelled");
+    }
+
+    #[test]
+    fn deterministic_archives_are_byte_identical() {
+        let build = |order: &[usize], mtime: f64| {
+            let options = ArchiveOptions {
+                comment: Some("reproducible".into()),
+                source_date_epoch: Some(1_700_000_000.0),
+                manifest: Some(ManifestOptions::default()),
+                ..ArchiveOptions::default()
+            };
+            let mut writer = ArchiveWriter::with_options(&options).unwrap();
+            for &idx in order {
+                let entry = EntryOptions {
+                    method: Method::Auto,
+                    last_modified: Some(mtime),
+                    unix_permissions: Some(if idx == 0 { 0o700 } else { 0o664 }),
+                    ..EntryOptions::default()
+                };
+                match idx {
+                    0 => writer.add_file("bin/run", b"#!/bin/sh\n", &entry),
+                    1 => writer.add_directory("bin", &entry),
+                    _ => {
+                        writer.start_file("readme.md", &entry).unwrap();
+                        writer.write_chunk(&b"docs ".repeat(100))
+                    }
+                }
+                .unwrap();
+            }
+            writer.finish().unwrap()
+        };
+
+        let first = build(&[2, 0, 1], 1_000_000_000_000.0);
+        let second = build(&[1, 0, 2], now_millis());
+        assert_eq!(first, second);
+
+        let entries = read::list_entries(&first).unwrap();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.unix_mode.unwrap() & 0o777))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("SHA256SUMS", 0o644),
+                ("bin/", 0o755),
+                ("bin/run", 0o755),
+                ("readme.md", 0o644)
+            ]
+        );
+        assert!(entries
+            .iter()
+            .all(|entry| entry.last_modified == 1_700_000_000_000.0));
+        assert_eq!(read::archive_comment(&first).unwrap(), "reproducible");
+        assert!(verify_archive(&first, None).unwrap().ok);
+
+        let mut writer = ArchiveWriter::with_options(&ArchiveOptions {
+            deterministic: true,
+            ..ArchiveOptions::default()
+        })
+        .unwrap();
+        let locked = EntryOptions {
+            password: Some("pw".into()),
+            ..EntryOptions::default()
+        };
+        assert!(matches!(
+            writer.add_file("secret.txt", b"x", &locked),
+            Err(Error::InvalidOption(_))
+        ));
+        writer
+            .add_file("a.txt", b"a", &EntryOptions::default())
+            .unwrap();
+        let bytes = writer.finish().unwrap();
+        let entry = &read::list_entries(&bytes).unwrap()[0];
+        assert_eq!(entry.last_modified, DETERMINISTIC_MTIME);
+    }
+
+    #[test]
+    fn filters_leave_out_matching_entries() {
+        let mut writer = ArchiveWriter::with_options(&ArchiveOptions {
+            exclude: vec!["*.log".into(), "!keep.log".into()],
+            skip_junk: true,
+            dedupe_content: true,
+            ..ArchiveOptions::default()
+        })
+        .unwrap();
+        let processed = Rc::new(RefCell::new(0));
+        let seen = Rc::clone(&processed);
+        writer.set_progress(
+            Progress::new(49, move |event: &ProgressEvent| {
+                *seen.borrow_mut() = event.bytes_processed;
+                Ok(())
+            })
+            .with_interval(0.0),
+        );
+        let options = EntryOptions::default();
+        writer
+            .add_file("src/main.rs", b"fn main() {}", &options)
+            .unwrap();
+        writer
+            .add_file("src/copy.rs", b"fn main() {}", &options)
+            .unwrap();
+        writer.add_file("src/.DS_Store", b"junk", &options).unwrap();
+        writer.add_directory("__MACOSX", &options).unwrap();
+        writer
+            .add_file("__MACOSX/._main.rs", b"junk", &options)
+            .unwrap();
+        writer.start_file("build.log", &options).unwrap();
+        writer.write_chunk(b"skipped chunk").unwrap();
+        writer.add_file("keep.log", b"kept", &options).unwrap();
+        writer
+            .add_file("/web/node_modules/x/index.js", b"", &options)
+            .unwrap();
+
+        assert_eq!(
+            writer.skipped(),
+            [
+                "src/.DS_Store",
+                "__MACOSX/",
+                "__MACOSX/._main.rs",
+                "build.log",
+                "web/node_modules/x/index.js"
+            ]
+        );
+        // Left-out files count as processed before `finish` fills in the rest.
+        assert_eq!(*processed.borrow(), 49);
+        let bytes = writer.finish().unwrap();
+        let names: Vec<_> = read::list_entries(&bytes)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, ["src/main.rs", "keep.log", "duplicates.json"]);
+
+        assert!(ArchiveWriter::with_options(&ArchiveOptions {
+            include: vec!["[a-".into()],
+            ..ArchiveOptions::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn applies_the_name_policy() {
+        let mut writer = ArchiveWriter::with_options(&ArchiveOptions {
+            name_policy: NamePolicy::Rename,
+            skip_junk: true,
+            ..ArchiveOptions::default()
+        })
+        .unwrap();
+        let options = EntryOptions::default();
+        writer.add_file("Notes.txt", b"one", &options).unwrap();
+        writer.add_file("notes.TXT", b"two", &options).unwrap();
+        writer.start_file("C:\\logs\\aux.log", &options).unwrap();
+        writer.write_chunk(b"three").unwrap();
+        writer.add_directory("Logs", &options).unwrap();
+        writer.add_file("logs/.DS_Store", b"", &options).unwrap();
+
+        let changes: Vec<_> = writer
+            .name_changes()
+            .iter()
+            .map(|change| (change.original.as_str(), change.name.as_deref()))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("notes.TXT", Some("notes (2).TXT")),
+                ("C:\\logs\\aux.log", Some("logs/aux_.log")),
+            ]
+        );
+        assert_eq!(writer.skipped(), ["logs/.DS_Store"]);
+        let bytes = writer.finish().unwrap();
+        let extracted = read::extract_all(&bytes, None, &Limits::default()).unwrap();
+        let entries: Vec<_> = extracted
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.data.as_slice()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("Notes.txt", b"one".as_slice()),
+                ("notes (2).TXT", b"two"),
+                ("logs/aux_.log", b"three"),
+                ("Logs/", b""),
+            ]
+        );
+
+        let mut writer = ArchiveWriter::with_options(&ArchiveOptions {
+            name_policy: NamePolicy::Reject,
+            ..ArchiveOptions::default()
+        })
+        .unwrap();
+        writer.add_file("a.txt", b"", &options).unwrap();
+        assert!(matches!(
+            writer.add_file("A.txt", b"", &options),
+            Err(Error::NameCollision(_))
+        ));
+    }
+
+    #[test]
+    fn preserves_metadata_and_comments() {
+        // 2021-06-15T08:09:11.500Z: odd seconds only survive in the extended timestamp.
+        let mtime = 1_623_744_551_500.0;
+        let script = EntryOptions {
+            unix_permissions: Some(0o100_755),
+            last_modified: Some(mtime),
+            comment: Some("entry point".into()),
+            ..EntryOptions::default()
+        };
+        let locked = EntryOptions {
+            password: Some("hunter2".into()),
+            encryption: Encryption::ZipCrypto,
+            comment: Some("geheim – nur intern".into()),
+            ..EntryOptions::default()
+        };
+        let dir = EntryOptions {
+            last_modified: Some(mtime),
+            comment: Some("assets".into()),
+            ..EntryOptions::default()
+        };
+
+        let mut writer = ArchiveWriter::with_options(&ArchiveOptions {
+            comment: Some("release build".into()),
+            ..ArchiveOptions::default()
+        })
+        .unwrap();
+        writer.add_file("run.sh", b"#!/bin/sh\n", &script).unwrap();
+        writer.add_file("key.txt", b"1234", &locked).unwrap();
+        writer.add_directory("assets", &dir).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        assert_eq!(read::archive_comment(&bytes).unwrap(), "release build");
+        let listing = read::list_entries(&bytes).unwrap();
+        assert_eq!(listing[0].last_modified, 1_623_744_551_000.0);
+        assert_eq!(listing[0].unix_mode, Some(0o100_755));
+        assert_eq!(listing[0].comment, "entry point");
+        assert_eq!(listing[1].comment, "geheim – nur intern");
+        assert!(listing[1].encrypted);
+        assert_eq!(listing[2].unix_mode, Some(0o040_755));
+        assert_eq!(listing[2].comment, "assets");
+        assert_eq!(
+            read::extract_entry(&bytes, "key.txt", Some("hunter2"), &Limits::default()).unwrap(),
+            b"1234"
+        );
+    }
+
+    #[test]
+    fn flags_utf8_comments_in_both_headers() {
+        let commented = EntryOptions {
+            comment: Some("ünï".into()),
+            ..EntryOptions::default()
+        };
+        for options in [
+            ArchiveOptions::default(),
+            ArchiveOptions {
+                deterministic: true,
+                manifest: Some(ManifestOptions::default()),
+                ..ArchiveOptions::default()
+            },
+        ] {
+            let mut writer = ArchiveWriter::with_options(&options).unwrap();
+            writer.add_file("b.txt", b"plain", &commented).unwrap();
+            writer
+                .add_file("a.txt", b"other", &EntryOptions::default())
+                .unwrap();
+            let bytes = writer.finish().unwrap();
+
+            let mut archive = ZipArchive::new(Cursor::new(bytes.as_slice())).unwrap();
+            for idx in 0..archive.len() {
+                let file = archive.by_index_raw(idx).unwrap();
+                let local = read_u16(&bytes, file.header_start() as usize + 6).unwrap();
+                let central = read_u16(&bytes, file.central_header_start() as usize + 8).unwrap();
+                assert_eq!(local & FLAG_UTF8, central & FLAG_UTF8, "{}", file.name());
+                assert_eq!(file.comment() == "ünï", central & FLAG_UTF8 != 0);
+            }
+            assert!(verify_archive(&bytes, None).unwrap().ok);
+        }
+    }
+
+    #[test]
+    fn writes_zip64_for_many_entries() {
+        let mut writer = ArchiveWriter::new();
+        for idx in 0..70_000 {
+            writer
+                .add_file(&format!("f{idx}"), &[], &EntryOptions::default())
+                .unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+
+        let entries = read::list_entries(&bytes).unwrap();
+        assert_eq!(entries.len(), 70_000);
+        assert_eq!(entries[69_999].name, "f69999");
+    }
+
+    #[test]
+    fn writes_zip64_for_entries_over_4_gib() {
+        const SIZE: u64 = (1 << 32) + (1 << 20);
+        let chunk = vec![0u8; 1 << 20];
+
+        let mut writer = ArchiveWriter::new();
+        writer
+            .start_file("huge.bin", &EntryOptions::default())
+            .unwrap();
+        for _ in 0..SIZE / chunk.len() as u64 {
+            writer.write_chunk(&chunk).unwrap();
+        }
+        writer
+            .add_file("after.txt", b"tail", &EntryOptions::default())
+            .unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let entries = read::list_entries(&bytes).unwrap();
+        assert_eq!(entries[0].uncompressed_size, SIZE);
+        assert_eq!(
+            read::extract_entry(&bytes, "after.txt", None, &Limits::default()).unwrap(),
+            b"tail"
+        );
+
+        let mut archive = ZipArchive::new(Cursor::new(bytes.as_slice())).unwrap();
+        let copied =
+            std::io::copy(&mut archive.by_index(0).unwrap(), &mut std::io::sink()).unwrap();
+        assert_eq!(copied, SIZE);
+    }
+}
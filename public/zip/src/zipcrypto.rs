@@ -0,0 +1,130 @@
+//! Legacy PKWARE "traditional" encryption (ZipCrypto) for archive output.
+//!
+//! The `zip` crate can read ZipCrypto entries but does not expose a way to write them,
+//! so entries are staged unencrypted in a one-entry archive and encrypted here before
+//! being merged into the output.
+
+use crate::error::{Error, Result};
+use crate::staged::{read_u16, read_u32, write_u16, write_u32, Layout, STAGING_LAYOUT};
+
+const ENCRYPTION_HEADER_LEN: usize = 12;
+const FLAG_ENCRYPTED: u16 = 0x0001;
+const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+fn crc32_update(crc: u32, byte: u8) -> u32 {
+    (crc >> 8) ^ CRC_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize]
+}
+
+/// The three rolling keys of the ZipCrypto stream cipher.
+struct Keys(u32, u32, u32);
+
+impl Keys {
+    fn new(password: &[u8]) -> Self {
+        let mut keys = Keys(0x1234_5678, 0x2345_6789, 0x3456_7890);
+        for &byte in password {
+            keys.update(byte);
+        }
+        keys
+    }
+
+    fn update(&mut self, byte: u8) {
+        self.0 = crc32_update(self.0, byte);
+        self.1 = (self.1.wrapping_add(self.0 & 0xFF))
+            .wrapping_mul(134_775_813)
+            .wrapping_add(1);
+        self.2 = crc32_update(self.2, (self.1 >> 24) as u8);
+    }
+
+    fn stream_byte(&self) -> u8 {
+        let temp = (self.2 | 2) & 0xFFFF;
+        (temp.wrapping_mul(temp ^ 1) >> 8) as u8
+    }
+
+    fn encrypt(&mut self, plain: u8) -> u8 {
+        let cipher = plain ^ self.stream_byte();
+        self.update(plain);
+        cipher
+    }
+}
+
+/// Encrypt the only entry of a freshly written, single-entry archive with ZipCrypto.
+///
+/// The staging archive must have no archive comment, no data descriptor and no Zip64
+/// records, which is what `ZipWriter` produces for a single entry under 4 GiB. An
+/// entry comment set with [`crate::staged::set_comment`] is carried over unchanged.
+pub fn encrypt_single_entry(staged: &[u8], password: &[u8]) -> Result<Vec<u8>> {
+    let layout = Layout::parse(staged)?;
+    let central = layout.central;
+
+    let flags = read_u16(staged, central + 8)?;
+    let crc = read_u32(staged, central + 16)?;
+    let compressed_size = read_u32(staged, central + 20)?;
+    if flags & FLAG_DATA_DESCRIPTOR != 0 {
+        return Err(STAGING_LAYOUT);
+    }
+    if layout.zip64_locator.is_some()
+        || compressed_size == u32::MAX
+        || compressed_size as usize + ENCRYPTION_HEADER_LEN > u32::MAX as usize
+    {
+        return Err(Error::InvalidOption(
+            "ZipCrypto does not support entries larger than 4 GiB; use AES",
+        ));
+    }
+
+    let data_start = layout.data_start;
+    let data_end = data_start + compressed_size as usize;
+    if data_end != central {
+        return Err(STAGING_LAYOUT);
+    }
+
+    let mut out = Vec::with_capacity(staged.len() + ENCRYPTION_HEADER_LEN);
+    out.extend_from_slice(&staged[..data_start]);
+
+    let mut keys = Keys::new(password);
+    let mut header = [0u8; ENCRYPTION_HEADER_LEN];
+    getrandom::fill(&mut header[..ENCRYPTION_HEADER_LEN - 1])
+        .map_err(|_| Error::Internal("no secure random source available"))?;
+    // The final header byte lets readers check the password against the CRC.
+    header[ENCRYPTION_HEADER_LEN - 1] = (crc >> 24) as u8;
+    out.extend(header.iter().map(|&byte| keys.encrypt(byte)));
+    out.extend(
+        staged[data_start..data_end]
+            .iter()
+            .map(|&byte| keys.encrypt(byte)),
+    );
+
+    let central_start = out.len();
+    out.extend_from_slice(&staged[central..]);
+
+    let new_size = compressed_size + ENCRYPTION_HEADER_LEN as u32;
+    write_u16(&mut out, 6, read_u16(staged, 6)? | FLAG_ENCRYPTED);
+    write_u32(&mut out, 18, new_size);
+    write_u16(&mut out, central_start + 8, flags | FLAG_ENCRYPTED);
+    write_u32(&mut out, central_start + 20, new_size);
+    let new_eocd = layout.eocd + ENCRYPTION_HEADER_LEN;
+    write_u32(&mut out, new_eocd + 16, central_start as u32);
+
+    Ok(out)
+}
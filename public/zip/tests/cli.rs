@@ -0,0 +1,171 @@
+//! Golden-file tests for the `compressor` binary. `tests/golden/wasm.mjs` checks the
+//! wasm build against the same files.
+//!
+//! Run with `cargo test --features cli`. After an intended change in output, rewrite
+//! the golden files with `UPDATE_GOLDEN=1 cargo test --features cli --test cli -- --test-threads=1`,
+//! which writes `archive.zip` before the other tests read it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use compressor::write::{ArchiveOptions, ArchiveWriter, EntryOptions};
+
+/// Archive options of the golden archive. Deterministic output makes it independent of
+/// the time and permissions of the checkout.
+const ARCHIVE_OPTIONS: &str =
+    r#"{"deterministic": true, "manifest": {"algorithms": ["sha256", "blake3"]}}"#;
+const ENTRY_OPTIONS: &str = r#"{"method": "deflated", "level": 9}"#;
+
+fn golden(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name)
+}
+
+/// Compare `actual` with a golden file, or rewrite the file when `UPDATE_GOLDEN` is set.
+fn check_golden(name: &str, actual: &[u8]) {
+    let path = golden(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read(&path).unwrap();
+    assert!(
+        expected == actual,
+        "output differs from {}:\n{}",
+        path.display(),
+        String::from_utf8_lossy(actual)
+    );
+}
+
+fn compressor(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_compressor"))
+        .args(args)
+        .current_dir(golden(""))
+        .output()
+        .unwrap()
+}
+
+/// A fresh directory for one test's output.
+fn scratch(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn create_matches_golden_and_library() {
+    let out = scratch("create").join("archive.zip");
+    let status = compressor(&[
+        "create",
+        out.to_str().unwrap(),
+        "input",
+        "--options",
+        ENTRY_OPTIONS,
+        "--archive-options",
+        ARCHIVE_OPTIONS,
+    ]);
+    assert!(status.status.success(), "{status:?}");
+    let bytes = fs::read(&out).unwrap();
+    check_golden("archive.zip", &bytes);
+
+    // The same entries through the library, in the order `zip_files` receives them
+    // from the browser.
+    let archive_options: ArchiveOptions = serde_json::from_str(ARCHIVE_OPTIONS).unwrap();
+    let options: EntryOptions = serde_json::from_str(ENTRY_OPTIONS).unwrap();
+    let mut writer = ArchiveWriter::with_options(&archive_options).unwrap();
+    for name in ["input/", "input/data/", "input/docs/"] {
+        writer.add_directory(name, &options).unwrap();
+    }
+    for name in [
+        "input/data/numbers.csv",
+        "input/docs/readme.md",
+        "input/hello.txt",
+    ] {
+        let data = fs::read(golden(name)).unwrap();
+        writer.add_file(name, &data, &options).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), bytes);
+}
+
+#[test]
+fn list_test_and_verify_match_golden() {
+    for (command, golden_name) in [
+        ("list", "list.json"),
+        ("test", "test.json"),
+        ("verify", "verify.json"),
+    ] {
+        let output = compressor(&[command, "archive.zip"]);
+        assert!(output.status.success(), "{command}: {output:?}");
+        check_golden(golden_name, &output.stdout);
+    }
+
+    // A flipped byte in an entry's data is reported with status 1.
+    let mut bytes = fs::read(golden("archive.zip")).unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(&bytes)).unwrap();
+    let data_start = archive.by_name("input/hello.txt").unwrap().data_start() as usize;
+    bytes[data_start] ^= 0xFF;
+    let damaged = scratch("damaged").join("archive.zip");
+    fs::write(&damaged, &bytes).unwrap();
+    let output = compressor(&["test", damaged.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+
+    let output = compressor(&["list", "missing.zip"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("compressor: cannot read"));
+}
+
+#[test]
+fn extract_round_trips_the_input() {
+    let dir = scratch("extract");
+    let output = compressor(&["extract", "archive.zip", "-C", dir.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    for name in [
+        "input/data/numbers.csv",
+        "input/docs/readme.md",
+        "input/hello.txt",
+    ] {
+        assert_eq!(
+            fs::read(dir.join(name)).unwrap(),
+            fs::read(golden(name)).unwrap()
+        );
+    }
+    assert!(dir.join("SHA256SUMS").is_file());
+
+    // Extracting again fails rather than replacing files, unless asked to.
+    let args = ["extract", "archive.zip", "input/hello.txt", "-C"];
+    let output = compressor(&[&args[..], &[dir.to_str().unwrap()]].concat());
+    assert_eq!(output.status.code(), Some(2));
+    let output = compressor(&[&args[..], &[dir.to_str().unwrap(), "--overwrite"]].concat());
+    assert!(output.status.success(), "{output:?}");
+}
+
+#[cfg(unix)]
+#[test]
+fn overwrite_replaces_symbolic_links() {
+    let dir = scratch("overwrite-link");
+    let outside = dir.join("outside.txt");
+    fs::write(&outside, "keep me").unwrap();
+    fs::create_dir_all(dir.join("out/input")).unwrap();
+    let link = dir.join("out/input/hello.txt");
+    std::os::unix::fs::symlink(&outside, &link).unwrap();
+
+    let out = dir.join("out");
+    let args = [
+        "extract",
+        "archive.zip",
+        "input/hello.txt",
+        "--overwrite",
+        "-C",
+    ];
+    let output = compressor(&[&args[..], &[out.to_str().unwrap()]].concat());
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(fs::read_to_string(&outside).unwrap(), "keep me");
+    assert!(fs::symlink_metadata(&link).unwrap().file_type().is_file());
+    assert_eq!(
+        fs::read(&link).unwrap(),
+        fs::read(golden("input/hello.txt")).unwrap()
+    );
+}
@@ -0,0 +1,64 @@
+// Checks the wasm build against the golden files that `cargo test --features cli`
+// checks the native binary against, so both give the same archives and reports.
+//
+//   wasm-pack build --target nodejs --out-dir pkg-node
+//   node tests/golden/wasm.mjs
+
+import assert from "node:assert/strict";
+import { readdirSync, readFileSync } from "node:fs";
+import { createRequire } from "node:module";
+import { dirname, join } from "node:path";
+import { fileURLToPath } from "node:url";
+
+const here = dirname(fileURLToPath(import.meta.url));
+const require = createRequire(import.meta.url);
+const { zip_files, list_archive, verify_zip, verify_manifest } = require(
+  join(here, "../../pkg-node/compressor.js"),
+);
+
+// Keep in sync with ARCHIVE_OPTIONS and ENTRY_OPTIONS in tests/cli.rs.
+const archiveOptions = {
+  deterministic: true,
+  manifest: { algorithms: ["sha256", "blake3"] },
+};
+const entryOptions = { method: "deflated", level: 9 };
+
+// Inputs in the order `compressor create` adds them: each directory, then its sorted contents.
+function collect(path, name, names, contents) {
+  const entries = readdirSync(path, { withFileTypes: true }).sort((a, b) =>
+    a.name < b.name ? -1 : a.name > b.name ? 1 : 0,
+  );
+  names.push(`${name}/`);
+  contents.push(new Uint8Array());
+  for (const entry of entries) {
+    const child = join(path, entry.name);
+    if (entry.isDirectory()) {
+      collect(child, `${name}/${entry.name}`, names, contents);
+    } else {
+      names.push(`${name}/${entry.name}`);
+      contents.push(new Uint8Array(readFileSync(child)));
+    }
+  }
+}
+
+// serde_json writes `null` and `1.0` where JS has `undefined` and `1`, so compare the
+// parsed values with empty fields dropped.
+function normalize(value) {
+  return JSON.parse(JSON.stringify(value), (_, field) => field ?? undefined);
+}
+
+function golden(name) {
+  return normalize(JSON.parse(readFileSync(join(here, name), "utf8")));
+}
+
+const names = [];
+const contents = [];
+collect(join(here, "input"), "input", names, contents);
+const archive = zip_files(names, contents, entryOptions, archiveOptions);
+const expected = new Uint8Array(readFileSync(join(here, "archive.zip")));
+assert.deepEqual(archive, expected, "zip_files differs from archive.zip");
+
+assert.deepEqual(normalize(list_archive(expected)), golden("list.json"));
+assert.deepEqual(normalize(verify_zip(expected)), golden("test.json"));
+assert.deepEqual(normalize(verify_manifest(expected)), golden("verify.json"));
+console.log("wasm build matches the golden files");